@@ -0,0 +1,196 @@
+use std::io::{self, Write};
+
+use super::arcdagbase::ArcDagBase;
+use super::batch::EdgeBatch;
+use super::error::DagError;
+use super::nodeptr::Shared;
+use super::ondag::OnDag;
+use super::rcdagbase::PendingBatch;
+use super::snapshot::DagSnapshot;
+
+pub use super::arcdagbase::{HalfEdge, FullEdge, IterTopo, IterTopoRev, NodeDataRef, NodeHandle, WeakNodeHandle};
+
+
+/// Thread-safe counterpart to `RcDag`: nodes live for as long as some handle (or edge) refers to
+/// them, and the graph & its handles can be shared between threads.
+/// Besides the `OnDag` interface, the graph can be modified through a shared reference (e.g. from
+/// several threads at once, via an `Arc<ArcDag>`). Modifications are serialized, so each edge is
+/// checked against the graph as it is when the edge is inserted.
+///
+/// Note: these graphs don't necessarily have explicit roots. It's the user's job to keep handles
+/// to root nodes in order to iterate them, etc.
+pub struct ArcDag<N, E> {
+    dag: ArcDagBase<N, E>,
+}
+
+impl <N, E : Eq + Clone> OnDag<N, E> for ArcDag<N, E> {
+    type NodeHandle = NodeHandle<N, E>;
+    type Edge = FullEdge<N, E>;
+    fn add_node(&mut self, node_data: N) -> Self::NodeHandle {
+        ArcDag::add_node(self, node_data)
+    }
+    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>> {
+        ArcDag::add_edge(self, from, to, data)
+    }
+    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>> {
+        ArcDag::rm_edge(self, from, to, data)
+    }
+    fn iter_topo(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        self.dag.iter_topo(from)
+    }
+    fn iter_topo_rev(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        self.dag.iter_topo_rev(from)
+    }
+    fn children(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        let from = node.clone();
+        self.dag.children(node).map(move |half| FullEdge::new(from.clone(), half))
+    }
+    fn parents(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        self.dag.parents(node)
+    }
+    fn contains(&self, node: &Self::NodeHandle) -> bool {
+        // A live handle keeps its node alive, so ownership is all that needs checking.
+        self.dag.owns(node)
+    }
+    fn node_count(&self) -> usize {
+        self.dag.node_count()
+    }
+    fn edge_count(&self) -> usize {
+        self.dag.edge_count()
+    }
+}
+
+impl <N, E : Eq> Default for ArcDag<N, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl <N, E : Eq> ArcDag<N, E> {
+    pub fn new() -> Self {
+        ArcDag {
+            dag: ArcDagBase::new()
+        }
+    }
+    pub fn add_node(&self, node_data: N) -> NodeHandle<N, E> {
+        self.dag.add_node(node_data)
+    }
+    /// Lazily iterate `from` and every node reachable from it, parents before children.
+    /// Unlike `OnDag::iter_topo`, the iterator's type can be named (e.g. to store it in a struct).
+    /// It holds handles to the nodes it has yet to yield, so it doesn't borrow the graph; edges
+    /// modified during iteration (e.g. by other threads) may or may not be reflected.
+    pub fn iter_topo(&self, from: &NodeHandle<N, E>) -> IterTopo<N, E> {
+        self.dag.iter_topo(from)
+    }
+    /// Lazily iterate `from` and every node reachable from it, children before parents (as for
+    /// `iter_topo`).
+    pub fn iter_topo_rev(&self, from: &NodeHandle<N, E>) -> IterTopoRev<N, E> {
+        self.dag.iter_topo_rev(from)
+    }
+}
+
+impl <N, E : Eq + Clone> ArcDag<N, E> {
+    /// Add an edge, unless it would close a cycle (in which case the error holds that cycle).
+    /// This is atomic with respect to other modifications, so racing insertions can't together
+    /// create a cycle.
+    pub fn add_edge(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, data: E) -> Result<(), DagError<FullEdge<N, E>>> {
+        self.dag.check_owners(from, to)?;
+        let _guard = self.dag.lock();
+        let edge = FullEdge::new(from.clone(), HalfEdge::new(to.clone(), data));
+        match self.cycle_through(&edge, None) {
+            Some(cycle) => Err(DagError::Cycle(cycle)),
+            None => {
+                self.dag.add_edge_unchecked(from, to, edge.weight().clone());
+                Ok(())
+            }
+        }
+    }
+    pub fn rm_edge(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, data: E) -> Result<(), DagError<FullEdge<N, E>>> {
+        self.dag.check_owners(from, to)?;
+        let _guard = self.dag.lock();
+        if self.dag.rm_edge(from, to, data) {
+            Ok(())
+        } else {
+            Err(DagError::NoSuchEdge)
+        }
+    }
+    /// Return true if `to` is reachable from (or is) `from`.
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn is_reachable(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> bool {
+        self.find_path(from, to).is_some()
+    }
+    /// Find a path that leads from `from` to `to`.
+    /// Returns the edges along that path (empty if `from == to`), or None if `to` isn't reachable
+    /// from `from`.
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn find_path(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> Option<Vec<FullEdge<N, E>>> {
+        // Hold off modifications, so that the path is consistent.
+        let _guard = self.dag.lock_for_read();
+        self.dag.find_path(to, from)
+    }
+    /// Return true if `add_edge(from, to, ..)` would be rejected for closing a cycle (unless the
+    /// graph is modified in the meantime).
+    pub fn would_create_cycle(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> bool {
+        self.is_reachable(to, from)
+    }
+    /// Remove every edge entering `node`, so that its parents no longer lead to it.
+    /// Returns the removed edges (which keep the parents alive until dropped).
+    /// Like `parents`, this panics if `node` belongs to another graph.
+    pub fn detach_from_parents(&self, node: &NodeHandle<N, E>) -> Vec<FullEdge<N, E>> {
+        let _guard = self.dag.lock();
+        self.dag.detach_from_parents(node)
+    }
+    /// Apply every modification in `batch` as a single step (see `EdgeBatch`).
+    /// If the result would contain a cycle, the graph is left untouched and the error holds one
+    /// such cycle. As with `add_edge`, the batch is checked before it's applied, so other threads
+    /// never see a cycle; they may see the batch partially applied, but only ever without some of
+    /// its insertions.
+    pub fn apply_batch(&self, batch: EdgeBatch<NodeHandle<N, E>, E>) -> Result<(), DagError<FullEdge<N, E>>> {
+        let _guard = self.dag.lock();
+        let pending = self.dag.plan_batch(batch)?;
+        // Any new cycle must pass through one of the new edges.
+        let cycle = pending.added.iter().filter_map(|edge| self.cycle_through(edge, Some(&pending))).next();
+        match cycle {
+            Some(cycle) => Err(DagError::Cycle(cycle)),
+            None => {
+                self.dag.apply_pending(pending);
+                Ok(())
+            }
+        }
+    }
+    /// Replace the edge `from` -> `to` weighted `old` with one weighted `new`.
+    /// The endpoints don't change, so this can't create a cycle.
+    pub fn set_edge_weight(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, old: E, new: E) -> Result<(), DagError<FullEdge<N, E>>> {
+        let mut batch = EdgeBatch::new();
+        batch.rm_edge(from.clone(), to.clone(), old).add_edge(from.clone(), to.clone(), new);
+        self.apply_batch(batch)
+    }
+    /// Copy every node & edge reachable from `roots` (e.g. in order to serialize them).
+    /// The copy can be loaded into a new graph via `DagSnapshot::restore`. Modifications are held
+    /// off meanwhile, so that the copy is consistent.
+    pub fn snapshot(&self, roots: &[NodeHandle<N, E>]) -> Result<DagSnapshot<N, E>, DagError<FullEdge<N, E>>>
+        where N: Clone
+    {
+        let _guard = self.dag.lock_for_read();
+        self.dag.snapshot(roots)
+    }
+    /// Write every node & edge reachable from `roots` in Graphviz DOT format (see
+    /// `RcDag::write_dot`). Modifications are held off meanwhile, so that the output is
+    /// consistent.
+    pub fn write_dot<Out, FN, FE>(&self, out: &mut Out, roots: &[NodeHandle<N, E>], node_label: FN, edge_label: FE, highlight: &[FullEdge<N, E>]) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> String, FE: Fn(&FullEdge<N, E>) -> String
+    {
+        let _guard = self.dag.lock_for_read();
+        self.dag.write_dot(out, roots, node_label, edge_label, highlight)
+    }
+    /// If `edge` (whether or not it's actually in the graph) would lie on a cycle, return that
+    /// cycle, starting with `edge`. The graph is treated as if `pending` had been applied.
+    fn cycle_through(&self, edge: &FullEdge<N, E>, pending: Option<&PendingBatch<N, E, Shared>>) -> Option<Vec<FullEdge<N, E>>> {
+        // a path from `to` to `from` means that the edge `from` -> `to` closes a cycle.
+        self.dag.find_path_within(edge.from(), edge.to(), u64::MAX, pending).map(|path| {
+            let mut cycle = vec![edge.clone()];
+            cycle.extend(path);
+            cycle
+        })
+    }
+}
@@ -0,0 +1,100 @@
+//! Base functions for implementing thread-safe DAG types on top of an Arc Node format.
+//! This is `rcdagbase` with nodes shared via `Arc<RwLock<_>>` (see `nodeptr::Shared`), so that
+//! graphs & their handles can be sent between threads.
+//! Modifications to the structure are serialized by a graph-wide lock (see `ArcDagBase::lock`),
+//! so that checking an edge & inserting it is a single step: two racing insertions can't together
+//! create a cycle. Node locks are only ever held one at a time, so readers can't deadlock.
+//! Callbacks run during a modification (e.g. `CostQueriable::is_zero_cost`) may read the graph
+//! through the lock, which the thread running them already holds, but mustn't modify it.
+
+use std::ops::Deref;
+use std::sync::{Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+
+use super::nodeptr::Shared;
+use super::rcdagbase::{self, RcDagBase};
+
+pub type NodeHandle<N, E> = rcdagbase::NodeHandle<N, E, Shared>;
+pub type WeakNodeHandle<N, E> = rcdagbase::WeakNodeHandle<N, E, Shared>;
+pub type HalfEdge<N, E> = rcdagbase::HalfEdge<N, E, Shared>;
+pub type FullEdge<N, E> = rcdagbase::FullEdge<N, E, Shared>;
+pub type IterTopo<N, E> = rcdagbase::IterTopo<N, E, Shared>;
+pub type IterTopoRev<N, E> = rcdagbase::IterTopoRev<N, E, Shared>;
+
+pub use super::rcdagbase::NodeDataRef;
+
+/// Note: these graphs don't necessarily have explicit roots. It's the user's job to keep handles
+/// to root nodes in order to iterate them, etc.
+/// Everything but the lock is provided by the underlying `RcDagBase` (via `Deref`), whose
+/// modifications must only be made while holding the lock.
+pub struct ArcDagBase<N, E> {
+    dag: RcDagBase<N, E, Shared>,
+    /// Held for the duration of each modification.
+    write_lock: Mutex<()>,
+    /// The thread holding `write_lock`, if any.
+    holder: Mutex<Option<ThreadId>>,
+}
+
+/// Holds off modifications to an `ArcDagBase` until dropped.
+pub(super) struct LockGuard<'a> {
+    _guard: MutexGuard<'a, ()>,
+    holder: &'a Mutex<Option<ThreadId>>,
+}
+
+impl<N, E> ArcDagBase<N, E> {
+    pub(super) fn new() -> Self {
+        ArcDagBase {
+            dag: RcDagBase::new(),
+            write_lock: Mutex::new(()),
+            holder: Mutex::new(None),
+        }
+    }
+    /// Block other modifications until the guard is dropped.
+    /// Panics if this thread is already modifying the graph (i.e. from a callback), rather than
+    /// deadlocking.
+    pub(super) fn lock(&self) -> LockGuard<'_> {
+        assert!(!self.is_locked_here(), "graph modified from a callback run while modifying it");
+        self.acquire()
+    }
+    /// Block modifications until the guard is dropped, so that a read sees a consistent graph.
+    /// If this thread is already modifying the graph (i.e. the read is made from a callback), the
+    /// graph can't change until the callback returns, so there's nothing more to lock.
+    pub(super) fn lock_for_read(&self) -> Option<LockGuard<'_>> {
+        if self.is_locked_here() {
+            None
+        } else {
+            Some(self.acquire())
+        }
+    }
+    fn acquire(&self) -> LockGuard<'_> {
+        // The lock guards no data, so a panic while it was held can't have left anything
+        // inconsistent.
+        let guard = self.write_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *lock_holder(&self.holder) = Some(thread::current().id());
+        LockGuard {
+            _guard: guard,
+            holder: &self.holder,
+        }
+    }
+    fn is_locked_here(&self) -> bool {
+        *lock_holder(&self.holder) == Some(thread::current().id())
+    }
+}
+
+impl<'a> Drop for LockGuard<'a> {
+    fn drop(&mut self) {
+        // This runs before the lock itself is released.
+        *lock_holder(self.holder) = None;
+    }
+}
+
+impl<N, E> Deref for ArcDagBase<N, E> {
+    type Target = RcDagBase<N, E, Shared>;
+    fn deref(&self) -> &RcDagBase<N, E, Shared> {
+        &self.dag
+    }
+}
+
+fn lock_holder(holder: &Mutex<Option<ThreadId>>) -> MutexGuard<'_, Option<ThreadId>> {
+    holder.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
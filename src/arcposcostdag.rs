@@ -0,0 +1,201 @@
+use std::io::{self, Write};
+
+use super::arcdagbase::ArcDagBase;
+use super::batch::EdgeBatch;
+use super::error::DagError;
+use super::nodeptr::Shared;
+use super::ondag::OnDag;
+use super::rcdagbase::PendingBatch;
+use super::snapshot::DagSnapshot;
+
+pub use super::arcdagbase::{HalfEdge, FullEdge, IterTopo, IterTopoRev, NodeDataRef, NodeHandle, WeakNodeHandle};
+/// Shared with `PosCostDag`: implement `CostQueriable<N, E, ArcPosCostDag<N, E>>`.
+pub use super::poscostdag::{CostGraph, CostQueriable};
+
+
+/// Thread-safe counterpart to `PosCostDag`: cycles are allowed, provided the cumulative edge
+/// weight of any cycle is > 0.
+/// As with `ArcDag`, the graph can be modified through a shared reference, and each edge is
+/// checked against the graph as it is when the edge is inserted. Edges that would close a 0-cycle
+/// are never visible to other threads.
+/// `CostQueriable::is_zero_cost` is called while other modifications are held off: it may read
+/// the graph it's given (e.g. via `find_path`), but modifying the graph from there panics.
+///
+/// Note: these graphs don't necessarily have explicit roots. It's the user's job to keep handles
+/// to root nodes in order to iterate them, etc.
+pub struct ArcPosCostDag<N, E> {
+    dag: ArcDagBase<N, E>,
+}
+
+impl <N, E : Eq + CostQueriable<N, E, ArcPosCostDag<N, E>> + Clone> OnDag<N, E> for ArcPosCostDag<N, E> {
+    type NodeHandle = NodeHandle<N, E>;
+    type Edge = FullEdge<N, E>;
+    fn add_node(&mut self, node_data: N) -> Self::NodeHandle {
+        ArcPosCostDag::add_node(self, node_data)
+    }
+    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>> {
+        ArcPosCostDag::add_edge(self, from, to, data)
+    }
+    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>> {
+        ArcPosCostDag::rm_edge(self, from, to, data)
+    }
+    fn iter_topo(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        self.dag.iter_topo(from)
+    }
+    fn iter_topo_rev(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        self.dag.iter_topo_rev(from)
+    }
+    fn children(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        let from = node.clone();
+        self.dag.children(node).map(move |half| FullEdge::new(from.clone(), half))
+    }
+    fn parents(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        self.dag.parents(node)
+    }
+    fn contains(&self, node: &Self::NodeHandle) -> bool {
+        // A live handle keeps its node alive, so ownership is all that needs checking.
+        self.dag.owns(node)
+    }
+    fn node_count(&self) -> usize {
+        self.dag.node_count()
+    }
+    fn edge_count(&self) -> usize {
+        self.dag.edge_count()
+    }
+}
+
+impl <N, E> CostGraph<N, E> for ArcPosCostDag<N, E> {
+    type HalfEdge = HalfEdge<N, E>;
+}
+
+impl <N, E : Eq> Default for ArcPosCostDag<N, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl <N, E : Eq> ArcPosCostDag<N, E> {
+    pub fn new() -> Self {
+        ArcPosCostDag {
+            dag: ArcDagBase::new()
+        }
+    }
+    pub fn add_node(&self, node_data: N) -> NodeHandle<N, E> {
+        self.dag.add_node(node_data)
+    }
+    /// Lazily iterate `from` and every node reachable from it, parents before children (see
+    /// `ArcDag::iter_topo`).
+    pub fn iter_topo(&self, from: &NodeHandle<N, E>) -> IterTopo<N, E> {
+        self.dag.iter_topo(from)
+    }
+    /// Lazily iterate `from` and every node reachable from it, children before parents (as for
+    /// `iter_topo`).
+    pub fn iter_topo_rev(&self, from: &NodeHandle<N, E>) -> IterTopoRev<N, E> {
+        self.dag.iter_topo_rev(from)
+    }
+}
+
+impl <N, E : Eq + CostQueriable<N, E, ArcPosCostDag<N, E>> + Clone> ArcPosCostDag<N, E> {
+    /// Add an edge, unless it would close a 0-cycle (in which case the error holds that cycle).
+    /// This is atomic with respect to other modifications, so racing insertions can't together
+    /// create a 0-cycle.
+    pub fn add_edge(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, data: E) -> Result<(), DagError<FullEdge<N, E>>> {
+        self.dag.check_owners(from, to)?;
+        let _guard = self.dag.lock();
+        let new_edge = FullEdge::new(from.clone(), HalfEdge::new(to.clone(), data));
+        // As in `PosCostDag::add_edge`, a new 0-cycle must pass through the new edge. Unlike
+        // there, the edge is checked before it's inserted, so readers never see it.
+        match self.zero_cost_cycle_through(&new_edge, None) {
+            Some(cycle) => Err(DagError::ZeroCostCycle(cycle)),
+            None => {
+                self.dag.add_edge_unchecked(from, to, new_edge.weight().clone());
+                Ok(())
+            }
+        }
+    }
+    pub fn rm_edge(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, data: E) -> Result<(), DagError<FullEdge<N, E>>> {
+        self.dag.check_owners(from, to)?;
+        let _guard = self.dag.lock();
+        if self.dag.rm_edge(from, to, data) {
+            Ok(())
+        } else {
+            Err(DagError::NoSuchEdge)
+        }
+    }
+    /// Return true if `to` is reachable from (or is) `from`.
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn is_reachable(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> bool {
+        self.find_path(from, to).is_some()
+    }
+    /// Find a path that leads from `from` to `to`.
+    /// Returns the edges along that path (empty if `from == to`), or None if `to` isn't reachable
+    /// from `from`.
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn find_path(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> Option<Vec<FullEdge<N, E>>> {
+        // Hold off modifications, so that the path is consistent.
+        let _guard = self.dag.lock_for_read();
+        self.dag.find_path(to, from)
+    }
+    /// Return true if `add_edge(from, to, data)` would be rejected for closing a 0-cycle (unless
+    /// the graph is modified in the meantime).
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn would_create_cycle(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, data: E) -> bool {
+        from.check_owner(&self.dag);
+        to.check_owner(&self.dag);
+        let _guard = self.dag.lock_for_read();
+        self.zero_cost_cycle_through(&FullEdge::new(from.clone(), HalfEdge::new(to.clone(), data)), None).is_some()
+    }
+    /// Remove every edge entering `node`, so that its parents no longer lead to it.
+    /// Returns the removed edges (which keep the parents alive until dropped).
+    /// Like `parents`, this panics if `node` belongs to another graph.
+    pub fn detach_from_parents(&self, node: &NodeHandle<N, E>) -> Vec<FullEdge<N, E>> {
+        let _guard = self.dag.lock();
+        self.dag.detach_from_parents(node)
+    }
+    /// Apply every modification in `batch` as a single step (see `EdgeBatch`).
+    /// If the result would contain a 0-cycle, the graph is left untouched and the error holds one
+    /// such cycle. As with `ArcDag::apply_batch`, the batch is checked before it's applied, so
+    /// other threads never see a 0-cycle.
+    pub fn apply_batch(&self, batch: EdgeBatch<NodeHandle<N, E>, E>) -> Result<(), DagError<FullEdge<N, E>>> {
+        let _guard = self.dag.lock();
+        let pending = self.dag.plan_batch(batch)?;
+        // As in add_edge: any new 0-cycle must pass through one of the new edges.
+        let cycle = pending.added.iter().filter_map(|edge| self.zero_cost_cycle_through(edge, Some(&pending))).next();
+        match cycle {
+            Some(cycle) => Err(DagError::ZeroCostCycle(cycle)),
+            None => {
+                self.dag.apply_pending(pending);
+                Ok(())
+            }
+        }
+    }
+    /// Replace the edge `from` -> `to` weighted `old` with one weighted `new`.
+    /// The new weight may make the edge 0-cost, so it's checked as in `add_edge`; if it would
+    /// close a 0-cycle, the old edge is left in place and the error holds that cycle.
+    pub fn set_edge_weight(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, old: E, new: E) -> Result<(), DagError<FullEdge<N, E>>> {
+        let mut batch = EdgeBatch::new();
+        batch.rm_edge(from.clone(), to.clone(), old).add_edge(from.clone(), to.clone(), new);
+        self.apply_batch(batch)
+    }
+    /// Copy every node & edge reachable from `roots` (see `ArcDag::snapshot`).
+    pub fn snapshot(&self, roots: &[NodeHandle<N, E>]) -> Result<DagSnapshot<N, E>, DagError<FullEdge<N, E>>>
+        where N: Clone
+    {
+        let _guard = self.dag.lock_for_read();
+        self.dag.snapshot(roots)
+    }
+    /// Write every node & edge reachable from `roots` in Graphviz DOT format (see
+    /// `PosCostDag::write_dot`). Modifications are held off meanwhile, so that the output is
+    /// consistent.
+    pub fn write_dot<Out, FN, FE>(&self, out: &mut Out, roots: &[NodeHandle<N, E>], node_label: FN, edge_label: FE, highlight: &[FullEdge<N, E>]) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> String, FE: Fn(&FullEdge<N, E>) -> String
+    {
+        let _guard = self.dag.lock_for_read();
+        self.dag.write_dot(out, roots, node_label, edge_label, highlight)
+    }
+    /// If `edge` (whether or not it's actually in the graph) would lie on a 0-cycle, return that
+    /// cycle, starting with `edge`. The graph is treated as if `pending` had been applied.
+    fn zero_cost_cycle_through(&self, edge: &FullEdge<N, E>, pending: Option<&PendingBatch<N, E, Shared>>) -> Option<Vec<FullEdge<N, E>>> {
+        self.dag.zero_cost_cycle_through(edge, pending, |edge, next| E::is_zero_cost(edge, next, self))
+    }
+}
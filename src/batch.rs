@@ -0,0 +1,43 @@
+//! Batches of edge modifications that are applied to a DAG all at once.
+
+/// A set of edge removals and insertions.
+/// When applied, every removal happens first, and the graph is only checked for cycles once all
+/// of the insertions have been made. So a batch may pass through cyclic intermediate states (e.g.
+/// when reversing an edge), provided the final state is acyclic.
+/// Either the whole batch is applied, or (on error) the graph is left untouched.
+///
+/// H=NodeHandle (or whatever identifies an edge endpoint in the target DAG)
+/// E=Edge weight
+pub struct EdgeBatch<H, E> {
+    pub(super) removals: Vec<(H, H, E)>,
+    pub(super) additions: Vec<(H, H, E)>,
+}
+
+impl<H, E> EdgeBatch<H, E> {
+    pub fn new() -> Self {
+        EdgeBatch {
+            removals: Vec::new(),
+            additions: Vec::new(),
+        }
+    }
+    /// Queue the insertion of the edge `from` -> `to`.
+    pub fn add_edge(&mut self, from: H, to: H, data: E) -> &mut Self {
+        self.additions.push((from, to, data));
+        self
+    }
+    /// Queue the removal of the edge `from` -> `to`.
+    /// It's an error for the edge to not exist at the time the batch is applied.
+    pub fn rm_edge(&mut self, from: H, to: H, data: E) -> &mut Self {
+        self.removals.push((from, to, data));
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.removals.is_empty() && self.additions.is_empty()
+    }
+}
+
+impl<H, E> Default for EdgeBatch<H, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
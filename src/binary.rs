@@ -0,0 +1,176 @@
+//! Compact binary format for `IODag` (see `IODag::write_binary` & `IODag::read_binary`), for
+//! graphs too large to save as text.
+//! Every integer is an unsigned LEB128 varint, and every node data/weight is written by the client,
+//! prefixed with its length. The layout is:
+//!
+//! - `MAGIC`, `VERSION` & the number of slots (see `iodag::NodeHandle`)
+//! - the generation of each slot, in order
+//! - the number of nodes
+//! - each node, in order of slot: the gap since the previous slot (+ 1), then its data
+//! - each node's position in the topological order, in order of slot
+//! - the number of nodes (including null) with outbound edges
+//! - each of those, in order of endpoint: the gap since the previous endpoint (+ 1) and the number
+//!   of edges, then each edge in order of target: the delta from the previous target, then its
+//!   weight
+//!
+//! Endpoints are numbered 0 for null and slot + 1 for nodes. As the slots of deleted nodes are
+//! reused, the gaps are small.
+//! The topological order is stored so that it can be restored as is, instead of being rebuilt
+//! edge by edge (which is quadratic when the slots are ordered against the edges).
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Identifies the format; the first bytes of every file.
+pub const MAGIC: &[u8; 4] = b"ODAG";
+/// The version of the format that's written. Other versions are rejected.
+pub const VERSION: u64 = 1;
+
+/// Error encountered while reading a binary graph.
+/// `P` is the type of edge that describes cycles (see `DagError`).
+#[derive(Debug)]
+pub enum BinaryError<P> {
+    /// Reading from the input failed.
+    Io(io::Error),
+    /// The input ended partway through the graph.
+    Truncated,
+    /// The input doesn't begin with `MAGIC`.
+    BadMagic,
+    /// The input was written in an unsupported version of the format.
+    UnsupportedVersion(u64),
+    /// The input is malformed, or was rejected by a decoder or by the graph.
+    Invalid(String),
+    /// An edge would have closed the given cycle (ordered as in `DagError::Cycle`).
+    Cycle(Vec<P>),
+}
+
+/// Writes the primitives of the format.
+pub(super) struct Encoder<'a, Out: 'a + Write> {
+    out: &'a mut Out,
+}
+
+/// Reads the primitives of the format, one at a time (so the input is best buffered).
+pub(super) struct Decoder<R: Read> {
+    input: R,
+    /// Holds the last data read.
+    buffer: Vec<u8>,
+}
+
+impl<'a, Out: Write> Encoder<'a, Out> {
+    /// Begin writing, with the header.
+    pub(super) fn new(out: &'a mut Out) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        let mut encoder = Encoder { out };
+        encoder.varint(VERSION)?;
+        Ok(encoder)
+    }
+    pub(super) fn varint(&mut self, mut value: u64) -> io::Result<()> {
+        let mut bytes = [0u8; 10];
+        let mut len = 0;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes[len] = byte;
+                len += 1;
+                break;
+            }
+            bytes[len] = byte | 0x80;
+            len += 1;
+        }
+        self.out.write_all(&bytes[..len])
+    }
+    /// Write data, prefixed with its length.
+    pub(super) fn bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        self.varint(data.len() as u64)?;
+        self.out.write_all(data)
+    }
+}
+
+impl<R: Read> Decoder<R> {
+    /// Begin reading, checking the header.
+    pub(super) fn new<P>(mut input: R) -> Result<Self, BinaryError<P>> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(BinaryError::BadMagic);
+        }
+        let mut decoder = Decoder {
+            input,
+            buffer: vec![],
+        };
+        match decoder.varint()? {
+            VERSION => Ok(decoder),
+            version => Err(BinaryError::UnsupportedVersion(version)),
+        }
+    }
+    pub(super) fn varint<P>(&mut self) -> Result<u64, BinaryError<P>> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0u8; 1];
+            self.input.read_exact(&mut byte)?;
+            let bits = u64::from(byte[0] & 0x7f);
+            if (bits << shift) >> shift != bits {
+                break;
+            }
+            value |= bits << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(BinaryError::Invalid("varint overflows 64 bits".to_string()))
+    }
+    /// Read length-prefixed data.
+    pub(super) fn bytes<P>(&mut self) -> Result<&[u8], BinaryError<P>> {
+        let len = self.varint()?;
+        self.buffer.clear();
+        // Read via `take`, so that a corrupt length can't cause a huge allocation.
+        (&mut self.input).take(len).read_to_end(&mut self.buffer)?;
+        if (self.buffer.len() as u64) < len {
+            return Err(BinaryError::Truncated);
+        }
+        Ok(&self.buffer)
+    }
+}
+
+/// Number an endpoint (None for null) by its index.
+pub(super) fn endpoint_code(index: Option<u64>) -> u64 {
+    index.map_or(0, |index| index + 1)
+}
+
+/// The index of the endpoint with the given number (None for null).
+pub(super) fn endpoint_index(code: u64) -> Option<u64> {
+    code.checked_sub(1)
+}
+
+/// Add `gap` to `base`, which must give a value less than `limit`.
+pub(super) fn offset<P>(base: u64, gap: u64, limit: u64) -> Result<u64, BinaryError<P>> {
+    base.checked_add(gap).filter(|&value| value < limit).ok_or_else(|| {
+        BinaryError::Invalid(format!("{} + {} is out of range", base, gap))
+    })
+}
+
+impl<P> From<io::Error> for BinaryError<P> {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => BinaryError::Truncated,
+            _ => BinaryError::Io(err),
+        }
+    }
+}
+
+impl<P> fmt::Display for BinaryError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BinaryError::Io(ref err) => write!(f, "failed to read graph: {}", err),
+            BinaryError::Truncated => write!(f, "graph is truncated"),
+            BinaryError::BadMagic => write!(f, "not a graph (bad magic number)"),
+            BinaryError::UnsupportedVersion(version) => write!(f, "unsupported format version {}", version),
+            BinaryError::Invalid(ref message) => write!(f, "invalid graph: {}", message),
+            BinaryError::Cycle(ref cycle) => write!(f, "edge would create a cycle of length {}", cycle.len()),
+        }
+    }
+}
+
+impl<P: fmt::Debug> Error for BinaryError<P> {}
@@ -0,0 +1,441 @@
+//! ConcurrentIODag is an `IODag` that many threads can modify at once, through a shared reference
+//! (e.g. an `Arc<ConcurrentIODag>`).
+//!
+//! Node handles are allocated from an atomic counter, without taking any lock. The nodes & their
+//! edge sets are spread across a fixed number of shards, each behind its own lock, and every lock
+//! is only held briefly: readers copy out what they need, so they never hold up writers for long.
+//!
+//! Inserting an edge searches for a cycle optimistically, without holding any lock, noting the
+//! version of each shard that it reads. The edge is then inserted only if none of those shards
+//! changed in the meantime (otherwise, the search is retried), so insertions are only serialized
+//! when their searches overlap. After `OPTIMISTIC_ATTEMPTS` failed attempts, the search is done
+//! with every shard locked instead, which guarantees progress.
+//!
+//! Unlike `IODag`, no topological order is maintained, as every insertion would contend on it.
+//! Once the graph is built, it can be converted with `into_iodag`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::error::DagError;
+use super::iodag::{EdgeSet, EditOp, IODag};
+use super::ondag::OnDag;
+
+pub use super::iodag::{Edge, NodeHandle};
+
+/// Number of shards the nodes are spread across.
+const SHARD_COUNT: usize = 32;
+/// Number of times an insertion is attempted without locking every shard.
+const OPTIMISTIC_ATTEMPTS: usize = 4;
+
+/// N=Node Data
+/// W=Weight
+pub struct ConcurrentIODag<N, W>
+    where W: Hash + Eq + PartialEq {
+    /// To create unique NodeHandles, we just assign them unique u64's from this counter.
+    node_counter: AtomicU64,
+    /// Node `n` lives in shard `n % SHARD_COUNT`, and null lives in shard 0.
+    shards: Vec<RwLock<Shard<N, W>>>,
+}
+
+struct Shard<N, W>
+    where W: Hash + Eq + PartialEq {
+    node_data: HashMap<NodeHandle, N>,
+    edges: HashMap<Option<NodeHandle>, EdgeSet<W>>,
+    /// Incremented whenever an edge set in this shard changes.
+    version: u64,
+}
+
+/// The write locks held while inserting an edge, by shard index.
+type ShardGuards<'a, N, W> = BTreeMap<usize, RwLockWriteGuard<'a, Shard<N, W>>>;
+
+impl<N, W> Default for ConcurrentIODag<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N, W> ConcurrentIODag<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    pub fn new() -> Self {
+        let shards = (0..SHARD_COUNT).map(|_| RwLock::new(Shard {
+            node_data: HashMap::new(),
+            edges: HashMap::new(),
+            version: 0,
+        })).collect::<Vec<_>>();
+        shards[0].write().unwrap().edges.insert(None, EdgeSet::new());
+        ConcurrentIODag {
+            node_counter: AtomicU64::new(0),
+            shards,
+        }
+    }
+    /// Return a copy of the node's data, or None if the node doesn't exist.
+    pub fn node_data(&self, node: NodeHandle) -> Option<N>
+        where N: Clone
+    {
+        self.read_shard(Some(node)).node_data.get(&node).cloned()
+    }
+    /// Iterate a copy of the edges leaving the node (none, if the node doesn't exist).
+    pub fn iter_outbound_edges(&self, node: Option<NodeHandle>) -> impl Iterator<Item=Edge<W>> {
+        self.read_shard(node).edges.get(&node).map_or(vec![], |edge_set| {
+            edge_set.outbound.iter().cloned().collect()
+        }).into_iter()
+    }
+    /// Iterate a copy of the edges entering the node (none, if the node doesn't exist).
+    pub fn iter_inbound_edges(&self, node: Option<NodeHandle>) -> impl Iterator<Item=Edge<W>> {
+        self.read_shard(node).edges.get(&node).map_or(vec![], |edge_set| {
+            edge_set.inbound.iter().cloned().collect()
+        }).into_iter()
+    }
+    /// Iterate the nodes. Each shard is copied in turn, so this isn't a snapshot of the whole
+    /// graph if it's being modified concurrently.
+    pub fn iter_nodes(&self) -> impl Iterator<Item=NodeHandle> {
+        self.shards.iter().flat_map(|shard| {
+            Self::read(shard).node_data.keys().cloned().collect::<Vec<_>>()
+        }).collect::<Vec<_>>().into_iter()
+    }
+    /// Iterate the edges. As for `iter_nodes`, this isn't a snapshot if the graph is being modified
+    /// concurrently.
+    pub fn iter_edges(&self) -> impl Iterator<Item=Edge<W>> {
+        self.shards.iter().flat_map(|shard| {
+            Self::read(shard).edges.values().flat_map(|edge_set| edge_set.outbound.iter().cloned()).collect::<Vec<_>>()
+        }).collect::<Vec<_>>().into_iter()
+    }
+    pub fn add_node(&self, node_data: N) -> NodeHandle {
+        let handle = NodeHandle::from_index(self.node_counter.fetch_add(1, Ordering::Relaxed));
+        let mut shard = self.write_shard(Some(handle));
+        // Panic if the NodeHandle was somehow already in use.
+        assert!(shard.edges.insert(Some(handle), EdgeSet::new()).is_none());
+        assert!(shard.node_data.insert(handle, node_data).is_none());
+        handle
+    }
+    /// Add the edge, unless it would close a cycle (in which case the error holds that cycle, as
+    /// for `IODag::add_edge`).
+    /// This is atomic with respect to other modifications, so racing insertions can't together
+    /// create a cycle.
+    /// `reachable_pred` must not access the graph, since it may be called while every shard is
+    /// locked.
+    pub fn add_edge<F>(&self, edge: Edge<W>, reachable_pred: &F) -> Result<(), DagError<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        for _ in 0..OPTIMISTIC_ATTEMPTS {
+            // The version of each shard read by the search, when it was first read.
+            let mut versions = HashMap::new();
+            let cycle = {
+                let mut outbound = |node: NodeHandle| {
+                    let shard = self.read_shard(Some(node));
+                    versions.entry(Self::shard_index(Some(node))).or_insert(shard.version);
+                    shard.edges.get(&Some(node)).map_or(vec![], |edge_set| {
+                        edge_set.outbound.iter().cloned().collect()
+                    })
+                };
+                Self::find_cycle(&edge, reachable_pred, &mut outbound)
+            };
+            let mut guards = self.lock_shards(versions.keys().cloned().chain(Self::endpoint_shards(&edge)));
+            // If none of the shards that were searched have changed since, the search still holds.
+            if versions.iter().all(|(index, &version)| guards[index].version == version) {
+                return Self::insert_locked(&mut guards, edge, cycle);
+            }
+        }
+        // Too much contention, so search again with everything locked.
+        let mut guards = self.lock_shards(0..SHARD_COUNT);
+        let cycle = {
+            let mut outbound = |node: NodeHandle| {
+                guards[&Self::shard_index(Some(node))].edges.get(&Some(node)).map_or(vec![], |edge_set| {
+                    edge_set.outbound.iter().cloned().collect()
+                })
+            };
+            Self::find_cycle(&edge, reachable_pred, &mut outbound)
+        };
+        Self::insert_locked(&mut guards, edge, cycle)
+    }
+    /// Return true if adding `edge` would be rejected for closing a cycle (unless the graph is
+    /// modified in the meantime).
+    pub fn would_create_cycle<F>(&self, edge: &Edge<W>, reachable_pred: &F) -> bool
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        self.exists(*edge.from()) && self.exists(*edge.to()) && {
+            let mut outbound = |node: NodeHandle| self.iter_outbound_edges(Some(node)).collect();
+            Self::find_cycle(edge, reachable_pred, &mut outbound).is_some()
+        }
+    }
+    /// Return true if `to` is reachable from (or is) `from` (see `find_path`).
+    pub fn is_reachable<F>(&self, from: NodeHandle, to: NodeHandle, reachable_pred: &F) -> bool
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        self.find_path(from, to, reachable_pred).is_some()
+    }
+    /// Find a path that leads from `from` to `to`, as for `IODag::find_path`.
+    /// Each node's edges are copied as the search reaches it, so if the graph is being modified
+    /// concurrently, the path may no longer be present by the time it's returned.
+    pub fn find_path<F>(&self, from: NodeHandle, to: NodeHandle, reachable_pred: &F) -> Option<Vec<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        if !self.exists(Some(from)) || !self.exists(Some(to)) {
+            return None;
+        }
+        if from == to {
+            return Some(vec![]);
+        }
+        let arrived = |edge: &Edge<W>| *edge.to() == Some(to);
+        let mut outbound = |node: NodeHandle| self.iter_outbound_edges(Some(node)).collect();
+        let mut visited = HashSet::new();
+        self.iter_outbound_edges(Some(from)).filter_map(|edge| {
+            let mut path = vec![edge.clone()];
+            let found = edge.to().is_some() && visited.insert(edge.clone()) && Self::search_path(&arrived, &edge, reachable_pred, &mut outbound, &mut path, &mut visited);
+            if found {
+                Some(path)
+            } else {
+                None
+            }
+        }).next()
+    }
+    /// Removes the node (if it exists)
+    /// Errors if the node has incoming or outgoing edges.
+    pub fn del_node(&self, node: NodeHandle) -> Result<(), DagError<Edge<W>>> {
+        let mut shard = self.write_shard(Some(node));
+        match shard.edges.get(&Some(node)) {
+            // Already deleted
+            None => return Ok(()),
+            Some(edge_set) if !edge_set.is_empty() => return Err(DagError::NodeHasEdges),
+            Some(_) => {},
+        }
+        shard.edges.remove(&Some(node));
+        shard.node_data.remove(&node);
+        Ok(())
+    }
+    /// Removes the edge.
+    /// Errors if the edge isn't present.
+    pub fn del_edge(&self, edge: Edge<W>) -> Result<(), DagError<Edge<W>>> {
+        let mut guards = self.lock_shards(Self::endpoint_shards(&edge));
+        let from_shard = Self::shard_index(*edge.from());
+        let was_present = match guards.get_mut(&from_shard).unwrap().edges.get_mut(edge.from()) {
+            Some(edge_set) => edge_set.outbound.remove(&edge),
+            None => false,
+        };
+        if !was_present {
+            return Err(DagError::NoSuchEdge);
+        }
+        // The graph is consistent, so the inbound half must exist too.
+        let to_shard = guards.get_mut(&Self::shard_index(*edge.to())).unwrap();
+        to_shard.edges.get_mut(edge.to()).unwrap().inbound.remove(&edge);
+        for shard in guards.values_mut() {
+            shard.version += 1;
+        }
+        Ok(())
+    }
+    /// Convert to an `IODag`, preserving node handles.
+    pub fn into_iodag(self) -> IODag<N, W> {
+        let mut dag = IODag::new();
+        // Indices aren't reused here, so every index that was handed out belongs to a live node
+        // or a deleted one. Start the slots of deleted nodes at the next generation, so that
+        // their handles stay stale.
+        let node_counter = self.node_counter.into_inner();
+        dag.set_slot_generations(vec![1; node_counter as usize]);
+        let mut nodes = vec![];
+        let mut edges = vec![];
+        for shard in self.shards {
+            let shard = shard.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+            nodes.extend(shard.node_data);
+            edges.extend(shard.edges.into_values().flat_map(|edge_set| edge_set.outbound));
+        }
+        nodes.sort_by_key(|&(handle, _)| handle.index());
+        for (handle, node_data) in nodes {
+            // Re-creating a node in a free slot can't fail.
+            let _ = dag.apply_op(EditOp::AddNode(handle, node_data));
+        }
+        // The edges come in no particular order, so they're ordered once they're all in.
+        dag.restore_edges(edges);
+        dag
+    }
+
+    /// Search for a cycle that `edge` would close, reading each node's outbound edges via
+    /// `outbound`. Returns the cycle, ordered as in `DagError::Cycle`.
+    fn find_cycle<F, R>(edge: &Edge<W>, reachable_pred: &F, outbound: &mut R) -> Option<Vec<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool, R: FnMut(NodeHandle) -> Vec<Edge<W>>
+    {
+        if edge.from().is_none() || edge.to().is_none() {
+            // Edges to/from null can't close a cycle.
+            return None;
+        }
+        let mut path = vec![];
+        // do we have (base -> [Node] -> edge) and Node passes the connection?
+        let arrived = |base: &Edge<W>| base.to() == edge.from() && reachable_pred(base, edge);
+        if Self::search_path(&arrived, edge, reachable_pred, outbound, &mut path, &mut HashSet::new()) {
+            let mut cycle = vec![edge.clone()];
+            cycle.extend(path);
+            Some(cycle)
+        } else {
+            None
+        }
+    }
+    /// Returns true if the target is reachable from `base` (see `IODag::search_path`).
+    fn search_path<F, T, R>(arrived: &T, base: &Edge<W>, reachable_pred: &F, outbound: &mut R, path: &mut Vec<Edge<W>>, visited: &mut HashSet<Edge<W>>) -> bool
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool, T: Fn(&Edge<W>) -> bool, R: FnMut(NodeHandle) -> Vec<Edge<W>>
+    {
+        let node = match *base.to() {
+            // if the base is an output, no edges are reachable.
+            None => return false,
+            Some(node) => node,
+        };
+        // does base lead straight to the target?
+        if arrived(base) {
+            return true;
+        }
+        // Each entry holds an edge on the current path, along with the edges leaving its
+        // destination that are yet to be tried.
+        let mut stack = vec![(base.clone(), outbound(node).into_iter())];
+        while let Some(&mut (ref prev, ref mut edges)) = stack.last_mut() {
+            match edges.next() {
+                Some(edge) => {
+                    if edge.to().is_some() && reachable_pred(prev, &edge) && visited.insert(edge.clone()) {
+                        path.push(edge.clone());
+                        if arrived(&edge) {
+                            return true;
+                        }
+                        let edges = outbound(edge.to().unwrap());
+                        stack.push((edge, edges.into_iter()));
+                    }
+                },
+                None => {
+                    // Every edge after this one is a dead end, so back up.
+                    stack.pop();
+                    if !stack.is_empty() {
+                        path.pop();
+                    }
+                }
+            }
+        }
+        false
+    }
+    /// Insert `edge`, given the cycle it would close (as found with the shards in `guards` held).
+    /// `guards` must include the shards of both endpoints.
+    fn insert_locked(guards: &mut ShardGuards<N, W>, edge: Edge<W>, cycle: Option<Vec<Edge<W>>>) -> Result<(), DagError<Edge<W>>> {
+        let (from_shard, to_shard) = (Self::shard_index(*edge.from()), Self::shard_index(*edge.to()));
+        if !guards[&from_shard].edges.contains_key(edge.from()) || !guards[&to_shard].edges.contains_key(edge.to()) {
+            return Err(DagError::NoSuchNode);
+        }
+        if let Some(cycle) = cycle {
+            return Err(DagError::Cycle(cycle));
+        }
+        let from = guards.get_mut(&from_shard).unwrap();
+        if !from.edges.get_mut(edge.from()).unwrap().outbound.insert(edge.clone()) {
+            // Already present.
+            return Ok(());
+        }
+        from.version += 1;
+        let to = guards.get_mut(&to_shard).unwrap();
+        to.edges.get_mut(edge.to()).unwrap().inbound.insert(edge);
+        to.version += 1;
+        Ok(())
+    }
+    /// Push every node reachable from `node` (via node -> node edges) onto `ordered`, deepest
+    /// first.
+    /// The search keeps its own stack, so that arbitrarily deep graphs can be sorted.
+    fn depth_first_sort(&self, node: NodeHandle, ordered: &mut Vec<NodeHandle>, marked: &mut HashSet<NodeHandle>) {
+        if !marked.insert(node) {
+            return;
+        }
+        // Each entry holds a node, along with the edges leaving it that are yet to be followed.
+        let mut stack = vec![(node, self.iter_outbound_edges(Some(node)))];
+        while let Some(&mut (_, ref mut edges)) = stack.last_mut() {
+            match edges.next() {
+                Some(edge) => {
+                    if let Some(to) = *edge.to() {
+                        if marked.insert(to) {
+                            let edges = self.iter_outbound_edges(Some(to));
+                            stack.push((to, edges));
+                        }
+                    }
+                },
+                None => {
+                    // Everything beneath the node has been pushed, so it can follow.
+                    let (node, _) = stack.pop().unwrap();
+                    ordered.push(node);
+                }
+            }
+        }
+    }
+}
+
+impl<N, W> ConcurrentIODag<N, W>
+    where W: Hash + Eq + PartialEq {
+    fn shard_index(node: Option<NodeHandle>) -> usize {
+        node.map_or(0, |node| (node.index() % SHARD_COUNT as u64) as usize)
+    }
+    /// Return true if the node (or null) exists.
+    fn exists(&self, node: Option<NodeHandle>) -> bool {
+        self.read_shard(node).edges.contains_key(&node)
+    }
+    /// The shards holding the edge's endpoints.
+    fn endpoint_shards(edge: &Edge<W>) -> Vec<usize> {
+        vec![Self::shard_index(*edge.from()), Self::shard_index(*edge.to())]
+    }
+    // Searches (which run client code) only read the shards, and every modification is complete
+    // before its lock is released, so a panic while a shard was locked can't have left it
+    // inconsistent.
+    fn read(shard: &RwLock<Shard<N, W>>) -> RwLockReadGuard<'_, Shard<N, W>> {
+        shard.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    fn read_shard(&self, node: Option<NodeHandle>) -> RwLockReadGuard<'_, Shard<N, W>> {
+        Self::read(&self.shards[Self::shard_index(node)])
+    }
+    fn write_shard(&self, node: Option<NodeHandle>) -> RwLockWriteGuard<'_, Shard<N, W>> {
+        self.shards[Self::shard_index(node)].write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    /// Write-lock the given shards, in order of index (so that racing callers can't deadlock).
+    fn lock_shards<I>(&self, indices: I) -> ShardGuards<'_, N, W>
+        where I: IntoIterator<Item=usize>
+    {
+        indices.into_iter().collect::<BTreeSet<_>>().into_iter().map(|index| {
+            (index, self.shards[index].write().unwrap_or_else(|poisoned| poisoned.into_inner()))
+        }).collect()
+    }
+}
+
+/// Exposes the node -> node edges through the generic interface, as for `IODag`.
+impl<N, W> OnDag<N, W> for ConcurrentIODag<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    type NodeHandle = NodeHandle;
+    type Edge = Edge<W>;
+    fn add_node(&mut self, node_data: N) -> Self::NodeHandle {
+        ConcurrentIODag::add_node(self, node_data)
+    }
+    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: W) -> Result<(), DagError<Self::Edge>> {
+        ConcurrentIODag::add_edge(self, Edge::new(Some(*from), Some(*to), data), &|_, _| true)
+    }
+    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: W) -> Result<(), DagError<Self::Edge>> {
+        self.del_edge(Edge::new(Some(*from), Some(*to), data))
+    }
+    fn iter_topo(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        // just a depth-first sort, but then reverse the results.
+        let mut ordered = vec![];
+        self.depth_first_sort(*from, &mut ordered, &mut HashSet::new());
+        ordered.into_iter().rev()
+    }
+    fn iter_topo_rev(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        let mut ordered = vec![];
+        self.depth_first_sort(*from, &mut ordered, &mut HashSet::new());
+        ordered.into_iter()
+    }
+    fn children(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        self.iter_outbound_edges(Some(*node))
+    }
+    fn parents(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        self.iter_inbound_edges(Some(*node))
+    }
+    fn contains(&self, node: &Self::NodeHandle) -> bool {
+        self.read_shard(Some(*node)).node_data.contains_key(node)
+    }
+    fn node_count(&self) -> usize {
+        self.shards.iter().map(|shard| Self::read(shard).node_data.len()).sum()
+    }
+    fn edge_count(&self) -> usize {
+        self.shards.iter().map(|shard| {
+            Self::read(shard).edges.values().map(|edge_set| edge_set.outbound.len()).sum::<usize>()
+        }).sum()
+    }
+}
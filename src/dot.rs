@@ -0,0 +1,170 @@
+//! Graphviz DOT output, for visualizing graphs while debugging, and input (see
+//! `IODag::read_dot`).
+//! Every DAG type has a `write_dot` method built on `write_graph`.
+//! The null endpoints of `IODag`/`IODagFull` edges are drawn as two terminals: edges from null
+//! leave `NULL_INPUT`, and edges to null enter `NULL_OUTPUT`. When reading, both names are
+//! reserved to mean null.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+
+/// Name of the terminal that edges from null are drawn from.
+pub const NULL_INPUT: &str = "null_in";
+/// Name of the terminal that edges to null are drawn to.
+pub const NULL_OUTPUT: &str = "null_out";
+
+/// Error encountered while reading a DOT file.
+/// `P` is the type of edge that describes cycles (see `DagError`).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DotError<P> {
+    /// The text isn't valid DOT (or uses features that aren't supported).
+    Syntax { line: usize, message: String },
+    /// The node/edge on `line` was rejected, either by a label/attribute parser, or by the graph.
+    Invalid { line: usize, message: String },
+    /// The edge on `line` would have closed the given cycle (ordered as in `DagError::Cycle`).
+    Cycle { line: usize, cycle: Vec<P> },
+}
+
+/// A graph read from a DOT file, along with the handles of its nodes, by name.
+pub struct DotImport<G, H> {
+    pub graph: G,
+    pub nodes: HashMap<String, H>,
+}
+
+/// How to draw an edge.
+#[derive(Clone, Copy)]
+pub(super) enum EdgeStyle {
+    Plain,
+    /// Drawn in bold red (e.g. to show the cycle that an edge would have closed).
+    Highlighted,
+    /// A highlighted edge that isn't actually in the graph (e.g. the edge that was rejected for
+    /// closing a cycle); also dashed.
+    Absent,
+}
+
+/// An edge to be written, with endpoints given by node index (None for null).
+pub(super) struct DotEdge {
+    pub(super) from: Option<u64>,
+    pub(super) to: Option<u64>,
+    pub(super) label: String,
+    pub(super) style: EdgeStyle,
+}
+
+impl EdgeStyle {
+    /// Style of an edge that's in the graph.
+    pub(super) fn present(is_highlighted: bool) -> Self {
+        if is_highlighted {
+            EdgeStyle::Highlighted
+        } else {
+            EdgeStyle::Plain
+        }
+    }
+}
+
+/// Writes the statements of a digraph, which is closed by `finish`.
+struct DotWriter<'a, Out: 'a + Write> {
+    out: &'a mut Out,
+}
+
+impl<'a, Out: Write> DotWriter<'a, Out> {
+    fn new(out: &'a mut Out) -> io::Result<Self> {
+        writeln!(out, "digraph {{")?;
+        Ok(DotWriter { out })
+    }
+    fn node(&mut self, id: &str, label: &str) -> io::Result<()> {
+        writeln!(self.out, "    {} [label={}];", id, quote(label))
+    }
+    /// Declare the terminal that edges from null are drawn from.
+    fn input_terminal(&mut self) -> io::Result<()> {
+        writeln!(self.out, "    {} [label=\"in\", shape=invhouse];", NULL_INPUT)
+    }
+    /// Declare the terminal that edges to null are drawn to.
+    fn output_terminal(&mut self) -> io::Result<()> {
+        writeln!(self.out, "    {} [label=\"out\", shape=house];", NULL_OUTPUT)
+    }
+    fn edge(&mut self, from: &str, to: &str, label: &str, style: &EdgeStyle) -> io::Result<()> {
+        let style = match *style {
+            EdgeStyle::Plain => "",
+            EdgeStyle::Highlighted => ", color=red, penwidth=2",
+            EdgeStyle::Absent => ", color=red, penwidth=2, style=dashed",
+        };
+        writeln!(self.out, "    {} -> {} [label={}{}];", from, to, quote(label), style)
+    }
+    fn finish(self) -> io::Result<()> {
+        writeln!(self.out, "}}")
+    }
+}
+
+/// Write a digraph containing the given nodes (index & label) and edges.
+/// These are sorted first, so that the output doesn't depend on hashing order. The null terminals
+/// are only declared if some edge uses them.
+pub(super) fn write_graph<Out: Write>(out: &mut Out, mut nodes: Vec<(u64, String)>, mut edges: Vec<DotEdge>) -> io::Result<()> {
+    nodes.sort_by_key(|&(index, _)| index);
+    edges.sort_by(|a, b| (a.from, a.to, &a.label).cmp(&(b.from, b.to, &b.label)));
+    let mut writer = DotWriter::new(out)?;
+    if edges.iter().any(|edge| edge.from.is_none()) {
+        writer.input_terminal()?;
+    }
+    if edges.iter().any(|edge| edge.to.is_none()) {
+        writer.output_terminal()?;
+    }
+    for (index, label) in nodes {
+        writer.node(&node_id(index), &label)?;
+    }
+    for edge in edges.iter() {
+        let from = edge.from.map_or(NULL_INPUT.to_string(), node_id);
+        let to = edge.to.map_or(NULL_OUTPUT.to_string(), node_id);
+        writer.edge(&from, &to, &edge.label, &edge.style)?;
+    }
+    writer.finish()
+}
+
+/// Id used for the node with the given index.
+fn node_id(index: u64) -> String {
+    format!("n{}", index)
+}
+
+/// Write `text` as a quoted DOT string.
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// True if the name refers to null when reading a graph.
+pub(super) fn is_null_name(name: &str) -> bool {
+    name == NULL_INPUT || name == NULL_OUTPUT
+}
+
+impl<P> DotError<P> {
+    /// The line of the DOT text that caused the error.
+    pub fn line(&self) -> usize {
+        match *self {
+            DotError::Syntax { line, .. } | DotError::Invalid { line, .. } | DotError::Cycle { line, .. } => line,
+        }
+    }
+}
+
+impl<P> fmt::Display for DotError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DotError::Syntax { line, ref message } => write!(f, "syntax error on line {}: {}", line, message),
+            DotError::Invalid { line, ref message } => write!(f, "invalid element on line {}: {}", line, message),
+            DotError::Cycle { line, ref cycle } =>
+                write!(f, "edge on line {} would create a cycle of length {}", line, cycle.len()),
+        }
+    }
+}
+
+impl<P: fmt::Debug> Error for DotError<P> {}
@@ -0,0 +1,359 @@
+//! Parser for the subset of the Graphviz DOT language needed to describe a DAG: a single digraph
+//! made of node, edge & attribute statements. Subgraphs, ports and HTML strings aren't supported.
+
+use std::collections::HashMap;
+
+use super::dot::DotError;
+
+/// A node, along with its attributes.
+pub(super) struct ParsedNode {
+    pub(super) name: String,
+    pub(super) attrs: HashMap<String, String>,
+    /// Line on which the node first appeared.
+    pub(super) line: usize,
+}
+
+pub(super) struct ParsedEdge {
+    pub(super) from: String,
+    pub(super) to: String,
+    pub(super) attrs: HashMap<String, String>,
+    /// Line of the edge operator.
+    pub(super) line: usize,
+}
+
+pub(super) struct ParsedGraph {
+    /// Every node (including those only mentioned by edges), in order of first appearance.
+    pub(super) nodes: Vec<ParsedNode>,
+    /// Every edge, in the order they appear.
+    pub(super) edges: Vec<ParsedEdge>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// An identifier, numeral or quoted string.
+    Id(String),
+    /// An unquoted keyword (these are case-insensitive, so they're stored in lowercase).
+    Keyword(&'static str),
+    Arrow,
+    UndirectedEdge,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Equals,
+    Semicolon,
+    Comma,
+}
+
+const KEYWORDS: [&str; 6] = ["strict", "graph", "digraph", "node", "edge", "subgraph"];
+
+/// Parse the text of a DOT file.
+pub(super) fn parse<P>(text: &str) -> Result<ParsedGraph, DotError<P>> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        nodes: vec![],
+        node_index: HashMap::new(),
+        edges: vec![],
+        node_defaults: HashMap::new(),
+        edge_defaults: HashMap::new(),
+    };
+    parser.graph()?;
+    Ok(ParsedGraph {
+        nodes: parser.nodes,
+        edges: parser.edges,
+    })
+}
+
+fn syntax_error<P>(line: usize, message: &str) -> DotError<P> {
+    DotError::Syntax {
+        line,
+        message: message.to_string(),
+    }
+}
+
+/// Split the text into tokens, each paired with the line it starts on (counting from 1).
+fn tokenize<P>(text: &str) -> Result<Vec<(Token, usize)>, DotError<P>> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    // Lines beginning with '#' are preprocessor output, which is ignored like a comment.
+    let mut at_line_start = true;
+    while let Some(c) = chars.next() {
+        let start_line = line;
+        let was_line_start = at_line_start;
+        at_line_start = c == '\n' || (at_line_start && c.is_whitespace());
+        let token = match c {
+            '\n' => {
+                line += 1;
+                continue;
+            },
+            c if c.is_whitespace() => continue,
+            '#' if was_line_start => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+                continue;
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+                continue;
+            },
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        None => return Err(syntax_error(start_line, "unterminated comment")),
+                        Some('/') if prev == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            prev = c;
+                        },
+                    }
+                }
+                continue;
+            },
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '=' => Token::Equals,
+            ';' => Token::Semicolon,
+            ',' => Token::Comma,
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                Token::Arrow
+            },
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                Token::UndirectedEdge
+            },
+            '"' => {
+                let mut id = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(syntax_error(start_line, "unterminated string")),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            None => return Err(syntax_error(start_line, "unterminated string")),
+                            // escaped line break: the string continues on the next line.
+                            Some('\n') => line += 1,
+                            Some('n') => id.push('\n'),
+                            Some(c @ '"') | Some(c @ '\\') => id.push(c),
+                            Some(c) => {
+                                id.push('\\');
+                                id.push(c);
+                            },
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            id.push(c);
+                        },
+                    }
+                }
+                Token::Id(id)
+            },
+            c if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' => {
+                let mut id = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '.' {
+                        id.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let lower = id.to_lowercase();
+                match KEYWORDS.iter().find(|&&keyword| keyword == lower) {
+                    Some(keyword) => Token::Keyword(keyword),
+                    None => Token::Id(id),
+                }
+            },
+            c => return Err(syntax_error(start_line, &format!("unexpected character '{}'", c))),
+        };
+        tokens.push((token, start_line));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    nodes: Vec<ParsedNode>,
+    /// Index into `nodes` of each node name.
+    node_index: HashMap<String, usize>,
+    edges: Vec<ParsedEdge>,
+    /// Attributes set by `node [...]`, applied to nodes that appear afterwards.
+    node_defaults: HashMap<String, String>,
+    /// Attributes set by `edge [...]`, applied to edges that appear afterwards.
+    edge_defaults: HashMap<String, String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|&(ref token, _line)| token)
+    }
+    /// Line of the next token (or of the last one, at the end of input).
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or(self.tokens.last()).map_or(1, |&(_, line)| line)
+    }
+    fn next<P>(&mut self) -> Result<Token, DotError<P>> {
+        match self.tokens.get(self.pos) {
+            None => Err(syntax_error(self.line(), "unexpected end of input")),
+            Some(&(ref token, _line)) => {
+                self.pos += 1;
+                Ok(token.clone())
+            },
+        }
+    }
+    /// Consume the next token if it's `token`.
+    fn accept(&mut self, token: &Token) -> bool {
+        let is_match = self.peek() == Some(token);
+        if is_match {
+            self.pos += 1;
+        }
+        is_match
+    }
+    fn expect<P>(&mut self, token: Token, what: &str) -> Result<(), DotError<P>> {
+        if self.accept(&token) {
+            Ok(())
+        } else {
+            Err(syntax_error(self.line(), &format!("expected {}", what)))
+        }
+    }
+    fn id<P>(&mut self) -> Result<String, DotError<P>> {
+        let line = self.line();
+        match self.next()? {
+            Token::Id(id) => Ok(id),
+            _ => Err(syntax_error(line, "expected an identifier")),
+        }
+    }
+
+    /// graph : [ strict ] digraph [ ID ] '{' stmt_list '}'
+    fn graph<P>(&mut self) -> Result<(), DotError<P>> {
+        self.accept(&Token::Keyword("strict"));
+        if self.accept(&Token::Keyword("graph")) {
+            return Err(syntax_error(self.line(), "undirected graphs aren't supported"));
+        }
+        self.expect(Token::Keyword("digraph"), "'digraph'")?;
+        if let Some(&Token::Id(_)) = self.peek() {
+            self.pos += 1;
+        }
+        self.expect(Token::LBrace, "'{'")?;
+        while !self.accept(&Token::RBrace) {
+            self.statement()?;
+            self.accept(&Token::Semicolon);
+        }
+        if self.pos < self.tokens.len() {
+            return Err(syntax_error(self.line(), "unexpected input after the graph"));
+        }
+        Ok(())
+    }
+    fn statement<P>(&mut self) -> Result<(), DotError<P>> {
+        let line = self.line();
+        match self.next()? {
+            Token::Keyword("graph") => {
+                self.attr_list()?;
+            },
+            Token::Keyword("node") => {
+                let attrs = self.attr_list()?;
+                self.node_defaults.extend(attrs);
+            },
+            Token::Keyword("edge") => {
+                let attrs = self.attr_list()?;
+                self.edge_defaults.extend(attrs);
+            },
+            Token::Keyword("subgraph") | Token::LBrace => {
+                return Err(syntax_error(line, "subgraphs aren't supported"));
+            },
+            Token::Id(id) => {
+                if self.accept(&Token::Equals) {
+                    // graph attribute
+                    self.id()?;
+                } else {
+                    self.node_or_edges(id, line)?;
+                }
+            },
+            _ => return Err(syntax_error(line, "expected a statement")),
+        }
+        Ok(())
+    }
+    /// node_stmt : ID [ attr_list ]
+    /// edge_stmt : ID ( '->' ID )+ [ attr_list ]
+    fn node_or_edges<P>(&mut self, first: String, line: usize) -> Result<(), DotError<P>> {
+        let mut names = vec![first];
+        let mut lines = vec![line];
+        loop {
+            let line = self.line();
+            if self.accept(&Token::Arrow) {
+                names.push(self.id()?);
+                lines.push(line);
+            } else if self.accept(&Token::UndirectedEdge) {
+                return Err(syntax_error(line, "undirected edges aren't supported"));
+            } else {
+                break;
+            }
+        }
+        let attrs = self.attr_list()?;
+        if names.len() == 1 {
+            let index = self.node(names.pop().unwrap(), line);
+            self.nodes[index].attrs.extend(attrs);
+        } else {
+            for (index, &line) in lines.iter().enumerate().skip(1) {
+                self.node(names[index - 1].clone(), lines[index - 1]);
+                self.node(names[index].clone(), line);
+                let mut edge_attrs = self.edge_defaults.clone();
+                edge_attrs.extend(attrs.iter().map(|(key, value)| (key.clone(), value.clone())));
+                self.edges.push(ParsedEdge {
+                    from: names[index - 1].clone(),
+                    to: names[index].clone(),
+                    attrs: edge_attrs,
+                    line,
+                });
+            }
+        }
+        Ok(())
+    }
+    /// Return the index of the named node, adding it if it hasn't appeared yet.
+    fn node(&mut self, name: String, line: usize) -> usize {
+        if let Some(&index) = self.node_index.get(&name) {
+            return index;
+        }
+        let index = self.nodes.len();
+        self.node_index.insert(name.clone(), index);
+        self.nodes.push(ParsedNode {
+            name,
+            attrs: self.node_defaults.clone(),
+            line,
+        });
+        index
+    }
+    /// attr_list : ( '[' [ a_list ] ']' )*
+    /// a_list : ID [ '=' ID ] [ ( ';' | ',' ) ] [ a_list ]
+    fn attr_list<P>(&mut self) -> Result<HashMap<String, String>, DotError<P>> {
+        let mut attrs = HashMap::new();
+        while self.accept(&Token::LBracket) {
+            while !self.accept(&Token::RBracket) {
+                let key = self.id()?;
+                let value = if self.accept(&Token::Equals) {
+                    self.id()?
+                } else {
+                    "true".to_string()
+                };
+                attrs.insert(key, value);
+                if !self.accept(&Token::Comma) {
+                    self.accept(&Token::Semicolon);
+                }
+            }
+        }
+        Ok(attrs)
+    }
+}
@@ -0,0 +1,54 @@
+//! Error type shared by all DAG implementations.
+//! Whenever an operation returns an error, the graph is left in the same state as before the
+//! operation.
+
+use std::error::Error;
+use std::fmt;
+
+/// `P` is the edge type used to describe paths through the graph (e.g. `FullEdge` for the
+/// Rc-based DAGs, or `iodag::Edge` for `IODag`).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DagError<P> {
+    /// Inserting the edge would have created a cycle.
+    /// The witness begins with the rejected edge, followed by the existing edges that lead from
+    /// its destination back to its source.
+    Cycle(Vec<P>),
+    /// Inserting the edge would have created a cycle whose cumulative cost is zero.
+    /// The witness is ordered the same way as for `Cycle`.
+    ZeroCostCycle(Vec<P>),
+    /// A handle that belongs to a different graph was passed in.
+    ForeignHandle,
+    /// A handle refers to a node that doesn't exist in the graph.
+    NoSuchNode,
+    /// The edge being removed isn't present in the graph.
+    NoSuchEdge,
+    /// The node can't be removed because edges are still attached to it.
+    NodeHasEdges,
+}
+
+impl<P> DagError<P> {
+    /// If the error was caused by a cycle (zero-cost or otherwise), return the edges forming it.
+    pub fn cycle(&self) -> Option<&[P]> {
+        match *self {
+            DagError::Cycle(ref path) | DagError::ZeroCostCycle(ref path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl<P> fmt::Display for DagError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DagError::Cycle(ref path) =>
+                write!(f, "edge would create a cycle of length {}", path.len()),
+            DagError::ZeroCostCycle(ref path) =>
+                write!(f, "edge would create a zero-cost cycle of length {}", path.len()),
+            DagError::ForeignHandle => write!(f, "handle belongs to a different graph"),
+            DagError::NoSuchNode => write!(f, "node does not exist in the graph"),
+            DagError::NoSuchEdge => write!(f, "edge does not exist in the graph"),
+            DagError::NodeHasEdges => write!(f, "node still has edges attached"),
+        }
+    }
+}
+
+impl<P: fmt::Debug> Error for DagError<P> {}
@@ -0,0 +1,473 @@
+//! GraphML input & output for `IODag` & `IODagFull`, for exchanging graphs with tools such as yEd
+//! and Gephi (see `IODag::write_graphml` & `IODag::read_graphml`).
+//! Node data & edge weights are converted to & from GraphML attributes (key/value pairs) by the
+//! client. Nodes are identified as "n{index}", where index is the slot of their handle, and the
+//! generation of each slot is written as a graph attribute, so that a graph which is written &
+//! read back has the same handles. Null endpoints use the same reserved
+//! names as DOT (`dot::NULL_INPUT` & `dot::NULL_OUTPUT`).
+//! Only a single, flat, directed graph is supported: nested graphs, hyperedges & undirected edges
+//! are rejected. Attributes without a name (such as yEd's graphics) are ignored.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use xml::attribute::OwnedAttribute;
+use xml::common::Position;
+use xml::reader::{EventReader, XmlEvent};
+
+use super::dot::{self, NULL_INPUT, NULL_OUTPUT};
+
+/// Name of the graph attribute that holds the generation of each slot (separated by spaces), so
+/// that handles of deleted nodes stay stale after a round trip.
+const SLOTS: &str = "slots";
+
+/// Error encountered while reading a GraphML file.
+/// `P` is the type of edge that describes cycles (see `DagError`).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GraphmlError<P> {
+    /// The text isn't well-formed XML.
+    Xml { line: usize, message: String },
+    /// The element on `line` isn't valid GraphML (or uses features that aren't supported), or was
+    /// rejected by an attribute parser or by the graph.
+    Invalid { line: usize, message: String },
+    /// The edge on `line` would have closed the given cycle (ordered as in `DagError::Cycle`).
+    Cycle { line: usize, cycle: Vec<P> },
+}
+
+/// A graph read from a GraphML file, along with the handles of its nodes, by id.
+pub struct GraphmlImport<G, H> {
+    pub graph: G,
+    pub nodes: HashMap<String, H>,
+}
+
+/// An edge to be written, with endpoints given by node index (None for null).
+pub(super) struct GraphmlEdge {
+    pub(super) from: Option<u64>,
+    pub(super) to: Option<u64>,
+    pub(super) attrs: HashMap<String, String>,
+}
+
+/// A node read from a file, along with its attributes (by name, with defaults applied).
+pub(super) struct ParsedNode {
+    pub(super) id: String,
+    pub(super) attrs: HashMap<String, String>,
+    pub(super) line: usize,
+}
+
+pub(super) struct ParsedEdge {
+    /// Node id, or a null name.
+    pub(super) source: String,
+    pub(super) target: String,
+    pub(super) attrs: HashMap<String, String>,
+    pub(super) line: usize,
+}
+
+pub(super) struct ParsedGraph {
+    /// Every node, in document order. The null terminals aren't included.
+    pub(super) nodes: Vec<ParsedNode>,
+    /// Every edge, in document order.
+    pub(super) edges: Vec<ParsedEdge>,
+    /// Slot of each node, and the generation of every slot.
+    pub(super) indices: Vec<u64>,
+    pub(super) generations: Vec<u64>,
+}
+
+/// Write a graph containing the given nodes (index & attributes) and edges.
+/// Every attribute name is declared as a string-typed key. Everything is sorted first, so that
+/// the output doesn't depend on hashing order. The null terminals are only declared if some edge
+/// uses them.
+pub(super) fn write_graph<Out: Write>(out: &mut Out, nodes: Vec<(u64, HashMap<String, String>)>, edges: Vec<GraphmlEdge>, generations: &[u64]) -> io::Result<()> {
+    let mut nodes = nodes.into_iter().map(|(index, attrs)| (index, sorted(attrs))).collect::<Vec<_>>();
+    nodes.sort();
+    let mut edges = edges.into_iter().map(|edge| (edge.from, edge.to, sorted(edge.attrs))).collect::<Vec<_>>();
+    edges.sort();
+    // Key ids are assigned in order of (domain, name).
+    let keys = {
+        let node_keys = nodes.iter().flat_map(|(_, attrs)| attrs.iter()).map(|(name, _)| ("node", name.as_str()));
+        let edge_keys = edges.iter().flat_map(|(_, _, attrs)| attrs.iter()).map(|(name, _)| ("edge", name.as_str()));
+        node_keys.chain(edge_keys).collect::<BTreeSet<_>>()
+    };
+    let key_ids = keys.iter().enumerate().map(|(index, &key)| (key, format!("d{}", index + 1))).collect::<HashMap<_, _>>();
+    let data = |domain, attrs: &[(String, String)]| {
+        attrs.iter().map(|(name, value)| (key_ids[&(domain, name.as_str())].clone(), value.clone())).collect::<Vec<_>>()
+    };
+
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(out, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">")?;
+    writeln!(out, "  <key id=\"d0\" for=\"graph\" attr.name=\"{}\" attr.type=\"string\"/>", SLOTS)?;
+    for key in keys.iter() {
+        writeln!(out, "  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"string\"/>", key_ids[key], key.0, escape(key.1))?;
+    }
+    writeln!(out, "  <graph id=\"G\" edgedefault=\"directed\">")?;
+    let generations = generations.iter().map(|generation| generation.to_string()).collect::<Vec<_>>();
+    writeln!(out, "    <data key=\"d0\">{}</data>", generations.join(" "))?;
+    if edges.iter().any(|&(from, _, _)| from.is_none()) {
+        writeln!(out, "    <node id=\"{}\"/>", NULL_INPUT)?;
+    }
+    if edges.iter().any(|&(_, to, _)| to.is_none()) {
+        writeln!(out, "    <node id=\"{}\"/>", NULL_OUTPUT)?;
+    }
+    for (index, attrs) in nodes.iter() {
+        let tag = format!("node id=\"{}\"", node_id(*index));
+        write_element(out, &tag, "node", &data("node", attrs))?;
+    }
+    for (from, to, attrs) in edges.iter() {
+        let from = from.map_or(NULL_INPUT.to_string(), node_id);
+        let to = to.map_or(NULL_OUTPUT.to_string(), node_id);
+        let tag = format!("edge source=\"{}\" target=\"{}\"", from, to);
+        write_element(out, &tag, "edge", &data("edge", attrs))?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")
+}
+
+/// Write a node or edge, given its opening tag (without brackets) and its data (key id & value).
+fn write_element<Out: Write>(out: &mut Out, tag: &str, name: &str, data: &[(String, String)]) -> io::Result<()> {
+    if data.is_empty() {
+        return writeln!(out, "    <{}/>", tag);
+    }
+    writeln!(out, "    <{}>", tag)?;
+    for (key, value) in data {
+        writeln!(out, "      <data key=\"{}\">{}</data>", key, escape(value))?;
+    }
+    writeln!(out, "    </{}>", name)
+}
+
+fn sorted(attrs: HashMap<String, String>) -> Vec<(String, String)> {
+    let mut attrs = attrs.into_iter().collect::<Vec<_>>();
+    attrs.sort();
+    attrs
+}
+
+/// Id used for the node with the given index.
+fn node_id(index: u64) -> String {
+    format!("n{}", index)
+}
+
+/// The index of the node with the given id, if it's one that `node_id` would produce.
+fn node_index(id: &str) -> Option<u64> {
+    id.get(1..).and_then(|index| index.parse().ok()).filter(|&index| node_id(index) == id)
+}
+
+/// Escape text for use within an element or a quoted attribute.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Copy the attributes into a new map, with `prefix` added to each name.
+pub(super) fn add_prefix(prefix: &str, attrs: HashMap<String, String>) -> HashMap<String, String> {
+    attrs.into_iter().map(|(name, value)| (format!("{}{}", prefix, name), value)).collect()
+}
+
+/// The attributes whose names start with `prefix`, with the prefix removed.
+pub(super) fn strip_prefix(prefix: &str, attrs: &HashMap<String, String>) -> HashMap<String, String> {
+    attrs.iter().filter_map(|(name, value)| {
+        name.strip_prefix(prefix).map(|name| (name.to_string(), value.clone()))
+    }).collect()
+}
+
+/// A declared attribute.
+struct Key {
+    /// The kind of element it applies to ("graph", "node", "edge" or "all").
+    domain: String,
+    /// None for attributes that aren't named (and so can't be passed on).
+    name: Option<String>,
+    default: Option<String>,
+}
+
+/// Text being read from a `<data>` or `<default>` element.
+struct Text {
+    /// The `<data>` key, or None for a `<default>`.
+    key: Option<String>,
+    text: String,
+    /// Set if the element contains other elements, in which case it's ignored.
+    has_elements: bool,
+    /// Depth of the element within the document.
+    depth: usize,
+    line: usize,
+}
+
+struct Parser {
+    /// Names of the enclosing elements.
+    path: Vec<String>,
+    keys: HashMap<String, Key>,
+    /// Id of the key being declared.
+    key: Option<String>,
+    text: Option<Text>,
+    has_graph: bool,
+    edges_directed: bool,
+    graph_attrs: HashMap<String, String>,
+    nodes: Vec<ParsedNode>,
+    edges: Vec<ParsedEdge>,
+}
+
+/// Read a GraphML file, checking that it describes a single directed graph whose edges connect
+/// declared nodes, and assigning each node a handle index.
+pub(super) fn parse<R: Read, P>(input: R) -> Result<ParsedGraph, GraphmlError<P>> {
+    let mut reader = EventReader::new(input);
+    let mut parser = Parser {
+        path: vec![],
+        keys: HashMap::new(),
+        key: None,
+        text: None,
+        has_graph: false,
+        edges_directed: true,
+        graph_attrs: HashMap::new(),
+        nodes: vec![],
+        edges: vec![],
+    };
+    loop {
+        let event = reader.next().map_err(|err| GraphmlError::Xml {
+            line: err.position().row as usize + 1,
+            message: err.msg().to_string(),
+        })?;
+        let line = reader.position().row as usize + 1;
+        match event {
+            XmlEvent::StartElement { name, attributes, .. } => parser.start(name.local_name, &attributes, line)?,
+            XmlEvent::EndElement { .. } => parser.end()?,
+            XmlEvent::Characters(text) | XmlEvent::CData(text) | XmlEvent::Whitespace(text) => {
+                if let Some(ref mut current) = parser.text {
+                    current.text.push_str(&text);
+                }
+            },
+            XmlEvent::EndDocument => break,
+            _ => {},
+        }
+    }
+    parser.finish()
+}
+
+fn invalid<P>(line: usize, message: String) -> GraphmlError<P> {
+    GraphmlError::Invalid {
+        line,
+        message,
+    }
+}
+
+/// The value of the named attribute of an element.
+fn attribute<'a, P>(attributes: &'a [OwnedAttribute], name: &str, element: &str, line: usize) -> Result<&'a str, GraphmlError<P>> {
+    optional_attribute(attributes, name).ok_or_else(|| {
+        invalid(line, format!("<{}> is missing the '{}' attribute", element, name))
+    })
+}
+
+fn optional_attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|attr| attr.name.local_name == name).map(|attr| attr.value.as_str())
+}
+
+impl Parser {
+    fn start<P>(&mut self, name: String, attributes: &[OwnedAttribute], line: usize) -> Result<(), GraphmlError<P>> {
+        if let Some(ref mut text) = self.text {
+            text.has_elements = true;
+            self.path.push(name);
+            return Ok(());
+        }
+        match (self.path.last().map(String::as_str), name.as_str()) {
+            (None, "graphml") => {},
+            (None, _) => return Err(invalid(line, format!("expected <graphml>, found <{}>", name))),
+            (Some("graphml"), "key") => {
+                let id = attribute(attributes, "id", "key", line)?;
+                let key = Key {
+                    domain: optional_attribute(attributes, "for").unwrap_or("all").to_string(),
+                    name: optional_attribute(attributes, "attr.name").map(str::to_string),
+                    default: None,
+                };
+                if self.keys.insert(id.to_string(), key).is_some() {
+                    return Err(invalid(line, format!("key '{}' is declared more than once", id)));
+                }
+                self.key = Some(id.to_string());
+            },
+            (Some("key"), "default") => {
+                if self.path.len() != 2 {
+                    return Err(invalid(line, "<default> must be inside a <key> declared by <graphml>".to_string()));
+                }
+                self.begin_text(None, line);
+            },
+            (Some("graphml"), "graph") => {
+                if self.has_graph {
+                    return Err(invalid(line, "only a single graph is supported".to_string()));
+                }
+                self.has_graph = true;
+                self.edges_directed = optional_attribute(attributes, "edgedefault") != Some("undirected");
+            },
+            (Some(_), "graph") => return Err(invalid(line, "nested graphs aren't supported".to_string())),
+            (Some("graph"), "node") => {
+                self.nodes.push(ParsedNode {
+                    id: attribute(attributes, "id", "node", line)?.to_string(),
+                    attrs: HashMap::new(),
+                    line,
+                });
+            },
+            (Some("graph"), "edge") => {
+                let directed = optional_attribute(attributes, "directed").map_or(self.edges_directed, |directed| directed == "true");
+                if !directed {
+                    return Err(invalid(line, "undirected edges aren't supported".to_string()));
+                }
+                self.edges.push(ParsedEdge {
+                    source: attribute(attributes, "source", "edge", line)?.to_string(),
+                    target: attribute(attributes, "target", "edge", line)?.to_string(),
+                    attrs: HashMap::new(),
+                    line,
+                });
+            },
+            (Some("graph"), "hyperedge") => return Err(invalid(line, "hyperedges aren't supported".to_string())),
+            (Some(element), "data") if element == "graph" || element == "node" || element == "edge" => {
+                // Only the graph & its own nodes & edges have attributes.
+                let depth = if element == "graph" { 2 } else { 3 };
+                if self.path.len() != depth || self.path[1] != "graph" {
+                    return Err(invalid(line, format!("<data> isn't allowed in a <{}> outside of <graph>", element)));
+                }
+                let key = attribute(attributes, "key", "data", line)?.to_string();
+                self.begin_text(Some(key), line);
+            },
+            // Anything else (descriptions, ports, extensions) is skipped.
+            _ => {},
+        }
+        self.path.push(name);
+        Ok(())
+    }
+    fn begin_text(&mut self, key: Option<String>, line: usize) {
+        self.text = Some(Text {
+            key,
+            text: String::new(),
+            has_elements: false,
+            depth: self.path.len(),
+            line,
+        });
+    }
+    fn end<P>(&mut self) -> Result<(), GraphmlError<P>> {
+        let name = self.path.pop().unwrap_or_default();
+        let parent = self.path.last().map(String::as_str);
+        if parent == Some("graphml") && name == "key" {
+            self.key = None;
+        }
+        if !self.text.as_ref().is_some_and(|text| text.depth == self.path.len()) {
+            return Ok(());
+        }
+        // This closes the element whose text is being read.
+        let text = self.text.take().unwrap();
+        if text.has_elements {
+            return Ok(());
+        }
+        match text.key {
+            None => {
+                let id = self.key.as_ref().unwrap();
+                self.keys.get_mut(id).unwrap().default = Some(text.text);
+            },
+            Some(key) => {
+                let element = parent.unwrap();
+                let (domain, name) = match self.keys.get(&key) {
+                    None => return Err(invalid(text.line, format!("key '{}' isn't declared", key))),
+                    Some(declared) => (&declared.domain, &declared.name),
+                };
+                if domain != element && domain != "all" {
+                    return Err(invalid(text.line, format!("key '{}' is for <{}>, not <{}>", key, domain, element)));
+                }
+                let attrs = match element {
+                    "graph" => &mut self.graph_attrs,
+                    "node" => &mut self.nodes.last_mut().unwrap().attrs,
+                    _ => &mut self.edges.last_mut().unwrap().attrs,
+                };
+                if let Some(name) = name.clone() {
+                    attrs.insert(name, text.text);
+                }
+            },
+        }
+        Ok(())
+    }
+    fn finish<P>(mut self) -> Result<ParsedGraph, GraphmlError<P>> {
+        if !self.has_graph {
+            return Err(invalid(1, "no <graph> found".to_string()));
+        }
+        // Fill in defaults for any attributes that weren't given.
+        for key in self.keys.values() {
+            if let (Some(name), Some(default)) = (key.name.as_ref(), key.default.as_ref()) {
+                let applies = |domain: &str| key.domain == domain || key.domain == "all";
+                let graph_attrs = applies("graph").then_some(&mut self.graph_attrs);
+                let node_attrs = self.nodes.iter_mut().filter(|_| applies("node")).map(|node| &mut node.attrs);
+                let edge_attrs = self.edges.iter_mut().filter(|_| applies("edge")).map(|edge| &mut edge.attrs);
+                for attrs in graph_attrs.into_iter().chain(node_attrs).chain(edge_attrs) {
+                    attrs.entry(name.clone()).or_insert_with(|| default.clone());
+                }
+            }
+        }
+
+        let mut nodes = vec![];
+        let mut ids = HashSet::new();
+        for node in self.nodes {
+            if !ids.insert(node.id.clone()) {
+                return Err(invalid(node.line, format!("node '{}' is declared more than once", node.id)));
+            }
+            if !dot::is_null_name(&node.id) {
+                nodes.push(node);
+            }
+        }
+        for edge in self.edges.iter() {
+            // Edges can leave the input terminal & enter the output terminal, but not vice versa.
+            if edge.source == dot::NULL_OUTPUT || edge.target == dot::NULL_INPUT {
+                let name = if edge.source == dot::NULL_OUTPUT { &edge.source } else { &edge.target };
+                return Err(invalid(edge.line, format!("{} can't be used on that end of an edge", name)));
+            }
+            for id in [&edge.source, &edge.target] {
+                if !ids.contains(id) && !dot::is_null_name(id) {
+                    return Err(invalid(edge.line, format!("node '{}' isn't declared", id)));
+                }
+            }
+        }
+
+        // Nodes named by slot keep it, so long as the slot is listed; the rest are given new slots.
+        let mut generations = match self.graph_attrs.get(SLOTS) {
+            None => vec![],
+            Some(slots) => slots.split_whitespace().map(|generation| generation.parse::<u64>()).collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid(1, format!("invalid {}: '{}'", SLOTS, slots)))?,
+        };
+        let slot_count = generations.len() as u64;
+        let indices = nodes.iter().map(|node| match node_index(&node.id).filter(|&index| index < slot_count) {
+            Some(index) => index,
+            None => {
+                generations.push(0);
+                generations.len() as u64 - 1
+            },
+        }).collect();
+        Ok(ParsedGraph {
+            nodes,
+            edges: self.edges,
+            indices,
+            generations,
+        })
+    }
+}
+
+impl<P> GraphmlError<P> {
+    /// The line of the GraphML text that caused the error.
+    pub fn line(&self) -> usize {
+        match *self {
+            GraphmlError::Xml { line, .. } | GraphmlError::Invalid { line, .. } | GraphmlError::Cycle { line, .. } => line,
+        }
+    }
+}
+
+impl<P> fmt::Display for GraphmlError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GraphmlError::Xml { line, ref message } => write!(f, "XML error on line {}: {}", line, message),
+            GraphmlError::Invalid { line, ref message } => write!(f, "invalid element on line {}: {}", line, message),
+            GraphmlError::Cycle { line, ref cycle } =>
+                write!(f, "edge on line {} would create a cycle of length {}", line, cycle.len()),
+        }
+    }
+}
+
+impl<P: fmt::Debug> Error for GraphmlError<P> {}
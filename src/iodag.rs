@@ -1,29 +1,65 @@
-/// IODag is a DAG where every modification is enforced.
-/// However, edges are allowed to have one (or both) end at null.
+//! IODag is a DAG where every modification is enforced.
+//! However, edges are allowed to have one (or both) end at null.
+//!
+//! A topological order of the nodes is maintained online (Pearce-Kelly), so that inserting an
+//! edge which already agrees with the order is O(1), and otherwise only the region of the graph
+//! between the edge's endpoints is searched and reordered.
 
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::collections::hash_map;
 use std::hash::Hash;
+use std::io::{self, Write};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::de::Error;
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+
+use super::batch::EdgeBatch;
+use super::binary::{self, BinaryError};
+use super::dot::{self, DotEdge, DotError, DotImport, EdgeStyle};
+use super::dotparse;
+use super::error::DagError;
+#[cfg(feature = "graphml")]
+use super::graphml::{self, GraphmlEdge, GraphmlError, GraphmlImport};
+use super::ondag::OnDag;
+use super::slots::Slots;
+pub use super::iodagtxn::Transaction;
 
 /// N=Node Data
 /// W=Weight
 pub struct IODag<N, W>
     where W: Hash + Eq + PartialEq {
-    /// To create unique NodeHandles, we just assign them unique u64's from this counter.
-    node_counter: u64,
+    /// Allocates the slot & generation of each NodeHandle.
+    slots: Slots,
     edges: HashMap<Option<NodeHandle>, EdgeSet<W>>,
     node_data: HashMap<NodeHandle, N>,
+    /// Topological label of each node: every node -> node edge (other than feedback edges)
+    /// leads from a lower label to a higher one.
+    order: HashMap<NodeHandle, u64>,
+    /// The nodes, indexed by their topological label.
+    by_order: BTreeMap<u64, NodeHandle>,
+    /// Label for the next node added (new nodes have no edges, so they can go last).
+    next_order: u64,
+    /// Node -> node edges that close a loop between nodes, which was allowed because
+    /// `reachable_pred` didn't connect the edges around it. No order can satisfy these, so they're
+    /// left out of it.
+    feedback: HashSet<Edge<W>>,
 }
 
 /// Include both the outbound and inbound edges associated with a Node.
-struct EdgeSet<W>
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(super) struct EdgeSet<W>
     where W: Hash + Eq + PartialEq {
-    outbound: HashSet<Edge<W>>,
-    inbound: HashSet<Edge<W>>,
+    pub(super) outbound: HashSet<Edge<W>>,
+    pub(super) inbound: HashSet<Edge<W>>,
 }
 
-#[derive(Clone, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Edge<W>
     where W: Hash + Eq + PartialEq {
     from: Option<NodeHandle>,
@@ -31,12 +67,35 @@ pub struct Edge<W>
     weight: W,
 }
 
-#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeHandle {
     // TODO: add NonZero attribute (or similar) to optimize Option<NodeHandle>
-    // Note: After many add/del_node calls, a 32 bit counter may overflow & cause logic errors
-    //   in client code. Use 64 bits to avoid this.
+    /// Slot of the node. The slots of deleted nodes are reused.
     index: u64,
+    /// Tells apart the nodes that have occupied the slot, so that a handle to a deleted node is
+    /// never mistaken for whichever node reuses its slot.
+    generation: u64,
+}
+
+/// The serialized form of an IODag.
+/// The edge sets are written out as they're stored, and checked for consistency when read back.
+/// The topological order isn't written at all; it's rebuilt instead.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct IODagRepr<N, W>
+    where W: Hash + Eq + PartialEq {
+    /// Generation of each slot.
+    slots: Vec<u64>,
+    nodes: Vec<(NodeHandle, N)>,
+    edges: Vec<(Option<NodeHandle>, EdgeSet<W>)>,
+}
+
+impl<N, W> Default for IODag<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<N, W> IODag<N, W>
@@ -45,118 +104,482 @@ impl<N, W> IODag<N, W>
         let mut edges = HashMap::new();
         edges.insert(None, EdgeSet::new());
         IODag{
-            node_counter: 0,
-            edges: edges,
+            slots: Slots::new(),
+            edges,
             node_data : HashMap::new(),
+            order: HashMap::new(),
+            by_order: BTreeMap::new(),
+            next_order: 0,
+            feedback: HashSet::new(),
         }
     }
+    /// Panics if the node doesn't exist (see `try_node_data`).
     pub fn node_data(&self, node: NodeHandle) -> &N {
         &self.node_data[&node]
     }
-    pub fn iter_outbound_edges<'a>(&'a self, node: Option<NodeHandle>) -> impl Iterator<Item=&Edge<W>> + 'a {
-        self.edges[&node].outbound.iter()
+    /// Return the node's data, or None if the handle is stale (i.e. the node was deleted).
+    pub fn try_node_data(&self, node: NodeHandle) -> Option<&N> {
+        self.node_data.get(&node)
+    }
+    /// True unless the node was deleted (even if its slot has been reused since).
+    pub fn is_live(&self, node: NodeHandle) -> bool {
+        self.node_data.contains_key(&node)
     }
-    pub fn iter_inbound_edges<'a>(&'a self, node: Option<NodeHandle>) -> impl Iterator<Item=&Edge<W>> + 'a {
-        self.edges[&node].inbound.iter()
+    /// Iterate the edges leaving the node (none, if the node doesn't exist).
+    pub fn iter_outbound_edges<'a>(&'a self, node: Option<NodeHandle>) -> impl Iterator<Item=&'a Edge<W>> + 'a {
+        self.edges.get(&node).into_iter().flat_map(|edge_set| edge_set.outbound.iter())
     }
-    pub fn iter_nodes<'a>(&'a self) -> impl Iterator<Item=&NodeHandle> + 'a {
+    /// Iterate the edges entering the node (none, if the node doesn't exist).
+    pub fn iter_inbound_edges<'a>(&'a self, node: Option<NodeHandle>) -> impl Iterator<Item=&'a Edge<W>> + 'a {
+        self.edges.get(&node).into_iter().flat_map(|edge_set| edge_set.inbound.iter())
+    }
+    pub fn iter_nodes<'a>(&'a self) -> impl Iterator<Item=&'a NodeHandle> + 'a {
         self.node_data.keys()
     }
-    pub fn iter_edges<'a>(&'a self) -> impl Iterator<Item=&Edge<W>> + 'a {
+    /// Iterate all nodes in the maintained topological order: for every node -> node edge, the
+    /// source comes before the destination.
+    /// The exception is edges that close a loop between nodes (allowed only when the
+    /// `reachable_pred` used to add them disconnects that loop); these may point backwards.
+    pub fn iter_topo<'a>(&'a self) -> impl Iterator<Item=&'a NodeHandle> + 'a {
+        self.by_order.values()
+    }
+    pub fn iter_edges<'a>(&'a self) -> impl Iterator<Item=&'a Edge<W>> + 'a {
         // Note: we DON'T duplicate any edges here;
         // This captures all outbound edges, which handles the edge cases correctly (edges leaving
         // NULL AND edges leaving * and entering NULL).
-        self.edges.iter().flat_map(|(_node, edges)| {
+        self.edges.values().flat_map(|edges| {
             edges.outbound.iter()
         })
     }
     pub fn add_node(&mut self, node_data: N) -> NodeHandle {
+        let (index, generation) = self.slots.allocate();
         let handle = NodeHandle {
-            index: self.node_counter,
+            index,
+            generation,
         };
-        self.node_counter = self.node_counter+1;
         // Create storage for the node's outgoing edges
         // Panic if the NodeHandle was somehow already in use.
         assert!(self.edges.insert(Some(handle), EdgeSet::new()).is_none());
         // Store the node's data
         assert!(self.node_data.insert(handle, node_data).is_none());
+        // The node has no edges yet, so it's free to go last in the order.
+        self.order.insert(handle, self.next_order);
+        self.by_order.insert(self.next_order, handle);
+        self.next_order += 1;
         handle
     }
-    pub fn add_edge<F>(&mut self, edge: Edge<W>, reachable_pred: &F) -> Result<(), ()>
+    pub fn add_edge<F>(&mut self, edge: Edge<W>, reachable_pred: &F) -> Result<(), DagError<Edge<W>>>
         where F: Fn(&Edge<W>, &Edge<W>) -> bool
     {
-        self.can_add_edge(&edge, reachable_pred).and_then(|ok| {
-            self.add_edge_unchecked(edge);
-            Ok(ok)
-        })
+        self.can_add_edge(&edge, reachable_pred)?;
+        self.add_edge_unchecked(edge);
+        Ok(())
     }
     pub fn add_edge_unchecked(&mut self, edge: Edge<W>) {
-        self.edges.get_mut(&edge.from).unwrap().outbound.insert(edge.clone());
-        self.edges.get_mut(&edge.to).unwrap().inbound.insert(edge);
+        if self.edges[&edge.from].outbound.contains(&edge) {
+            return;
+        }
+        self.order_edge(&edge);
+        self.insert_raw(edge);
+    }
+    /// Apply every modification in `batch` as a single step (see `EdgeBatch`).
+    /// If the result would contain a cycle, the graph is left untouched and the error holds one
+    /// such cycle.
+    pub fn apply_batch<F>(&mut self, batch: EdgeBatch<Option<NodeHandle>, W>, reachable_pred: &F) -> Result<(), DagError<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        let EdgeBatch { removals, additions } = batch;
+        let all_exist = removals.iter().chain(additions.iter()).all(|(from, to, _)| {
+            self.edges.contains_key(from) && self.edges.contains_key(to)
+        });
+        if !all_exist {
+            return Err(DagError::NoSuchNode);
+        }
+        let mut removed = vec![];
+        for (from, to, weight) in removals {
+            let edge = Edge::new(from, to, weight);
+            if let Err(err) = self.del_edge(edge.clone()) {
+                for edge in removed.into_iter().rev() {
+                    self.add_edge_unchecked(edge);
+                }
+                return Err(err);
+            }
+            removed.push(edge);
+        }
+        // The intermediate states may be cyclic, which the order can't represent, so try out the
+        // new edges without it.
+        let mut added = vec![];
+        for (from, to, weight) in additions {
+            let edge = Edge::new(from, to, weight);
+            if self.insert_raw(edge.clone()) {
+                added.push(edge);
+            }
+        }
+        // Any new cycle must pass through one of the new edges (and edges to/from null can't
+        // close one).
+        let cycle = added.iter().filter(|edge| edge.from().is_some() && edge.to().is_some()).filter_map(|edge| {
+            let mut path = vec![];
+            if self.find_edge_path(edge, edge, reachable_pred, &mut path, &mut HashSet::new()) {
+                let mut cycle = vec![edge.clone()];
+                cycle.extend(path);
+                Some(cycle)
+            } else {
+                None
+            }
+        }).next();
+        for edge in added.iter() {
+            self.remove_raw(edge);
+        }
+        match cycle {
+            Some(cycle) => {
+                for edge in removed.into_iter().rev() {
+                    self.add_edge_unchecked(edge);
+                }
+                Err(DagError::Cycle(cycle))
+            },
+            None => {
+                // Now insert them for real, updating the order as we go.
+                for edge in added {
+                    self.add_edge_unchecked(edge);
+                }
+                Ok(())
+            }
+        }
+    }
+    /// Replace the edge `from` -> `to` weighted `old` with one weighted `new`.
+    /// `reachable_pred` may depend on the weights, so the new edge is checked as in `add_edge`; if
+    /// it would close a cycle, the old edge is left in place and the error holds that cycle.
+    pub fn set_edge_weight<F>(&mut self, from: Option<NodeHandle>, to: Option<NodeHandle>, old: W, new: W, reachable_pred: &F) -> Result<(), DagError<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        let mut batch = EdgeBatch::new();
+        batch.rm_edge(from, to, old).add_edge(from, to, new);
+        self.apply_batch(batch, reachable_pred)
     }
-    pub fn can_add_edge<F>(&self, edge: &Edge<W>, reachable_pred: &F) -> Result<(), ()>
+    /// On failure due to a cycle, the error holds the cycle that `edge` would have closed.
+    pub fn can_add_edge<F>(&self, edge: &Edge<W>, reachable_pred: &F) -> Result<(), DagError<Edge<W>>>
         where F: Fn(&Edge<W>, &Edge<W>) -> bool
     {
-        let is_cyclic = self.is_reachable(&edge, &edge, reachable_pred);
+        if !self.edges.contains_key(edge.from()) || !self.edges.contains_key(edge.to()) {
+            return Err(DagError::NoSuchNode);
+        }
+        let (from, to) = match (*edge.from(), *edge.to()) {
+            (Some(from), Some(to)) => (from, to),
+            // Edges to/from null can't close a cycle.
+            _ => return Ok(()),
+        };
+        // Without feedback edges, the order accounts for every path between nodes: an edge that
+        // agrees with it can't close a loop, and otherwise a loop needs a path from `to` to `from`
+        // among the nodes ordered between them.
+        if self.feedback.is_empty() && from != to {
+            let bound = self.order[&from];
+            if bound < self.order[&to] || self.forward_region(to, bound, from).is_some() {
+                return Ok(());
+            }
+        }
+        // There's a loop between nodes (or we can't rule one out), so check whether the edges
+        // around it actually connect.
+        let mut path = vec![];
+        let is_cyclic = self.find_edge_path(edge, edge, reachable_pred, &mut path, &mut HashSet::new());
 
         if is_cyclic {
-            Err(())
+            let mut cycle = vec![edge.clone()];
+            cycle.extend(path);
+            Err(DagError::Cycle(cycle))
         } else {
             Ok(())
         }
     }
+    /// Return true if adding `edge` would be rejected for closing a cycle.
+    pub fn would_create_cycle<F>(&self, edge: &Edge<W>, reachable_pred: &F) -> bool
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        matches!(self.can_add_edge(edge, reachable_pred), Err(DagError::Cycle(_)))
+    }
+    /// Return true if `to` is reachable from (or is) `from` (see `find_path`).
+    pub fn is_reachable<F>(&self, from: NodeHandle, to: NodeHandle, reachable_pred: &F) -> bool
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        self.find_path(from, to, reachable_pred).is_some()
+    }
+    /// Find a path that leads from `from` to `to`, where each edge is reachable from the one
+    /// before it according to `reachable_pred` (as for `add_edge`).
+    /// Returns the edges along that path (empty if `from == to`), or None if there's no such path
+    /// (or either node doesn't exist).
+    pub fn find_path<F>(&self, from: NodeHandle, to: NodeHandle, reachable_pred: &F) -> Option<Vec<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        if !self.is_live(from) || !self.is_live(to) {
+            return None;
+        }
+        if from == to {
+            return Some(vec![]);
+        }
+        // Without feedback edges, every path between nodes agrees with the order.
+        if self.feedback.is_empty() && self.order[&from] > self.order[&to] {
+            return None;
+        }
+        let arrived = |edge: &Edge<W>| *edge.to() == Some(to);
+        let mut visited = HashSet::new();
+        self.edges[&Some(from)].outbound.iter().filter_map(|edge| {
+            let mut path = vec![edge.clone()];
+            let found = edge.to().is_some() && visited.insert(edge) && self.search_path(&arrived, edge, reachable_pred, &mut path, &mut visited);
+            if found {
+                Some(path)
+            } else {
+                None
+            }
+        }).next()
+    }
     /// Removes the node (if it exists)
     /// Errors if the node has incoming or outgoing edges.
-    pub fn del_node(&mut self, node: NodeHandle) -> Result<(), ()> {
-        let ok_to_delete = match self.edges.entry(Some(node)) {
-            // Already deleted
-            hash_map::Entry::Vacant(_) => Ok(()),
-            hash_map::Entry::Occupied(entry) => {
-                if entry.get().is_empty() {
-                    entry.remove();
-                    Ok(())
-                } else {
-                    // Node has edges
-                    Err(())
-                }
+    pub fn del_node(&mut self, node: NodeHandle) -> Result<(), DagError<Edge<W>>> {
+        self.take_node(node).map(|_data| ())
+    }
+    /// Removes the edge.
+    /// Errors if the edge isn't present.
+    pub fn del_edge(&mut self, edge: Edge<W>) -> Result<(), DagError<Edge<W>>> {
+        if self.remove_raw(&edge) {
+            // Removing edges never invalidates the order.
+            self.feedback.remove(&edge);
+            Ok(())
+        } else {
+            Err(DagError::NoSuchEdge)
+        }
+    }
+
+    /// Build a graph from the text of a DOT file.
+    /// Each node's data is parsed from its label (or its name, if it has none) by `parse_node`, and
+    /// each edge's weight is parsed from its attributes by `parse_edge`. The names
+    /// `dot::NULL_INPUT` & `dot::NULL_OUTPUT` are reserved to mean null, and may only begin &
+    /// end edges respectively.
+    /// Nodes are added in order of appearance, and then each edge is added via `add_edge`; if one
+    /// would close a cycle, the error reports its line.
+    pub fn read_dot<FN, FE, F>(text: &str, mut parse_node: FN, mut parse_edge: FE, reachable_pred: &F) -> Result<DotImport<Self, NodeHandle>, DotError<Edge<W>>>
+        where FN: FnMut(&str) -> Result<N, String>, FE: FnMut(&HashMap<String, String>) -> Result<W, String>, F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        let parsed = dotparse::parse(text)?;
+        let mut dag = IODag::new();
+        let mut handles = HashMap::new();
+        for node in parsed.nodes {
+            if dot::is_null_name(&node.name) {
+                continue;
             }
-        };
-        if let Ok(_) = ok_to_delete {
-            // delete the data associated with this node
-            self.node_data.remove(&node);
+            let node_data = {
+                let label = node.attrs.get("label").unwrap_or(&node.name);
+                parse_node(label).map_err(|message| DotError::Invalid { line: node.line, message })?
+            };
+            handles.insert(node.name, dag.add_node(node_data));
         }
-        ok_to_delete
+        for edge in parsed.edges {
+            let line = edge.line;
+            // Edges can leave the input terminal & enter the output terminal, but not vice versa.
+            let misplaced = if edge.from == dot::NULL_OUTPUT {
+                Some(&edge.from)
+            } else if edge.to == dot::NULL_INPUT {
+                Some(&edge.to)
+            } else {
+                None
+            };
+            if let Some(name) = misplaced {
+                return Err(DotError::Invalid { line, message: format!("{} can't be used on that end of an edge", name) });
+            }
+            let weight = parse_edge(&edge.attrs).map_err(|message| DotError::Invalid { line, message })?;
+            let from = handles.get(&edge.from).cloned();
+            let to = handles.get(&edge.to).cloned();
+            dag.add_edge(Edge::new(from, to, weight), reachable_pred).map_err(|err| match err {
+                DagError::Cycle(cycle) => DotError::Cycle { line, cycle },
+                err => DotError::Invalid { line, message: err.to_string() },
+            })?;
+        }
+        Ok(DotImport {
+            graph: dag,
+            nodes: handles,
+        })
     }
-    /// Removes the edge (if it exists).
-    pub fn del_edge(&mut self, edge: Edge<W>) {
-        if let Some(edge_set) = self.edges.get_mut(&edge.from) {
-            edge_set.outbound.remove(&edge);
+
+    /// Write the graph in the compact binary format described in the `binary` module.
+    /// `encode_node` & `encode_weight` encode each node's data & each edge's weight.
+    /// Handles are preserved, so the graph can be read back with `read_binary`.
+    pub fn write_binary<Out, FN, FE>(&self, out: &mut Out, encode_node: FN, encode_weight: FE) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> Vec<u8>, FE: Fn(&W) -> Vec<u8>
+    {
+        let mut encoder = binary::Encoder::new(out)?;
+        encoder.varint(self.slots.len())?;
+        for &generation in self.slots.generations() {
+            encoder.varint(generation)?;
         }
-        if let Some(edge_set) = self.edges.get_mut(&edge.to) {
-            edge_set.inbound.remove(&edge);
+        let mut nodes = self.node_data.iter().collect::<Vec<_>>();
+        nodes.sort_by_key(|&(handle, _data)| handle.index);
+        encoder.varint(nodes.len() as u64)?;
+        let mut next_index = 0;
+        for &(handle, node_data) in nodes.iter() {
+            encoder.varint(handle.index - next_index)?;
+            encoder.bytes(&encode_node(node_data))?;
+            next_index = handle.index + 1;
+        }
+        let position = self.by_order.values().enumerate().map(|(position, handle)| (*handle, position as u64)).collect::<HashMap<_, _>>();
+        for (handle, _data) in nodes {
+            encoder.varint(position[handle])?;
+        }
+        let mut sources = self.edges.iter().filter(|&(_from, edges)| !edges.outbound.is_empty()).map(|(from, edges)| {
+            (binary::endpoint_code(from.map(|from| from.index)), edges)
+        }).collect::<Vec<_>>();
+        sources.sort_by_key(|&(source, _edges)| source);
+        encoder.varint(sources.len() as u64)?;
+        let mut next_source = 0;
+        for (source, edges) in sources {
+            encoder.varint(source - next_source)?;
+            next_source = source + 1;
+            let mut targets = edges.outbound.iter().map(|edge| {
+                (binary::endpoint_code(edge.to().map(|to| to.index)), edge.weight())
+            }).collect::<Vec<_>>();
+            targets.sort_by_key(|&(target, _weight)| target);
+            encoder.varint(targets.len() as u64)?;
+            let mut prev_target = 0;
+            for (target, weight) in targets {
+                encoder.varint(target - prev_target)?;
+                encoder.bytes(&encode_weight(weight))?;
+                prev_target = target;
+            }
         }
+        Ok(())
+    }
+    /// Build a graph from the binary format written by `write_binary`, as it's read from `input`
+    /// (which is best buffered).
+    /// `decode_node` & `decode_weight` decode each node's data & each edge's weight. Each edge is
+    /// added via `add_edge`, so cyclic input is rejected (as is truncated or malformed input).
+    /// The saved topological order is restored first, so that each edge which agrees with it is
+    /// added in O(1); only the others (e.g. edges that `reachable_pred` allowed to close a loop)
+    /// are searched for cycles, once the rest are in place.
+    pub fn read_binary<R, FN, FE, F>(input: R, mut decode_node: FN, mut decode_weight: FE, reachable_pred: &F) -> Result<Self, BinaryError<Edge<W>>>
+        where R: io::Read, FN: FnMut(&[u8]) -> Result<N, String>, FE: FnMut(&[u8]) -> Result<W, String>, F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        let mut decoder = binary::Decoder::new(input)?;
+        let mut dag = IODag::new();
+        let slot_count = decoder.varint()?;
+        let mut generations = vec![];
+        for _ in 0..slot_count {
+            generations.push(decoder.varint()?);
+        }
+        dag.slots = Slots::with_generations(generations);
+        let mut next_index = 0;
+        let mut handles = vec![];
+        for _ in 0..decoder.varint()? {
+            let index = binary::offset(next_index, decoder.varint()?, slot_count)?;
+            let node_data = decode_node(decoder.bytes()?).map_err(BinaryError::Invalid)?;
+            let handle = dag.slot_handle(index).unwrap();
+            dag.restore_node(handle, node_data);
+            handles.push(handle);
+            next_index = index + 1;
+        }
+        let node_count = handles.len() as u64;
+        let mut by_order = BTreeMap::new();
+        for handle in handles {
+            let position = binary::offset(0, decoder.varint()?, node_count)?;
+            if by_order.insert(position, handle).is_some() {
+                return Err(BinaryError::Invalid(format!("position {} is taken more than once", position)));
+            }
+        }
+        dag.set_order(by_order);
+        // Endpoints are numbered from 0 (null) to slot_count (one past the last slot).
+        let endpoint_limit = slot_count.saturating_add(1);
+        let endpoint = |dag: &Self, code| {
+            match binary::endpoint_index(code) {
+                None => Ok(None),
+                Some(index) => dag.slot_handle(index).filter(|handle| dag.is_live(*handle)).map(Some).ok_or_else(|| {
+                    BinaryError::Invalid(format!("slot {} is empty", index))
+                }),
+            }
+        };
+        let add_edge = |dag: &mut Self, edge| dag.add_edge(edge, reachable_pred).map_err(|err| match err {
+            DagError::Cycle(cycle) => BinaryError::Cycle(cycle),
+            err => BinaryError::Invalid(err.to_string()),
+        });
+        // Edges that go against the order, which are added last.
+        let mut unordered = vec![];
+        let mut next_source = 0;
+        for _ in 0..decoder.varint()? {
+            let source = binary::offset(next_source, decoder.varint()?, endpoint_limit)?;
+            let from = endpoint(&dag, source)?;
+            next_source = source + 1;
+            let mut target = 0;
+            for _ in 0..decoder.varint()? {
+                target = binary::offset(target, decoder.varint()?, endpoint_limit)?;
+                let to = endpoint(&dag, target)?;
+                let weight = decode_weight(decoder.bytes()?).map_err(BinaryError::Invalid)?;
+                let edge = Edge::new(from, to, weight);
+                match (from, to) {
+                    (Some(from), Some(to)) if dag.order[&from] >= dag.order[&to] => unordered.push(edge),
+                    _ => add_edge(&mut dag, edge)?,
+                }
+            }
+        }
+        for edge in unordered {
+            add_edge(&mut dag, edge)?;
+        }
+        Ok(dag)
     }
 
+    /// Returns true if `search` is reachable from `base` (see `search_path`), i.e. if `base` leads
+    /// back around to `search` & `search` is reachable from the last edge.
+    fn find_edge_path<'a, F>(&'a self, search: &Edge<W>, base: &Edge<W>, reachable_pred: &F, path: &mut Vec<Edge<W>>, visited: &mut HashSet<&'a Edge<W>>) -> bool
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        // do we have (base -> [Node] -> search) and Node passes the connection?
+        let arrived = |edge: &Edge<W>| edge.to() == search.from() && reachable_pred(edge, search);
+        self.search_path(&arrived, base, reachable_pred, path, visited)
+    }
     /// F(edge_in, edge_out) should return true if and only if edge_out would be reachable from
     /// edge_in, where edge_in.to() == edge_out.from().
     /// Note that edge_out might not actually exist IN the DAG yet (as it could be a proposed new
     /// edge).
     /// F is only relevant if not every edge exiting a node is reachable from all edges entering it
-    fn is_reachable<F>(&self, search: &Edge<W>, base: &Edge<W>, reachable_pred: &F) -> bool
-        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    ///
+    /// Returns true if the target is reachable from `base`, where `arrived(edge)` tells whether
+    /// `edge` (reached from `base`, or `base` itself) leads straight to the target. `path` holds the
+    /// edges traversed after `base`; on success it's left holding every edge up to the one that
+    /// arrived.
+    /// `visited` holds the edges already searched from, so that each is searched at most once
+    /// (this also guarantees termination when the graph is cyclic, which can be the case while
+    /// applying a batch).
+    /// The search keeps its own stack, so that arbitrarily long paths can be followed.
+    fn search_path<'a, F, T>(&'a self, arrived: &T, base: &Edge<W>, reachable_pred: &F, path: &mut Vec<Edge<W>>, visited: &mut HashSet<&'a Edge<W>>) -> bool
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool, T: Fn(&Edge<W>) -> bool
     {
         // if the base is an output, no edges are reachable.
-        base.to().is_some() && (
-            // do we have (base -> [Node] -> search) and Node passes the connection?
-            (base.to() == search.from() && reachable_pred(base, search)) ||
-            // else, recurse for all reachable nodes.
-            self.edges[base.to()].outbound.iter()
-                // only consider the edges leaving base.to() that are reachable from base.
-                .filter(|edge| edge.to().is_some() && reachable_pred(base, edge))
-                .any(|edge| {
-                    self.is_reachable(search, edge, reachable_pred)
-                })
-            )
+        if base.to().is_none() {
+            return false;
+        }
+        // does base lead straight to the target?
+        if arrived(base) {
+            return true;
+        }
+        // Each entry holds an edge on the current path, along with the edges leaving its
+        // destination that are yet to be tried.
+        let mut stack = vec![(base, self.edges[base.to()].outbound.iter())];
+        while let Some(&mut (prev, ref mut edges)) = stack.last_mut() {
+            match edges.next() {
+                // only consider the edges leaving prev.to() that are reachable from prev.
+                Some(edge) => {
+                    if edge.to().is_some() && reachable_pred(prev, edge) && visited.insert(edge) {
+                        path.push(edge.clone());
+                        if arrived(edge) {
+                            return true;
+                        }
+                        stack.push((edge, self.edges[edge.to()].outbound.iter()));
+                    }
+                },
+                None => {
+                    // Every edge after this one is a dead end, so back up.
+                    stack.pop();
+                    if !stack.is_empty() {
+                        path.pop();
+                    }
+                }
+            }
+        }
+        false
     }
     /// Iterate edge by edge starting from edges from null.
     /// For each edge, call `pred`. If `pred(edge)` returns true,
@@ -172,24 +595,395 @@ impl<N, W> IODag<N, W>
             }
         }
     }
+    /// Continue `traverse` past `edge`, keeping an explicit stack so that arbitrarily long paths
+    /// can be followed.
+    /// Edges to null are outputs, so nothing is reachable from them (null's outbound edges are
+    /// the inputs).
     fn traverse_from<F>(&self, edge: &Edge<W>, pred: &mut F)
         where F: FnMut(&Edge<W>) -> bool
     {
-        for new_edge in self.edges[edge.to()].outbound.iter() {
-            if pred(new_edge) {
-                self.traverse_from(new_edge, pred);
+        if edge.to().is_none() {
+            return;
+        }
+        // Each entry holds the edges leaving a node on the current path that are yet to be visited.
+        let mut stack = vec![self.edges[edge.to()].outbound.iter()];
+        while let Some(edges) = stack.last_mut() {
+            match edges.next() {
+                Some(new_edge) => {
+                    if pred(new_edge) && new_edge.to().is_some() {
+                        stack.push(self.edges[new_edge.to()].outbound.iter());
+                    }
+                },
+                None => {
+                    stack.pop();
+                }
             }
         }
     }
+    /// Write the graph in Graphviz DOT format.
+    /// `node_label` & `edge_label` format the labels of each node & edge (e.g. from the node's
+    /// data & the edge's weight). The edges in `highlight` (e.g. the cycle returned by a rejected
+    /// insertion) are drawn highlighted; any that aren't in the graph (such as the rejected edge
+    /// itself) are also dashed.
+    /// Edges from null are drawn from the `dot::NULL_INPUT` terminal, and edges to null are drawn
+    /// to the `dot::NULL_OUTPUT` terminal.
+    pub fn write_dot<Out, FN, FE>(&self, out: &mut Out, node_label: FN, edge_label: FE, highlight: &[Edge<W>]) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> String, FE: Fn(&Edge<W>) -> String
+    {
+        let highlighted = highlight.iter().collect::<HashSet<_>>();
+        let nodes = self.node_data.iter().map(|(handle, node_data)| (handle.index, node_label(node_data))).collect();
+        let dot_edge = |edge: &Edge<W>, style| DotEdge {
+            from: edge.from().map(|from| from.index),
+            to: edge.to().map(|to| to.index),
+            label: edge_label(edge),
+            style,
+        };
+        let mut edges = self.iter_edges().map(|edge| {
+            dot_edge(edge, EdgeStyle::present(highlighted.contains(edge)))
+        }).collect::<Vec<_>>();
+        // Highlighted edges that aren't in the graph are drawn too, so long as their endpoints are.
+        edges.extend(highlight.iter().filter(|edge| {
+            self.edges.contains_key(edge.from()) && self.edges.contains_key(edge.to()) &&
+                !self.edges[edge.from()].outbound.contains(edge)
+        }).map(|edge| dot_edge(edge, EdgeStyle::Absent)));
+        dot::write_graph(out, nodes, edges)
+    }
+}
+
+/// Exposes the node -> node edges through the generic interface.
+/// Every edge entering a node is treated as reaching every edge leaving it (i.e. cycles are
+/// checked as if `reachable_pred` always returns true).
+/// Edges to/from null aren't created through this interface, but they're included when
+/// iterating a node's children/parents and in the edge count.
+impl<N, W> OnDag<N, W> for IODag<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    type NodeHandle = NodeHandle;
+    type Edge = Edge<W>;
+    fn add_node(&mut self, node_data: N) -> Self::NodeHandle {
+        IODag::add_node(self, node_data)
+    }
+    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: W) -> Result<(), DagError<Self::Edge>> {
+        IODag::add_edge(self, Edge::new(Some(*from), Some(*to), data), &|_, _| true)
+    }
+    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: W) -> Result<(), DagError<Self::Edge>> {
+        self.del_edge(Edge::new(Some(*from), Some(*to), data))
+    }
+    fn iter_topo(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        // just a depth-first sort, but then reverse the results.
+        let mut ordered = vec![];
+        self.depth_first_sort(*from, &mut ordered, &mut HashSet::new());
+        ordered.into_iter().rev()
+    }
+    fn iter_topo_rev(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        let mut ordered = vec![];
+        self.depth_first_sort(*from, &mut ordered, &mut HashSet::new());
+        ordered.into_iter()
+    }
+    fn children(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        self.iter_outbound_edges(Some(*node)).cloned()
+    }
+    fn parents(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        self.iter_inbound_edges(Some(*node)).cloned()
+    }
+    fn contains(&self, node: &Self::NodeHandle) -> bool {
+        self.node_data.contains_key(node)
+    }
+    fn node_count(&self) -> usize {
+        self.node_data.len()
+    }
+    fn edge_count(&self) -> usize {
+        self.iter_edges().count()
+    }
+}
+
+impl<N, W> IODag<N, W>
+    where W: Hash + Eq + PartialEq {
+    /// Insert the edge into the edge sets of its endpoints, leaving the order untouched.
+    /// Returns false if the edge was already present.
+    fn insert_raw(&mut self, edge: Edge<W>) -> bool
+        where W: Clone
+    {
+        let is_new = self.edges.get_mut(&edge.from).unwrap().outbound.insert(edge.clone());
+        self.edges.get_mut(&edge.to).unwrap().inbound.insert(edge);
+        is_new
+    }
+    /// Remove the edge from the edge sets of its endpoints, leaving the order untouched.
+    /// Returns false if the edge wasn't present.
+    fn remove_raw(&mut self, edge: &Edge<W>) -> bool {
+        let was_present = match self.edges.get_mut(&edge.from) {
+            Some(edge_set) => edge_set.outbound.remove(edge),
+            None => false,
+        };
+        if was_present {
+            // The graph is consistent, so the inbound half must exist too.
+            self.edges.get_mut(&edge.to).unwrap().inbound.remove(edge);
+        }
+        was_present
+    }
+    /// True if `edge` is a node -> node edge that the order accounts for.
+    fn is_ordered(&self, edge: &Edge<W>) -> bool {
+        edge.from().is_some() && edge.to().is_some() &&
+            (self.feedback.is_empty() || !self.feedback.contains(edge))
+    }
+    /// Update the order to account for `edge`, which is about to be inserted.
+    /// If that's impossible because the edge closes a loop between nodes, it's recorded as a
+    /// feedback edge instead.
+    fn order_edge(&mut self, edge: &Edge<W>)
+        where W: Clone
+    {
+        let (from, to) = match (*edge.from(), *edge.to()) {
+            (Some(from), Some(to)) => (from, to),
+            // Null isn't part of the order.
+            _ => return,
+        };
+        let (from_label, to_label) = (self.order[&from], self.order[&to]);
+        if from_label < to_label {
+            // Already consistent.
+            return;
+        }
+        match self.forward_region(to, from_label, from) {
+            // `to` can't precede `from`.
+            None => { self.feedback.insert(edge.clone()); },
+            Some(forward) => {
+                let backward = self.backward_region(from, to_label);
+                self.reorder(backward, forward);
+            }
+        }
+    }
+    /// Collect the nodes reachable from `start` via ordered edges, without passing through any
+    /// node whose label exceeds `bound`.
+    /// Returns None if `target` is reachable in this way.
+    fn forward_region(&self, start: NodeHandle, bound: u64, target: NodeHandle) -> Option<Vec<NodeHandle>> {
+        let mut region = vec![start];
+        let mut visited = HashSet::new();
+        visited.insert(start);
+        let mut index = 0;
+        while index < region.len() {
+            let node = region[index];
+            if node == target {
+                return None;
+            }
+            for edge in self.edges[&Some(node)].outbound.iter().filter(|edge| self.is_ordered(edge)) {
+                let next = edge.to().unwrap();
+                if self.order[&next] <= bound && visited.insert(next) {
+                    region.push(next);
+                }
+            }
+            index += 1;
+        }
+        Some(region)
+    }
+    /// Collect the nodes that reach `start` via ordered edges, without passing through any node
+    /// whose label is below `bound`.
+    fn backward_region(&self, start: NodeHandle, bound: u64) -> Vec<NodeHandle> {
+        let mut region = vec![start];
+        let mut visited = HashSet::new();
+        visited.insert(start);
+        let mut index = 0;
+        while index < region.len() {
+            let node = region[index];
+            for edge in self.edges[&Some(node)].inbound.iter().filter(|edge| self.is_ordered(edge)) {
+                let prev = edge.from().unwrap();
+                if self.order[&prev] > bound && visited.insert(prev) {
+                    region.push(prev);
+                }
+            }
+            index += 1;
+        }
+        region
+    }
+    /// Given the regions found while inserting an edge `from -> to`, where `backward` reaches
+    /// `from` and `forward` is reachable from `to`, shuffle the labels they occupy so that all of
+    /// `backward` precedes all of `forward`. Relative order within each region is preserved.
+    fn reorder(&mut self, mut backward: Vec<NodeHandle>, mut forward: Vec<NodeHandle>) {
+        backward.sort_by_key(|node| self.order[node]);
+        forward.sort_by_key(|node| self.order[node]);
+        let mut labels = backward.iter().chain(forward.iter()).map(|node| self.order[node]).collect::<Vec<_>>();
+        labels.sort();
+        for (node, label) in backward.into_iter().chain(forward).zip(labels) {
+            self.order.insert(node, label);
+            self.by_order.insert(label, node);
+        }
+    }
+    /// Push every node reachable from `node` (via node -> node edges) onto `ordered`, deepest
+    /// first.
+    /// The search keeps its own stack, so that arbitrarily deep graphs can be sorted.
+    fn depth_first_sort(&self, node: NodeHandle, ordered: &mut Vec<NodeHandle>, marked: &mut HashSet<NodeHandle>) {
+        if !marked.insert(node) {
+            return;
+        }
+        // Each entry holds a node, along with the edges leaving it that are yet to be followed.
+        let mut stack = vec![(node, self.edges[&Some(node)].outbound.iter())];
+        while let Some(&mut (_, ref mut edges)) = stack.last_mut() {
+            match edges.next() {
+                Some(edge) => {
+                    if let Some(to) = *edge.to() {
+                        if marked.insert(to) {
+                            stack.push((to, self.edges[&Some(to)].outbound.iter()));
+                        }
+                    }
+                },
+                None => {
+                    // Everything beneath the node has been pushed, so it can follow.
+                    let (node, _) = stack.pop().unwrap();
+                    ordered.push(node);
+                }
+            }
+        }
+    }
+    /// Remove the node, returning its data (or None if it was already deleted).
+    /// Errors if the node has incoming or outgoing edges.
+    fn take_node(&mut self, node: NodeHandle) -> Result<Option<N>, DagError<Edge<W>>> {
+        match self.edges.entry(Some(node)) {
+            // Already deleted
+            hash_map::Entry::Vacant(_) => return Ok(None),
+            hash_map::Entry::Occupied(entry) => {
+                if entry.get().is_empty() {
+                    entry.remove();
+                } else {
+                    // Node has edges
+                    return Err(DagError::NodeHasEdges);
+                }
+            }
+        }
+        // delete the data associated with this node
+        self.slots.release(node.index);
+        let label = self.order.remove(&node).unwrap();
+        self.by_order.remove(&label);
+        Ok(self.node_data.remove(&node))
+    }
+    /// Re-create a node that was removed by `take_node`, under its original handle.
+    fn restore_node(&mut self, handle: NodeHandle, node_data: N) {
+        // Panic if the NodeHandle (or its slot) is somehow in use.
+        self.slots.claim(handle.index, handle.generation);
+        assert!(self.edges.insert(Some(handle), EdgeSet::new()).is_none());
+        assert!(self.node_data.insert(handle, node_data).is_none());
+        // Like a new node, it has no edges yet.
+        self.order.insert(handle, self.next_order);
+        self.by_order.insert(self.next_order, handle);
+        self.next_order += 1;
+    }
+    /// Replace the order with the given one (label -> node), which must hold every node. The
+    /// graph must have no node -> node edges (or the feedback edges must be recomputed).
+    fn set_order(&mut self, by_order: BTreeMap<u64, NodeHandle>) {
+        debug_assert!(by_order.len() == self.node_data.len());
+        self.order = by_order.iter().map(|(label, handle)| (*handle, *label)).collect();
+        self.next_order = by_order.keys().next_back().map_or(0, |label| label + 1);
+        self.by_order = by_order;
+    }
+    /// Insert `edges` into a graph that has none yet (e.g. one being rebuilt), without checking
+    /// for cycles. Rather than ordering the graph edge by edge, which is quadratic when the edges
+    /// are inserted against the order, the order is rebuilt once they're all in place. Edges that
+    /// it can't agree with (loops that a `reachable_pred` allowed) become feedback edges.
+    pub(super) fn restore_edges(&mut self, edges: Vec<Edge<W>>)
+        where W: Clone
+    {
+        debug_assert!(self.edges.values().all(EdgeSet::is_empty));
+        for edge in edges {
+            self.insert_raw(edge);
+        }
+        let mut ordered = vec![];
+        let mut marked = HashSet::new();
+        // Sorting from the last node to the first keeps unrelated nodes in their current order.
+        let nodes = self.by_order.values().rev().cloned().collect::<Vec<_>>();
+        for node in nodes {
+            self.depth_first_sort(node, &mut ordered, &mut marked);
+        }
+        // Each node follows everything reachable from it, so reverse that.
+        let by_order = ordered.into_iter().rev().enumerate().map(|(label, node)| (label as u64, node)).collect();
+        self.set_order(by_order);
+        self.feedback = self.iter_edges().filter(|edge| match (*edge.from(), *edge.to()) {
+            (Some(from), Some(to)) => self.order[&from] >= self.order[&to],
+            _ => false,
+        }).cloned().collect();
+    }
+    /// The handle that a node in the given slot would currently have.
+    fn slot_handle(&self, index: u64) -> Option<NodeHandle> {
+        self.slots.generation(index).map(|generation| NodeHandle {
+            index,
+            generation,
+        })
+    }
+    /// Start the slots afresh with the given generations (e.g. before restoring the nodes of a
+    /// saved graph). The graph must be empty.
+    pub(super) fn set_slot_generations(&mut self, generations: Vec<u64>) {
+        debug_assert!(self.node_data.is_empty());
+        self.slots = Slots::with_generations(generations);
+    }
+}
+
+/// A single modification of an `IODag`, as recorded by `Transaction` & `IODagHistory`.
+/// Applying one (via `IODag::apply_op`) yields the op that undoes it.
+pub(super) enum EditOp<N, W>
+    where W: Hash + Eq + PartialEq {
+    /// (Re)create the node under the given handle.
+    AddNode(NodeHandle, N),
+    DelNode(NodeHandle),
+    AddEdge(Edge<W>),
+    DelEdge(Edge<W>),
+}
+
+impl<N, W> IODag<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    /// Apply `op` WITHOUT checking whether it creates a cycle.
+    /// Returns the op that reverts it, or None if it didn't modify the graph (e.g. the edge was
+    /// already present).
+    pub(super) fn apply_op(&mut self, op: EditOp<N, W>) -> Result<Option<EditOp<N, W>>, DagError<Edge<W>>> {
+        match op {
+            EditOp::AddNode(handle, node_data) => {
+                self.restore_node(handle, node_data);
+                Ok(Some(EditOp::DelNode(handle)))
+            },
+            EditOp::DelNode(handle) => {
+                Ok(self.take_node(handle)?.map(|node_data| EditOp::AddNode(handle, node_data)))
+            },
+            EditOp::AddEdge(edge) => {
+                if !self.edges.contains_key(edge.from()) || !self.edges.contains_key(edge.to()) {
+                    Err(DagError::NoSuchNode)
+                } else if self.edges[edge.from()].outbound.contains(&edge) {
+                    Ok(None)
+                } else {
+                    self.add_edge_unchecked(edge.clone());
+                    Ok(Some(EditOp::DelEdge(edge)))
+                }
+            },
+            EditOp::DelEdge(edge) => {
+                self.del_edge(edge.clone())?;
+                Ok(Some(EditOp::AddEdge(edge)))
+            },
+        }
+    }
+    /// Check that `apply_op` would succeed, and that any edge it adds wouldn't create a cycle.
+    pub(super) fn can_apply_op<F>(&self, op: &EditOp<N, W>, reachable_pred: &F) -> Result<(), DagError<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        match *op {
+            EditOp::AddNode(..) => Ok(()),
+            EditOp::DelNode(handle) => match self.edges.get(&Some(handle)) {
+                Some(edge_set) if !edge_set.is_empty() => Err(DagError::NodeHasEdges),
+                _ => Ok(()),
+            },
+            EditOp::AddEdge(ref edge) => self.can_add_edge(edge, reachable_pred),
+            EditOp::DelEdge(ref edge) => match self.edges.get(edge.from()) {
+                Some(edge_set) if edge_set.outbound.contains(edge) => Ok(()),
+                _ => Err(DagError::NoSuchEdge),
+            },
+        }
+    }
+    /// Begin a transaction. Modifications made through the returned guard are undone when it's
+    /// dropped or rolled back, unless it's committed first.
+    pub fn transaction(&mut self) -> Transaction<'_, N, W> {
+        Transaction::new(self)
+    }
 }
 
 impl<W> Edge<W>
     where W: Hash + Eq + PartialEq {
     pub fn new(from: Option<NodeHandle>, to: Option<NodeHandle>, weight: W) -> Self {
         Edge {
-            from: from,
-            to: to,
-            weight: weight,
+            from,
+            to,
+            weight,
         }
     }
     pub fn from(&self) -> &Option<NodeHandle> {
@@ -203,15 +997,176 @@ impl<W> Edge<W>
     }
 }
 
+impl NodeHandle {
+    /// The handle of the node with the given index, at generation 0 (for graphs that allocate
+    /// their own indices, without reusing them).
+    pub(super) fn from_index(index: u64) -> Self {
+        NodeHandle {
+            index,
+            generation: 0,
+        }
+    }
+    pub(super) fn index(&self) -> u64 {
+        self.index
+    }
+}
+
 impl<W> EdgeSet<W>
     where W: Hash + Eq + PartialEq {
-    fn new() -> Self {
+    pub(super) fn new() -> Self {
         EdgeSet {
             outbound: HashSet::new(),
             inbound: HashSet::new(),
         }
     }
-    fn is_empty(&self) -> bool {
-        self.outbound.is_empty()
+    pub(super) fn is_empty(&self) -> bool {
+        self.outbound.is_empty() && self.inbound.is_empty()
+    }
+}
+
+/// Writes the nodes (with their handles), every edge & the generation of each slot.
+#[cfg(feature = "serde")]
+impl<N, W> Serialize for IODag<N, W>
+    where N: Serialize, W: Hash + Eq + PartialEq + Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut nodes = self.node_data.iter().collect::<Vec<_>>();
+        nodes.sort_by_key(|&(handle, _data)| handle.index);
+        let mut edges = self.edges.iter().collect::<Vec<_>>();
+        edges.sort_by_key(|&(handle, _edges)| handle.map(|handle| handle.index));
+        let mut state = serializer.serialize_struct("IODag", 3)?;
+        state.serialize_field("slots", self.slots.generations())?;
+        state.serialize_field("nodes", &nodes)?;
+        state.serialize_field("edges", &edges)?;
+        state.end()
+    }
+}
+
+/// Rejects input that doesn't describe a valid graph, including graphs with cycles (checked as
+/// if `reachable_pred` always returns true; see `IODag::deserialize_with`).
+#[cfg(feature = "serde")]
+impl<'de, N, W> Deserialize<'de> for IODag<N, W>
+    where N: Deserialize<'de>, W: Clone + Hash + Eq + PartialEq + Deserialize<'de> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        IODag::deserialize_with(deserializer, &|_, _| true)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<N, W> IODag<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    /// Deserialize a graph, checking for cycles using `reachable_pred` (see `add_edge`).
+    pub fn deserialize_with<'de, D, F>(deserializer: D, reachable_pred: &F) -> Result<Self, D::Error>
+        where D: Deserializer<'de>, N: Deserialize<'de>, W: Deserialize<'de>, F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        let repr = IODagRepr::deserialize(deserializer)?;
+        IODag::from_repr(repr, reachable_pred).map_err(D::Error::custom)
+    }
+    fn from_repr<F>(repr: IODagRepr<N, W>, reachable_pred: &F) -> Result<Self, String>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        let IODagRepr { slots, mut nodes, edges } = repr;
+        let mut dag = IODag::new();
+        dag.slots = Slots::with_generations(slots);
+        // Recreate the nodes in order of slot, so that the order is deterministic.
+        nodes.sort_by_key(|&(handle, _)| handle.index);
+        for (handle, node_data) in nodes {
+            if dag.slot_handle(handle.index) != Some(handle) {
+                return Err(format!("{:?} doesn't match the generation of its slot", handle));
+            }
+            if dag.node_data.contains_key(&handle) {
+                return Err(format!("{:?} appears more than once", handle));
+            }
+            dag.restore_node(handle, node_data);
+        }
+
+        let edge_sets = {
+            let mut edge_sets = HashMap::new();
+            for (handle, edge_set) in edges {
+                if !dag.edges.contains_key(&handle) {
+                    return Err(format!("edges are listed for {:?}, which doesn't exist", handle));
+                }
+                if edge_sets.insert(handle, edge_set).is_some() {
+                    return Err(format!("edges are listed for {:?} more than once", handle));
+                }
+            }
+            edge_sets
+        };
+        if edge_sets.len() != dag.edges.len() {
+            return Err("edges aren't listed for every node (and null)".to_string());
+        }
+        // Every edge must be filed under its endpoints, and nowhere else.
+        for (handle, edge_set) in edge_sets.iter() {
+            for edge in edge_set.outbound.iter() {
+                if edge.from() != handle || !edge_sets.get(edge.to()).is_some_and(|to| to.inbound.contains(edge)) {
+                    return Err(format!("outbound edges of {:?} are inconsistent", handle));
+                }
+            }
+            for edge in edge_set.inbound.iter() {
+                if edge.to() != handle || !edge_sets.get(edge.from()).is_some_and(|from| from.outbound.contains(edge)) {
+                    return Err(format!("inbound edges of {:?} are inconsistent", handle));
+                }
+            }
+        }
+        for edge in edge_sets.into_values().flat_map(|edge_set| edge_set.outbound) {
+            dag.add_edge(edge, reachable_pred).map_err(|err| format!("invalid edge: {}", err))?;
+        }
+        Ok(dag)
+    }
+}
+
+#[cfg(feature = "graphml")]
+impl<N, W> IODag<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    /// Write the graph in GraphML format.
+    /// `node_attrs` & `edge_attrs` convert each node's data & each edge's weight into attributes
+    /// (name -> value). Nodes are identified by their handles, so reading the file back with
+    /// `read_graphml` gives the same handles.
+    pub fn write_graphml<Out, FN, FE>(&self, out: &mut Out, node_attrs: FN, edge_attrs: FE) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> HashMap<String, String>, FE: Fn(&W) -> HashMap<String, String>
+    {
+        let nodes = self.node_data.iter().map(|(handle, node_data)| (handle.index, node_attrs(node_data))).collect();
+        let edges = self.iter_edges().map(|edge| GraphmlEdge {
+            from: edge.from().map(|from| from.index),
+            to: edge.to().map(|to| to.index),
+            attrs: edge_attrs(edge.weight()),
+        }).collect();
+        graphml::write_graph(out, nodes, edges, self.slots.generations())
+    }
+    /// Build a graph from a GraphML file.
+    /// Each node's data is parsed from its attributes by `parse_node`, and each edge's weight is
+    /// parsed from its attributes by `parse_edge`. Nodes written by `write_graphml` keep their
+    /// handles; any others are given new ones. Each edge is added via `add_edge`; if one would
+    /// close a cycle, the error reports its line.
+    pub fn read_graphml<R, FN, FE, F>(input: R, mut parse_node: FN, mut parse_edge: FE, reachable_pred: &F) -> Result<GraphmlImport<Self, NodeHandle>, GraphmlError<Edge<W>>>
+        where R: io::Read, FN: FnMut(&HashMap<String, String>) -> Result<N, String>, FE: FnMut(&HashMap<String, String>) -> Result<W, String>, F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        let parsed = graphml::parse(input)?;
+        let mut dag = IODag::new();
+        dag.slots = Slots::with_generations(parsed.generations);
+        let mut handles = HashMap::new();
+        for (node, index) in parsed.nodes.into_iter().zip(parsed.indices) {
+            let node_data = parse_node(&node.attrs).map_err(|message| GraphmlError::Invalid { line: node.line, message })?;
+            let handle = dag.slot_handle(index).unwrap();
+            dag.restore_node(handle, node_data);
+            handles.insert(node.id, handle);
+        }
+        for edge in parsed.edges {
+            let line = edge.line;
+            let weight = parse_edge(&edge.attrs).map_err(|message| GraphmlError::Invalid { line, message })?;
+            let from = handles.get(&edge.source).cloned();
+            let to = handles.get(&edge.target).cloned();
+            dag.add_edge(Edge::new(from, to, weight), reachable_pred).map_err(|err| match err {
+                DagError::Cycle(cycle) => GraphmlError::Cycle { line, cycle },
+                err => GraphmlError::Invalid { line, message: err.to_string() },
+            })?;
+        }
+        Ok(GraphmlImport {
+            graph: dag,
+            nodes: handles,
+        })
     }
 }
@@ -1,150 +1,351 @@
-/// IODagFull is DAG where every modification is enforced.
-/// However, edges are allowed to have one end at null.
-/// There are InEdges, in which the `from` component is null,
-/// and OutEdges, in which the `to` component is null.
-/// (Plus, MidEdge, which has no null components).
-/// Each Edge type allows a different type of weight.
-///
+//! IODagFull is DAG where every modification is enforced.
+//! However, edges are allowed to have one end at null.
+//! There are InEdges, in which the `from` component is null,
+//! and OutEdges, in which the `to` component is null.
+//! (Plus, MidEdge, which has no null components).
+//! Each Edge type allows a different type of weight.
+//!
 
 use std::collections::{HashMap, HashSet};
+use std::collections::hash_map;
 use std::hash::Hash;
+use std::io::{self, Write};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::de::Error;
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+
+use super::dot::{self, DotEdge, EdgeStyle};
+use super::error::DagError;
+#[cfg(feature = "graphml")]
+use super::graphml::{self, GraphmlEdge, GraphmlError, GraphmlImport};
+use super::ondag::OnDag;
+use super::slots::Slots;
 
 /// W=Weight
 pub struct IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
     where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq, ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
-    /// To create unique NodeHandles, we just assign them unique u64's from this counter.
-    node_counter: u64,
-    /// Node states, including their outgoing edges.
+    /// Allocates the slot & generation of each NodeHandle.
+    slots: Slots,
+    /// Node states, including their outgoing and incoming edges.
     node_data: HashMap<NodeHandle, NodeData<N, FromNodeW, FromNullW, ToNodeW, ToNullW>>,
     /// Edges that start at null.
     edges_from_null: HashSet<Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>,
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeData<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
     where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq, ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
     /// userdata associated with this node
     data: N,
     /// Edges leaving this node
     outbound: HashSet<Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>,
+    /// Edges entering this node
+    inbound: HashSet<Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>,
 }
 
 // Edges consist of two parts: the `from` and the `to`.
 // Define this structure in a way that creates 4 types of edges.
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FromNull<W> {
     weight: W,
 }
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FromNode<W> {
     node: NodeHandle,
     weight: W,
 }
 
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ToNull<W> {
     weight: W,
 }
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ToNode<W> {
     node: NodeHandle,
     weight: W,
 }
 
 
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum EdgeFrom<FromNodeW, FromNullW>
     where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq {
     Null(FromNull<FromNullW>),
     Node(FromNode<FromNodeW>),
 }
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum EdgeTo<ToNodeW, ToNullW>
     where ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
     Null(ToNull<ToNullW>),
     Node(ToNode<ToNodeW>),
 }
 
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>
     where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq, ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
     from: EdgeFrom<FromNodeW, FromNullW>,
     to: EdgeTo<ToNodeW, ToNullW>,
 }
 
-#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeHandle {
+    /// Slot of the node. The slots of deleted nodes are reused.
     index: u64,
+    /// Tells apart the nodes that have occupied the slot (see `iodag::NodeHandle`).
+    generation: u64,
+}
+
+/// A node, as serialized.
+#[cfg(feature = "serde")]
+type NodeEntry<N, FromNodeW, FromNullW, ToNodeW, ToNullW> = (NodeHandle, NodeData<N, FromNodeW, FromNullW, ToNodeW, ToNullW>);
+
+/// The serialized form of an IODagFull.
+/// The edges are written out as they're stored, and checked for consistency when read back.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct IODagFullRepr<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq, ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
+    /// Generation of each slot.
+    slots: Vec<u64>,
+    nodes: Vec<NodeEntry<N, FromNodeW, FromNullW, ToNodeW, ToNullW>>,
+    edges_from_null: HashSet<Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>,
+}
+
+impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> Default for IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq, ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
     where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq, ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
     pub fn new() -> Self {
         IODagFull {
-            node_counter: 0,
+            slots: Slots::new(),
             node_data : HashMap::new(),
             edges_from_null: HashSet::new(),
         }
     }
     pub fn add_node(&mut self, node_data: N) -> NodeHandle {
+        let (index, generation) = self.slots.allocate();
         let handle = NodeHandle {
-            index: self.node_counter,
+            index,
+            generation,
         };
-        self.node_counter = self.node_counter+1;
         // Create storage for the node's outgoing edges
         // Panic if the NodeHandle was somehow already in use.
         assert!(self.node_data.insert(handle, NodeData::new(node_data)).is_none());
         handle
     }
-    pub fn add_edge(&mut self, edge: Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>) -> Result<(), ()> {
-        let from_handle = edge.from_handle();
-        let to_handle = edge.to_handle();
-        let safe_to_add = match from_handle {
-            // Edges from Null cannot cycle
-            None => true,
-            Some(from) => match to_handle {
-                // Edges to Null cannot cycle
-                None => true,
-                // if we can reach 'from' via 'to', then connecting from -> to creates cycle.
-                Some(to) => !self.is_reachable(from, to),
+    /// Panics if the node doesn't exist (see `try_node_data`).
+    pub fn node_data(&self, node: NodeHandle) -> &N {
+        &self.node_data[&node].data
+    }
+    /// Return the node's data, or None if the handle is stale (i.e. the node was deleted).
+    pub fn try_node_data(&self, node: NodeHandle) -> Option<&N> {
+        self.node_data.get(&node).map(|node| &node.data)
+    }
+    /// True unless the node was deleted (even if its slot has been reused since).
+    pub fn is_live(&self, node: NodeHandle) -> bool {
+        self.node_data.contains_key(&node)
+    }
+    /// Removes the node (if it exists)
+    /// Errors if the node has incoming or outgoing edges.
+    pub fn del_node(&mut self, node: NodeHandle) -> Result<(), DagError<Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>> {
+        match self.node_data.entry(node) {
+            // Already deleted
+            hash_map::Entry::Vacant(_) => Ok(()),
+            hash_map::Entry::Occupied(entry) => {
+                if entry.get().outbound.is_empty() && entry.get().inbound.is_empty() {
+                    entry.remove();
+                    self.slots.release(node.index);
+                    Ok(())
+                } else {
+                    // Node has edges
+                    Err(DagError::NodeHasEdges)
+                }
             }
-        };
-
-        if safe_to_add {
-            match from_handle {
-                None => self.edges_from_null.insert(edge),
-                Some(from) => self.node_data.get_mut(&from).unwrap().outbound.insert(edge),
-            };
-            Ok(())
-        } else {
-            Err(())
         }
     }
-    /// Removes the edge (if it exists).
-    /// Returns true if the edge was previously present
-    pub fn del_edge(&mut self, edge: Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>) -> bool {
+    /// Removes the edge.
+    /// Errors if the edge isn't present.
+    pub fn del_edge(&mut self, edge: Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>) -> Result<(), DagError<Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>> {
         let from_handle = edge.from_handle();
-        match from_handle {
+        let was_present = match from_handle {
             None => self.edges_from_null.remove(&edge),
             Some(from) => match self.node_data.get_mut(&from) {
                 // The 'from' portion of the node isn't in this Dag.
                 None => false,
                 Some(node_data) => node_data.outbound.remove(&edge),
             },
+        };
+        if was_present {
+            if let Some(to) = edge.to_handle() {
+                // The graph is consistent, so the inbound half must exist too.
+                self.node_data.get_mut(&to).unwrap().inbound.remove(&edge);
+            }
+            Ok(())
+        } else {
+            Err(DagError::NoSuchEdge)
         }
     }
 
-    /// Return true if and only if `search` is reachable from (or is equal to) `base`
-    fn is_reachable(&self, search: NodeHandle, base: NodeHandle) -> bool {
-        (base == search) || self.node_data[&base].outbound.iter().any(|edge| {
-            match edge.to_handle() {
-                // Edge to Null
-                None => false,
-                Some(node_handle) => self.is_reachable(search, node_handle),
+    /// Write the graph in Graphviz DOT format.
+    /// `node_label` & `edge_label` format the labels of each node & edge (e.g. from the node's
+    /// data & the edge's weights). The edges in `highlight` (e.g. the cycle returned by a rejected
+    /// insertion) are drawn highlighted; any that aren't in the graph (such as the rejected edge
+    /// itself) are also dashed.
+    /// Edges from null are drawn from the `dot::NULL_INPUT` terminal, and edges to null are drawn
+    /// to the `dot::NULL_OUTPUT` terminal.
+    pub fn write_dot<Out, FN, FE>(&self, out: &mut Out, node_label: FN, edge_label: FE, highlight: &[Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>]) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> String, FE: Fn(&Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>) -> String
+    {
+        let highlighted = highlight.iter().collect::<HashSet<_>>();
+        let nodes = self.node_data.iter().map(|(handle, node)| (handle.index, node_label(&node.data))).collect();
+        let dot_edge = |edge: &Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>, style| DotEdge {
+            from: edge.from_handle().map(|from| from.index),
+            to: edge.to_handle().map(|to| to.index),
+            label: edge_label(edge),
+            style,
+        };
+        let node_edges = self.node_data.values().flat_map(|node| node.outbound.iter());
+        let mut edges = self.edges_from_null.iter().chain(node_edges).map(|edge| {
+            dot_edge(edge, EdgeStyle::present(highlighted.contains(edge)))
+        }).collect::<Vec<_>>();
+        // Highlighted edges that aren't in the graph are drawn too, so long as their endpoints are.
+        let has_endpoint = |handle: Option<NodeHandle>| handle.is_none_or(|handle| self.node_data.contains_key(&handle));
+        edges.extend(highlight.iter().filter(|edge| {
+            has_endpoint(edge.from_handle()) && has_endpoint(edge.to_handle()) && !match edge.from_handle() {
+                None => self.edges_from_null.contains(edge),
+                Some(from) => self.node_data[&from].outbound.contains(edge),
             }
-        })
+        }).map(|edge| dot_edge(edge, EdgeStyle::Absent)));
+        dot::write_graph(out, nodes, edges)
+    }
+    /// Return true if and only if `search` is reachable from (or is equal to) `base`.
+    /// `path` holds the edges traversed to reach `base`; on success it's left holding the full
+    /// path to `search`.
+    /// `visited` holds the nodes already searched, so that each is searched at most once.
+    /// The search keeps its own stack, so that arbitrarily long paths can be followed.
+    fn search_path<'a>(&'a self, search: NodeHandle, base: NodeHandle, path: &mut Vec<&'a Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>, visited: &mut HashSet<NodeHandle>) -> bool {
+        if base == search {
+            return true;
+        }
+        if !visited.insert(base) {
+            return false;
+        }
+        // Each entry holds the edges leaving a node on the current path that are yet to be tried.
+        let mut stack = vec![self.node_data[&base].outbound.iter()];
+        while let Some(edges) = stack.last_mut() {
+            match edges.next() {
+                Some(edge) => {
+                    // Edges to Null lead nowhere.
+                    if let Some(node_handle) = edge.to_handle() {
+                        if node_handle == search {
+                            path.push(edge);
+                            return true;
+                        }
+                        if visited.insert(node_handle) {
+                            path.push(edge);
+                            stack.push(self.node_data[&node_handle].outbound.iter());
+                        }
+                    }
+                },
+                None => {
+                    // Every edge from this node is a dead end, so back up.
+                    stack.pop();
+                    if !stack.is_empty() {
+                        path.pop();
+                    }
+                }
+            }
+        }
+        false
     }
 }
 
+impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where FromNodeW: Clone + Hash + Eq + PartialEq, FromNullW: Clone + Hash + Eq + PartialEq, ToNodeW: Clone + Hash + Eq + PartialEq, ToNullW: Clone + Hash + Eq + PartialEq {
+    /// On failure due to a cycle, the error holds the cycle that `edge` would have closed.
+    pub fn add_edge(&mut self, edge: Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>) -> Result<(), DagError<Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>> {
+        let from_handle = edge.from_handle();
+        let to_handle = edge.to_handle();
+        for handle in from_handle.iter().chain(to_handle.iter()) {
+            if !self.node_data.contains_key(handle) {
+                return Err(DagError::NoSuchNode);
+            }
+        }
+        let cycle = match from_handle {
+            // Edges from Null cannot cycle
+            None => None,
+            Some(from) => match to_handle {
+                // Edges to Null cannot cycle
+                None => None,
+                // if we can reach 'from' via 'to', then connecting from -> to creates cycle.
+                Some(to) => {
+                    let mut path = vec![];
+                    if self.search_path(from, to, &mut path, &mut HashSet::new()) {
+                        Some(path.into_iter().cloned().collect::<Vec<_>>())
+                    } else {
+                        None
+                    }
+                },
+            }
+        };
+
+        match cycle {
+            None => {
+                if let Some(to) = to_handle {
+                    self.node_data.get_mut(&to).unwrap().inbound.insert(edge.clone());
+                }
+                match from_handle {
+                    None => self.edges_from_null.insert(edge),
+                    Some(from) => self.node_data.get_mut(&from).unwrap().outbound.insert(edge),
+                };
+                Ok(())
+            },
+            Some(path) => {
+                let mut cycle = vec![edge];
+                cycle.extend(path);
+                Err(DagError::Cycle(cycle))
+            }
+        }
+    }
+    /// Return true if `to` is reachable from (or is) `from`.
+    pub fn is_reachable(&self, from: NodeHandle, to: NodeHandle) -> bool {
+        self.find_path(from, to).is_some()
+    }
+    /// Find a path that leads from `from` to `to`.
+    /// Returns the edges along that path (empty if `from == to`), or None if `to` isn't reachable
+    /// from `from` (or either node doesn't exist).
+    pub fn find_path(&self, from: NodeHandle, to: NodeHandle) -> Option<Vec<Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>> {
+        if !self.is_live(from) || !self.is_live(to) {
+            return None;
+        }
+        let mut path = vec![];
+        if self.search_path(to, from, &mut path, &mut HashSet::new()) {
+            Some(path.into_iter().cloned().collect())
+        } else {
+            None
+        }
+    }
+    /// Return true if adding an edge from `from` to `to` would be rejected for closing a cycle.
+    pub fn would_create_cycle(&self, from: NodeHandle, to: NodeHandle) -> bool {
+        self.is_reachable(to, from)
+    }
+}
 
 
 impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> NodeData<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
@@ -153,6 +354,7 @@ impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> NodeData<N, FromNodeW, FromNullW
         Self {
             data: node_data,
             outbound: HashSet::new(),
+            inbound: HashSet::new(),
         }
     }
 }
@@ -160,18 +362,339 @@ impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> NodeData<N, FromNodeW, FromNullW
 
 impl<FromNodeW, FromNullW, ToNodeW, ToNullW> Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>
     where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq, ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
+    pub fn new(from: EdgeFrom<FromNodeW, FromNullW>, to: EdgeTo<ToNodeW, ToNullW>) -> Self {
+        Edge {
+            from,
+            to,
+        }
+    }
+    pub fn from(&self) -> &EdgeFrom<FromNodeW, FromNullW> {
+        &self.from
+    }
+    pub fn to(&self) -> &EdgeTo<ToNodeW, ToNullW> {
+        &self.to
+    }
     /// Return the NodeHandle that this edge points from, or None if it points from Null.
-    fn from_handle(&self) -> Option<NodeHandle> {
+    pub fn from_handle(&self) -> Option<NodeHandle> {
         match self.from {
             EdgeFrom::Null(_) => None,
             EdgeFrom::Node(ref from_node) => Some(from_node.node),
         }
     }
     /// Return the NodeHandle that this edge points to, or None if it points to Null.
-    fn to_handle(&self) -> Option<NodeHandle> {
+    pub fn to_handle(&self) -> Option<NodeHandle> {
         match self.to {
             EdgeTo::Null(_) => None,
             EdgeTo::Node(ref to_node) => Some(to_node.node),
         }
     }
 }
+
+impl<W> FromNull<W> {
+    pub fn new(weight: W) -> Self {
+        FromNull {
+            weight,
+        }
+    }
+    pub fn weight(&self) -> &W {
+        &self.weight
+    }
+}
+
+impl<W> FromNode<W> {
+    pub fn new(node: NodeHandle, weight: W) -> Self {
+        FromNode {
+            node,
+            weight,
+        }
+    }
+    pub fn node(&self) -> NodeHandle {
+        self.node
+    }
+    pub fn weight(&self) -> &W {
+        &self.weight
+    }
+}
+
+impl<W> ToNull<W> {
+    pub fn new(weight: W) -> Self {
+        ToNull {
+            weight,
+        }
+    }
+    pub fn weight(&self) -> &W {
+        &self.weight
+    }
+}
+
+impl<W> ToNode<W> {
+    pub fn new(node: NodeHandle, weight: W) -> Self {
+        ToNode {
+            node,
+            weight,
+        }
+    }
+    pub fn node(&self) -> NodeHandle {
+        self.node
+    }
+    pub fn weight(&self) -> &W {
+        &self.weight
+    }
+}
+
+/// Exposes the node -> node edges through the generic interface, with the weight being the pair
+/// of weights for the `from` and `to` components.
+/// Edges to/from null aren't created through this interface, but they're included when
+/// iterating a node's children/parents and in the edge count.
+impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> OnDag<N, (FromNodeW, ToNodeW)> for IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where FromNodeW: Clone + Hash + Eq + PartialEq, FromNullW: Clone + Hash + Eq + PartialEq, ToNodeW: Clone + Hash + Eq + PartialEq, ToNullW: Clone + Hash + Eq + PartialEq {
+    type NodeHandle = NodeHandle;
+    type Edge = Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>;
+    fn add_node(&mut self, node_data: N) -> Self::NodeHandle {
+        IODagFull::add_node(self, node_data)
+    }
+    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: (FromNodeW, ToNodeW)) -> Result<(), DagError<Self::Edge>> {
+        IODagFull::add_edge(self, Edge::node_to_node(*from, *to, data))
+    }
+    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: (FromNodeW, ToNodeW)) -> Result<(), DagError<Self::Edge>> {
+        self.del_edge(Edge::node_to_node(*from, *to, data))
+    }
+    fn iter_topo(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        // just a depth-first sort, but then reverse the results.
+        let mut ordered = vec![];
+        self.depth_first_sort(*from, &mut ordered, &mut HashSet::new());
+        ordered.into_iter().rev()
+    }
+    fn iter_topo_rev(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        let mut ordered = vec![];
+        self.depth_first_sort(*from, &mut ordered, &mut HashSet::new());
+        ordered.into_iter()
+    }
+    fn children(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        // A deleted node has no edges.
+        self.node_data.get(node).into_iter().flat_map(|node| node.outbound.iter()).cloned()
+    }
+    fn parents(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        self.node_data.get(node).into_iter().flat_map(|node| node.inbound.iter()).cloned()
+    }
+    fn contains(&self, node: &Self::NodeHandle) -> bool {
+        self.node_data.contains_key(node)
+    }
+    fn node_count(&self) -> usize {
+        self.node_data.len()
+    }
+    fn edge_count(&self) -> usize {
+        self.edges_from_null.len() + self.node_data.values().map(|node| { node.outbound.len() }).sum::<usize>()
+    }
+}
+
+impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq, ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
+    /// Push every node reachable from `node` (via node -> node edges) onto `ordered`, deepest
+    /// first.
+    /// The search keeps its own stack, so that arbitrarily deep graphs can be sorted.
+    fn depth_first_sort(&self, node: NodeHandle, ordered: &mut Vec<NodeHandle>, marked: &mut HashSet<NodeHandle>) {
+        if !marked.insert(node) {
+            return;
+        }
+        // Each entry holds a node, along with the edges leaving it that are yet to be followed.
+        let mut stack = vec![(node, self.node_data[&node].outbound.iter())];
+        while let Some(&mut (_, ref mut edges)) = stack.last_mut() {
+            match edges.next() {
+                Some(edge) => {
+                    if let Some(to) = edge.to_handle() {
+                        if marked.insert(to) {
+                            stack.push((to, self.node_data[&to].outbound.iter()));
+                        }
+                    }
+                },
+                None => {
+                    // Everything beneath the node has been pushed, so it can follow.
+                    let (node, _) = stack.pop().unwrap();
+                    ordered.push(node);
+                }
+            }
+        }
+    }
+}
+
+impl<FromNodeW, FromNullW, ToNodeW, ToNullW> Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where FromNodeW: Hash + Eq + PartialEq, FromNullW: Hash + Eq + PartialEq, ToNodeW: Hash + Eq + PartialEq, ToNullW: Hash + Eq + PartialEq {
+    /// Build an edge with no null components.
+    fn node_to_node(from: NodeHandle, to: NodeHandle, weights: (FromNodeW, ToNodeW)) -> Self {
+        Edge::new(EdgeFrom::Node(FromNode::new(from, weights.0)), EdgeTo::Node(ToNode::new(to, weights.1)))
+    }
+}
+
+/// Writes the nodes (with their handles), every edge & the generation of each slot.
+#[cfg(feature = "serde")]
+impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> Serialize for IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where N: Serialize,
+          FromNodeW: Hash + Eq + PartialEq + Serialize, FromNullW: Hash + Eq + PartialEq + Serialize,
+          ToNodeW: Hash + Eq + PartialEq + Serialize, ToNullW: Hash + Eq + PartialEq + Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut nodes = self.node_data.iter().collect::<Vec<_>>();
+        nodes.sort_by_key(|&(handle, _data)| handle.index);
+        let mut state = serializer.serialize_struct("IODagFull", 3)?;
+        state.serialize_field("slots", self.slots.generations())?;
+        state.serialize_field("nodes", &nodes)?;
+        state.serialize_field("edges_from_null", &self.edges_from_null)?;
+        state.end()
+    }
+}
+
+/// Rejects input that doesn't describe a valid graph, including graphs with cycles.
+#[cfg(feature = "serde")]
+impl<'de, N, FromNodeW, FromNullW, ToNodeW, ToNullW> Deserialize<'de> for IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where N: Deserialize<'de>,
+          FromNodeW: Clone + Hash + Eq + PartialEq + Deserialize<'de>, FromNullW: Clone + Hash + Eq + PartialEq + Deserialize<'de>,
+          ToNodeW: Clone + Hash + Eq + PartialEq + Deserialize<'de>, ToNullW: Clone + Hash + Eq + PartialEq + Deserialize<'de> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let repr = IODagFullRepr::deserialize(deserializer)?;
+        IODagFull::from_repr(repr).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where FromNodeW: Clone + Hash + Eq + PartialEq, FromNullW: Clone + Hash + Eq + PartialEq, ToNodeW: Clone + Hash + Eq + PartialEq, ToNullW: Clone + Hash + Eq + PartialEq {
+    fn from_repr(repr: IODagFullRepr<N, FromNodeW, FromNullW, ToNodeW, ToNullW>) -> Result<Self, String> {
+        let IODagFullRepr { slots, nodes, edges_from_null } = repr;
+        let mut node_sets = HashMap::new();
+        for (handle, node) in nodes {
+            if slots.get(handle.index as usize) != Some(&handle.generation) {
+                return Err(format!("{:?} doesn't match the generation of its slot", handle));
+            }
+            if node_sets.insert(handle, node).is_some() {
+                return Err(format!("{:?} appears more than once", handle));
+            }
+        }
+        // Every edge must be filed under its endpoints, and nowhere else.
+        let is_outbound = |edge: &Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>| match edge.from_handle() {
+            None => edges_from_null.contains(edge),
+            Some(from) => node_sets.get(&from).is_some_and(|node: &NodeData<_, _, _, _, _>| node.outbound.contains(edge)),
+        };
+        let is_inbound = |edge: &Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>| match edge.to_handle() {
+            None => true,
+            Some(to) => node_sets.get(&to).is_some_and(|node: &NodeData<_, _, _, _, _>| node.inbound.contains(edge)),
+        };
+        if !edges_from_null.iter().all(|edge| edge.from_handle().is_none() && is_inbound(edge)) {
+            return Err("edges from null are inconsistent".to_string());
+        }
+        for (handle, node) in node_sets.iter() {
+            if !node.outbound.iter().all(|edge| edge.from_handle() == Some(*handle) && is_inbound(edge)) {
+                return Err(format!("outbound edges of {:?} are inconsistent", handle));
+            }
+            if !node.inbound.iter().all(|edge| edge.to_handle() == Some(*handle) && is_outbound(edge)) {
+                return Err(format!("inbound edges of {:?} are inconsistent", handle));
+            }
+        }
+
+        let mut dag = IODagFull::new();
+        dag.slots = Slots::with_generations(slots);
+        let mut edges = edges_from_null.into_iter().collect::<Vec<_>>();
+        for (handle, node) in node_sets {
+            let NodeData { data, outbound, inbound: _ } = node;
+            dag.slots.claim(handle.index, handle.generation);
+            dag.node_data.insert(handle, NodeData::new(data));
+            edges.extend(outbound);
+        }
+        for edge in edges {
+            dag.add_edge(edge).map_err(|err| format!("invalid edge: {}", err))?;
+        }
+        Ok(dag)
+    }
+}
+
+/// Result of reading an IODagFull from GraphML.
+#[cfg(feature = "graphml")]
+type GraphmlResult<N, FromNodeW, FromNullW, ToNodeW, ToNullW> =
+    Result<GraphmlImport<IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>, NodeHandle>, GraphmlError<Edge<FromNodeW, FromNullW, ToNodeW, ToNullW>>>;
+
+#[cfg(feature = "graphml")]
+impl<N, FromNodeW, FromNullW, ToNodeW, ToNullW> IODagFull<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+    where FromNodeW: Clone + Hash + Eq + PartialEq, FromNullW: Clone + Hash + Eq + PartialEq, ToNodeW: Clone + Hash + Eq + PartialEq, ToNullW: Clone + Hash + Eq + PartialEq {
+    /// Write the graph in GraphML format.
+    /// `node_attrs` converts each node's data into attributes (name -> value), and the other
+    /// converters do the same for each of the four weight types. Each edge carries the attributes
+    /// of both its halves, so to keep them distinct, their names are prefixed with "from_node.",
+    /// "from_null.", "to_node." or "to_null.".
+    /// Nodes are identified by their handles, so reading the file back with `read_graphml` gives
+    /// the same handles.
+    pub fn write_graphml<Out, FN, FFromNode, FFromNull, FToNode, FToNull>(&self, out: &mut Out, node_attrs: FN, from_node_attrs: FFromNode, from_null_attrs: FFromNull, to_node_attrs: FToNode, to_null_attrs: FToNull) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> HashMap<String, String>,
+            FFromNode: Fn(&FromNodeW) -> HashMap<String, String>, FFromNull: Fn(&FromNullW) -> HashMap<String, String>,
+            FToNode: Fn(&ToNodeW) -> HashMap<String, String>, FToNull: Fn(&ToNullW) -> HashMap<String, String>
+    {
+        let nodes = self.node_data.iter().map(|(handle, node)| (handle.index, node_attrs(&node.data))).collect();
+        let node_edges = self.node_data.values().flat_map(|node| node.outbound.iter());
+        let edges = self.edges_from_null.iter().chain(node_edges).map(|edge| {
+            let mut attrs = match *edge.from() {
+                EdgeFrom::Null(ref from) => graphml::add_prefix("from_null.", from_null_attrs(from.weight())),
+                EdgeFrom::Node(ref from) => graphml::add_prefix("from_node.", from_node_attrs(from.weight())),
+            };
+            attrs.extend(match *edge.to() {
+                EdgeTo::Null(ref to) => graphml::add_prefix("to_null.", to_null_attrs(to.weight())),
+                EdgeTo::Node(ref to) => graphml::add_prefix("to_node.", to_node_attrs(to.weight())),
+            });
+            GraphmlEdge {
+                from: edge.from_handle().map(|from| from.index),
+                to: edge.to_handle().map(|to| to.index),
+                attrs,
+            }
+        }).collect();
+        graphml::write_graph(out, nodes, edges, self.slots.generations())
+    }
+    /// Build a graph from a GraphML file.
+    /// Each node's data is parsed from its attributes by `parse_node`. Each edge's weights are
+    /// parsed by the parser for their type, from the edge attributes with the matching prefix (see
+    /// `write_graphml`), with the prefix removed. Nodes written by `write_graphml` keep their
+    /// handles; any others are given new ones. Each edge is added via `add_edge`; if one would
+    /// close a cycle, the error reports its line.
+    pub fn read_graphml<R, FN, FFromNode, FFromNull, FToNode, FToNull>(input: R, mut parse_node: FN, mut parse_from_node: FFromNode, mut parse_from_null: FFromNull, mut parse_to_node: FToNode, mut parse_to_null: FToNull) -> GraphmlResult<N, FromNodeW, FromNullW, ToNodeW, ToNullW>
+        where R: io::Read, FN: FnMut(&HashMap<String, String>) -> Result<N, String>,
+            FFromNode: FnMut(&HashMap<String, String>) -> Result<FromNodeW, String>, FFromNull: FnMut(&HashMap<String, String>) -> Result<FromNullW, String>,
+            FToNode: FnMut(&HashMap<String, String>) -> Result<ToNodeW, String>, FToNull: FnMut(&HashMap<String, String>) -> Result<ToNullW, String>
+    {
+        let parsed = graphml::parse(input)?;
+        let mut dag = IODagFull::new();
+        let mut handles = HashMap::new();
+        for (node, index) in parsed.nodes.into_iter().zip(parsed.indices) {
+            let node_data = parse_node(&node.attrs).map_err(|message| GraphmlError::Invalid { line: node.line, message })?;
+            let handle = NodeHandle {
+                index,
+                generation: parsed.generations[index as usize],
+            };
+            dag.node_data.insert(handle, NodeData::new(node_data));
+            handles.insert(node.id, handle);
+        }
+        dag.slots = Slots::with_generations(parsed.generations);
+        for handle in handles.values() {
+            dag.slots.claim(handle.index, handle.generation);
+        }
+        for edge in parsed.edges {
+            let line = edge.line;
+            let invalid = |message| GraphmlError::Invalid { line, message };
+            let from = match handles.get(&edge.source) {
+                None => EdgeFrom::Null(FromNull::new(parse_from_null(&graphml::strip_prefix("from_null.", &edge.attrs)).map_err(invalid)?)),
+                Some(&from) => EdgeFrom::Node(FromNode::new(from, parse_from_node(&graphml::strip_prefix("from_node.", &edge.attrs)).map_err(invalid)?)),
+            };
+            let to = match handles.get(&edge.target) {
+                None => EdgeTo::Null(ToNull::new(parse_to_null(&graphml::strip_prefix("to_null.", &edge.attrs)).map_err(invalid)?)),
+                Some(&to) => EdgeTo::Node(ToNode::new(to, parse_to_node(&graphml::strip_prefix("to_node.", &edge.attrs)).map_err(invalid)?)),
+            };
+            dag.add_edge(Edge::new(from, to)).map_err(|err| match err {
+                DagError::Cycle(cycle) => GraphmlError::Cycle { line, cycle },
+                err => invalid(err.to_string()),
+            })?;
+        }
+        Ok(GraphmlImport {
+            graph: dag,
+            nodes: handles,
+        })
+    }
+}
@@ -0,0 +1,203 @@
+//! Undo/redo history for an IODag.
+//! Edits are made through an IODagHistory, which records the ops needed to revert them (including
+//! node data & edge weights), grouped into named steps.
+
+use std::hash::Hash;
+use std::ops::Deref;
+
+use super::error::DagError;
+use super::iodag::{Edge, EditOp, IODag, NodeHandle};
+
+pub struct IODagHistory<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    dag: IODag<N, W>,
+    /// Steps that can be undone, most recent last.
+    undo: Vec<Step<N, W>>,
+    /// Steps that have been undone, most recent last. Cleared by any new edit.
+    redo: Vec<Step<N, W>>,
+    /// The step opened by `begin_step`, if any.
+    open: Option<Step<N, W>>,
+}
+
+/// A named group of edits.
+struct Step<N, W>
+    where W: Hash + Eq + PartialEq {
+    name: String,
+    /// Ops that revert (or for redo steps, re-apply) the edits, in the order the edits were made.
+    ops: Vec<EditOp<N, W>>,
+}
+
+impl<N, W> IODagHistory<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    /// Begin recording edits to `dag`. Its existing contents can't be undone.
+    pub fn new(dag: IODag<N, W>) -> Self {
+        IODagHistory {
+            dag,
+            undo: vec![],
+            redo: vec![],
+            open: None,
+        }
+    }
+    /// Stop recording, and return the graph.
+    pub fn into_inner(self) -> IODag<N, W> {
+        self.dag
+    }
+    /// Group all edits made until `end_step` (or the next `begin_step`) into a single undo step.
+    /// Edits made outside of a step each form their own step, named after the method called.
+    pub fn begin_step(&mut self, name: &str) {
+        self.end_step();
+        self.open = Some(Step::new(name));
+    }
+    /// Close the step opened by `begin_step`. A step containing no edits is discarded.
+    pub fn end_step(&mut self) {
+        if let Some(step) = self.open.take() {
+            if !step.ops.is_empty() {
+                self.undo.push(step);
+            }
+        }
+    }
+    /// Name of the step that `undo` would revert.
+    pub fn undo_name(&self) -> Option<&str> {
+        match self.open {
+            Some(ref step) if !step.ops.is_empty() => Some(&step.name),
+            _ => self.undo.last().map(|step| &step.name[..]),
+        }
+    }
+    /// Name of the step that `redo` would re-apply.
+    pub fn redo_name(&self) -> Option<&str> {
+        self.redo.last().map(|step| &step.name[..])
+    }
+
+    pub fn add_node(&mut self, node_data: N) -> NodeHandle {
+        let handle = self.dag.add_node(node_data);
+        self.record("add_node", EditOp::DelNode(handle));
+        handle
+    }
+    /// See `IODag::add_edge`.
+    pub fn add_edge<F>(&mut self, edge: Edge<W>, reachable_pred: &F) -> Result<(), DagError<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        self.dag.can_add_edge(&edge, reachable_pred)?;
+        self.apply("add_edge", EditOp::AddEdge(edge))
+    }
+    /// See `IODag::del_edge`.
+    pub fn del_edge(&mut self, edge: Edge<W>) -> Result<(), DagError<Edge<W>>> {
+        self.apply("del_edge", EditOp::DelEdge(edge))
+    }
+    /// See `IODag::del_node`.
+    pub fn del_node(&mut self, node: NodeHandle) -> Result<(), DagError<Edge<W>>> {
+        self.apply("del_node", EditOp::DelNode(node))
+    }
+
+    /// Revert the most recent step (closing it first, if it's open).
+    /// Any edges it re-creates are checked for cycles via `reachable_pred`; if one would be
+    /// created, the graph & history are left untouched.
+    /// Returns false if there's nothing to undo.
+    pub fn undo<F>(&mut self, reachable_pred: &F) -> Result<bool, DagError<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        self.end_step();
+        match self.undo.pop() {
+            None => Ok(false),
+            Some(mut step) => match self.replay(&mut step, reachable_pred) {
+                Ok(inverse) => {
+                    self.redo.push(inverse);
+                    Ok(true)
+                },
+                Err(err) => {
+                    self.undo.push(step);
+                    Err(err)
+                },
+            },
+        }
+    }
+    /// Re-apply the most recently undone step.
+    /// Any edges it re-creates are checked for cycles via `reachable_pred`; if one would be
+    /// created, the graph & history are left untouched.
+    /// Returns false if there's nothing to redo.
+    pub fn redo<F>(&mut self, reachable_pred: &F) -> Result<bool, DagError<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        self.end_step();
+        match self.redo.pop() {
+            None => Ok(false),
+            Some(mut step) => match self.replay(&mut step, reachable_pred) {
+                Ok(inverse) => {
+                    self.undo.push(inverse);
+                    Ok(true)
+                },
+                Err(err) => {
+                    self.redo.push(step);
+                    Err(err)
+                },
+            },
+        }
+    }
+
+    /// Apply the op to the graph, and record the op that reverts it.
+    fn apply(&mut self, method: &str, op: EditOp<N, W>) -> Result<(), DagError<Edge<W>>> {
+        if let Some(inverse) = self.dag.apply_op(op)? {
+            self.record(method, inverse);
+        }
+        Ok(())
+    }
+    fn record(&mut self, method: &str, inverse: EditOp<N, W>) {
+        self.redo.clear();
+        match self.open {
+            Some(ref mut step) => step.ops.push(inverse),
+            None => {
+                let mut step = Step::new(method);
+                step.ops.push(inverse);
+                self.undo.push(step);
+            },
+        }
+    }
+    /// Apply the step's ops (newest first), checking each new edge for cycles.
+    /// On success, returns the step that reverts this one (and `step` is left empty).
+    /// On failure, the ops already applied are reverted, leaving `step` as it was.
+    fn replay<F>(&mut self, step: &mut Step<N, W>, reachable_pred: &F) -> Result<Step<N, W>, DagError<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        let mut inverse = Step::new(&step.name);
+        while let Some(op) = step.ops.pop() {
+            if let Err(err) = self.dag.can_apply_op(&op, reachable_pred) {
+                // Put the step back together: the ops not yet applied, followed by the reverse of
+                // those that were.
+                step.ops.push(op);
+                while let Some(op) = inverse.ops.pop() {
+                    let reverted = self.apply_checked(op);
+                    step.ops.extend(reverted);
+                }
+                return Err(err);
+            }
+            let inverse_op = self.apply_checked(op);
+            inverse.ops.extend(inverse_op);
+        }
+        Ok(inverse)
+    }
+    /// Apply an op that's known to be valid.
+    fn apply_checked(&mut self, op: EditOp<N, W>) -> Option<EditOp<N, W>> {
+        match self.dag.apply_op(op) {
+            Ok(inverse) => inverse,
+            Err(_) => panic!("IODagHistory failed to apply a valid op"),
+        }
+    }
+}
+
+impl<N, W> Deref for IODagHistory<N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    type Target = IODag<N, W>;
+    fn deref(&self) -> &IODag<N, W> {
+        &self.dag
+    }
+}
+
+impl<N, W> Step<N, W>
+    where W: Hash + Eq + PartialEq {
+    fn new(name: &str) -> Self {
+        Step {
+            name: name.to_string(),
+            ops: vec![],
+        }
+    }
+}
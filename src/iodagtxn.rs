@@ -0,0 +1,92 @@
+//! Transactions over an IODag: a guard through which the graph is modified, and which reverts
+//! those modifications unless it's committed.
+
+use std::hash::Hash;
+use std::ops::Deref;
+
+use super::error::DagError;
+use super::iodag::{Edge, EditOp, IODag, NodeHandle};
+
+/// Created by `IODag::transaction()`.
+/// Every modification made through the guard is recorded, and dropping it (or calling
+/// `rollback()`) restores the graph's nodes & edges to exactly the state they were in beforehand.
+/// Handles given to nodes created during the transaction aren't reused once it's rolled back; like
+/// the handles of any other deleted node, they stay stale. Call `commit()` to keep the
+/// modifications.
+/// The graph can be read through the guard while the transaction is open.
+pub struct Transaction<'a, N: 'a, W: 'a>
+    where W: Clone + Hash + Eq + PartialEq {
+    dag: &'a mut IODag<N, W>,
+    /// Ops that revert each modification, in the order the modifications were made.
+    undo: Vec<EditOp<N, W>>,
+    committed: bool,
+}
+
+impl<'a, N, W> Transaction<'a, N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    pub(super) fn new(dag: &'a mut IODag<N, W>) -> Self {
+        Transaction {
+            dag,
+            undo: vec![],
+            committed: false,
+        }
+    }
+    pub fn add_node(&mut self, node_data: N) -> NodeHandle {
+        let handle = self.dag.add_node(node_data);
+        self.undo.push(EditOp::DelNode(handle));
+        handle
+    }
+    /// See `IODag::add_edge`.
+    pub fn add_edge<F>(&mut self, edge: Edge<W>, reachable_pred: &F) -> Result<(), DagError<Edge<W>>>
+        where F: Fn(&Edge<W>, &Edge<W>) -> bool
+    {
+        self.dag.can_add_edge(&edge, reachable_pred)?;
+        self.record(EditOp::AddEdge(edge))
+    }
+    /// See `IODag::del_edge`.
+    pub fn del_edge(&mut self, edge: Edge<W>) -> Result<(), DagError<Edge<W>>> {
+        self.record(EditOp::DelEdge(edge))
+    }
+    /// See `IODag::del_node`.
+    pub fn del_node(&mut self, node: NodeHandle) -> Result<(), DagError<Edge<W>>> {
+        self.record(EditOp::DelNode(node))
+    }
+    /// Keep every modification made during the transaction.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+    /// Revert every modification made during the transaction.
+    /// This is also done when the transaction is dropped without being committed.
+    pub fn rollback(self) {}
+
+    fn record(&mut self, op: EditOp<N, W>) -> Result<(), DagError<Edge<W>>> {
+        if let Some(inverse) = self.dag.apply_op(op)? {
+            self.undo.push(inverse);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, N, W> Deref for Transaction<'a, N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    type Target = IODag<N, W>;
+    fn deref(&self) -> &IODag<N, W> {
+        self.dag
+    }
+}
+
+impl<'a, N, W> Drop for Transaction<'a, N, W>
+    where W: Clone + Hash + Eq + PartialEq {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        // Undo the modifications newest first, so that each op is applied to the same state its
+        // modification left behind.
+        while let Some(op) = self.undo.pop() {
+            if self.dag.apply_op(op).is_err() {
+                panic!("Transaction rollback failed");
+            }
+        }
+    }
+}
@@ -1,13 +1,36 @@
 // Requires feature-gate for returning impl Iterator
 #![feature(conservative_impl_trait)]
 
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "graphml")]
+extern crate xml;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
 #[cfg(test)]
 mod tests;
 
+pub mod arcdag;
+pub mod arcposcostdag;
+pub mod batch;
+pub mod binary;
+pub mod concurrentiodag;
+pub mod dot;
+pub mod error;
+#[cfg(feature = "graphml")]
+pub mod graphml;
 pub mod iodag;
 pub mod iodagfull;
+pub mod iodaghistory;
 pub mod ondag;
 pub mod poscostdag;
 pub mod rcdag;
+pub mod snapshot;
 
+mod arcdagbase;
+mod dotparse;
+mod iodagtxn;
+mod nodeptr;
 mod rcdagbase;
+mod slots;
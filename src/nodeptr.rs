@@ -0,0 +1,121 @@
+//! The pointer & lock types through which the handle-based graphs share their nodes: `Local`
+//! (`Rc<RefCell<_>>`) for `RcDag` & `PosCostDag`, and `Shared` (`Arc<RwLock<_>>`) for the
+//! thread-safe `ArcDag` & `ArcPosCostDag`. Everything in `rcdagbase` is written once, over
+//! `NodePtr`.
+
+use std::cell::{self, RefCell};
+use std::ops::{Deref, DerefMut};
+use std::rc::{self, Rc};
+use std::sync::{self, Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+pub trait NodePtr: Sized {
+    /// Owning pointer to a value that may be borrowed mutably through it.
+    type Strong<T>;
+    type Weak<T>;
+    type Ref<'a, T: 'a>: Deref<Target=T>;
+    type RefMut<'a, T: 'a>: DerefMut<Target=T>;
+    fn new<T>(value: T) -> Self::Strong<T>;
+    fn clone<T>(ptr: &Self::Strong<T>) -> Self::Strong<T>;
+    fn borrow<T>(ptr: &Self::Strong<T>) -> Self::Ref<'_, T>;
+    fn borrow_mut<T>(ptr: &Self::Strong<T>) -> Self::RefMut<'_, T>;
+    /// Borrow the value mutably, or return None if it's borrowed elsewhere.
+    fn try_borrow_mut<T>(ptr: &Self::Strong<T>) -> Option<Self::RefMut<'_, T>>;
+    /// Take the value if `ptr` is the only strong pointer to it; otherwise just release `ptr`.
+    /// This is atomic, so the value can't be revived meanwhile through a weak pointer.
+    fn try_unwrap<T>(ptr: Self::Strong<T>) -> Option<T>;
+    fn downgrade<T>(ptr: &Self::Strong<T>) -> Self::Weak<T>;
+    fn upgrade<T>(weak: &Self::Weak<T>) -> Option<Self::Strong<T>>;
+    /// True once every strong pointer to the value has been dropped.
+    fn is_dead<T>(weak: &Self::Weak<T>) -> bool;
+    /// Address of the value, which identifies it for as long as it's alive.
+    fn addr<T>(ptr: &Self::Strong<T>) -> usize;
+    fn weak_addr<T>(weak: &Self::Weak<T>) -> usize;
+}
+
+/// Nodes that belong to a single thread.
+pub enum Local {}
+
+/// Nodes that can be shared between threads.
+pub enum Shared {}
+
+impl NodePtr for Local {
+    type Strong<T> = Rc<RefCell<T>>;
+    type Weak<T> = rc::Weak<RefCell<T>>;
+    type Ref<'a, T: 'a> = cell::Ref<'a, T>;
+    type RefMut<'a, T: 'a> = cell::RefMut<'a, T>;
+    fn new<T>(value: T) -> Self::Strong<T> {
+        Rc::new(RefCell::new(value))
+    }
+    fn clone<T>(ptr: &Self::Strong<T>) -> Self::Strong<T> {
+        ptr.clone()
+    }
+    fn borrow<T>(ptr: &Self::Strong<T>) -> Self::Ref<'_, T> {
+        ptr.borrow()
+    }
+    fn borrow_mut<T>(ptr: &Self::Strong<T>) -> Self::RefMut<'_, T> {
+        ptr.borrow_mut()
+    }
+    fn try_borrow_mut<T>(ptr: &Self::Strong<T>) -> Option<Self::RefMut<'_, T>> {
+        ptr.try_borrow_mut().ok()
+    }
+    fn try_unwrap<T>(ptr: Self::Strong<T>) -> Option<T> {
+        Rc::try_unwrap(ptr).ok().map(RefCell::into_inner)
+    }
+    fn downgrade<T>(ptr: &Self::Strong<T>) -> Self::Weak<T> {
+        Rc::downgrade(ptr)
+    }
+    fn upgrade<T>(weak: &Self::Weak<T>) -> Option<Self::Strong<T>> {
+        weak.upgrade()
+    }
+    fn is_dead<T>(weak: &Self::Weak<T>) -> bool {
+        weak.strong_count() == 0
+    }
+    fn addr<T>(ptr: &Self::Strong<T>) -> usize {
+        Rc::as_ptr(ptr) as usize
+    }
+    fn weak_addr<T>(weak: &Self::Weak<T>) -> usize {
+        weak.as_ptr() as usize
+    }
+}
+
+/// A lock is only poisoned by a panic partway through modifying the node, which leaves nothing
+/// that readers can't cope with, so poisoning is ignored.
+impl NodePtr for Shared {
+    type Strong<T> = Arc<RwLock<T>>;
+    type Weak<T> = sync::Weak<RwLock<T>>;
+    type Ref<'a, T: 'a> = RwLockReadGuard<'a, T>;
+    type RefMut<'a, T: 'a> = RwLockWriteGuard<'a, T>;
+    fn new<T>(value: T) -> Self::Strong<T> {
+        Arc::new(RwLock::new(value))
+    }
+    fn clone<T>(ptr: &Self::Strong<T>) -> Self::Strong<T> {
+        ptr.clone()
+    }
+    fn borrow<T>(ptr: &Self::Strong<T>) -> Self::Ref<'_, T> {
+        ptr.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    fn borrow_mut<T>(ptr: &Self::Strong<T>) -> Self::RefMut<'_, T> {
+        ptr.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    fn try_borrow_mut<T>(ptr: &Self::Strong<T>) -> Option<Self::RefMut<'_, T>> {
+        ptr.try_write().ok()
+    }
+    fn try_unwrap<T>(ptr: Self::Strong<T>) -> Option<T> {
+        Arc::try_unwrap(ptr).ok().map(|lock| lock.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+    fn downgrade<T>(ptr: &Self::Strong<T>) -> Self::Weak<T> {
+        Arc::downgrade(ptr)
+    }
+    fn upgrade<T>(weak: &Self::Weak<T>) -> Option<Self::Strong<T>> {
+        weak.upgrade()
+    }
+    fn is_dead<T>(weak: &Self::Weak<T>) -> bool {
+        weak.strong_count() == 0
+    }
+    fn addr<T>(ptr: &Self::Strong<T>) -> usize {
+        Arc::as_ptr(ptr) as usize
+    }
+    fn weak_addr<T>(weak: &Self::Weak<T>) -> usize {
+        weak.as_ptr() as usize
+    }
+}
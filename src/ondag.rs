@@ -1,12 +1,25 @@
+use super::error::DagError;
+
 /// Implements the OnDag trait, which defines the general interface
 /// that all DAG implementations need implement.
 /// Note: some implementations may require N or E to also be hashable, cloneable, or orderable.
 pub trait OnDag<N, E> {
     type NodeHandle;
+    /// Edge type used to report paths through the graph (e.g. cycle witnesses).
+    type Edge;
     fn add_node(&mut self, node: N) -> Self::NodeHandle;
-    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(),()>;
-    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(),()>;
-    // fn iter_topo(&self, from: &NodeHandle) -> impl Iterator<Item=Self::NodeHandle>
-    // fn iter_topo_rev(&self, from: &NodeHandle) -> impl Iterator<Item=Self::NodeHandle>
-    // fn children(&self, node: &NodeHandle) -> impl Iterator<Item=Edge>
+    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>>;
+    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>>;
+    /// Iterate `from` and every node reachable from it, parents before children.
+    fn iter_topo(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle>;
+    /// Iterate `from` and every node reachable from it, children before parents.
+    fn iter_topo_rev(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle>;
+    /// Iterate the edges leaving `node`.
+    fn children(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge>;
+    /// Iterate the edges entering `node`.
+    fn parents(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge>;
+    /// Return true if `node` is a live node of this graph.
+    fn contains(&self, node: &Self::NodeHandle) -> bool;
+    fn node_count(&self) -> usize;
+    fn edge_count(&self) -> usize;
 }
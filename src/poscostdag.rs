@@ -1,13 +1,25 @@
+use std::io::{self, Write};
+
+use super::batch::EdgeBatch;
+use super::error::DagError;
 use super::ondag::OnDag;
 use super::rcdagbase::RcDagBase;
+use super::snapshot::DagSnapshot;
 
-pub use super::rcdagbase::{HalfEdge, FullEdge, NodeHandle, WeakNodeHandle};
+pub use super::rcdagbase::{HalfEdge, FullEdge, IterTopo, IterTopoRev, NodeHandle, WeakNodeHandle};
 
-pub trait CostQueriable<N, E> {
+/// Tells which edges of a `PosCostDag` (or, given `D`, of an `ArcPosCostDag`) are free to traverse.
+pub trait CostQueriable<N, E, D: CostGraph<N, E> = PosCostDag<N, E>> {
     /// Return true if the cost of traversing this edge, in the context of traveling to `next`, is 0.
     /// `next` is included because some graphs have internal costs associated with the node -
     /// most graphs won't need to peek at `next`.
-    fn is_zero_cost(edge: &HalfEdge<N, E>, next: &HalfEdge<N, E>, dag: &PosCostDag<N, E>) -> bool;
+    fn is_zero_cost(edge: &D::HalfEdge, next: &D::HalfEdge, dag: &D) -> bool;
+}
+
+/// A graph whose edges are costed via `CostQueriable`.
+pub trait CostGraph<N, E> {
+    /// The edge type passed to `CostQueriable::is_zero_cost`.
+    type HalfEdge;
 }
 
 
@@ -22,16 +34,18 @@ pub struct PosCostDag<N, E> {
 
 impl <N, E : Eq + CostQueriable<N, E> + Clone> OnDag<N, E> for PosCostDag<N, E> {
     type NodeHandle = NodeHandle<N, E>;
+    type Edge = FullEdge<N, E>;
     fn add_node(&mut self, node_data: N) -> Self::NodeHandle {
         self.dag.add_node(node_data)
     }
-    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(),()> {
+    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>> {
         // the edge must connect two nodes owned by *this* graph.
-        from.check_owner(&self.dag);
-        to.check_owner(&self.dag);
+        if !self.dag.owns(from) || !self.dag.owns(to) {
+            return Err(DagError::ForeignHandle);
+        }
 
         self.dag.add_edge_unchecked(from, to, data.clone());
-        let half_edge = HalfEdge::new(to.clone(), data.clone());
+        let new_edge = FullEdge::new(from.clone(), HalfEdge::new(to.clone(), data.clone()));
         // Theory:
         //  1. Before the new edge, there were no 0-cycles.
         //  2. If the new edge introduces a 0-cycle, that edge must be a component of the cycle.
@@ -39,18 +53,57 @@ impl <N, E : Eq + CostQueriable<N, E> + Clone> OnDag<N, E> for PosCostDag<N, E>
         //  4. Therefore, a 0-cycle was introduced to the graph IFF there is a 0-cycle from
         //     the new edge to itself.
         //  Note: 0-cycle = zero cumulative cost cycle.
-        if self.is_zero_cost(&half_edge, &half_edge) {
-            // This edge introduced a 0-cycle
-            self.dag.rm_edge(from, to, data);
-            Err(())
-        } else {
+        match self.zero_cost_cycle_through(&new_edge) {
+            Some(cycle) => {
+                // This edge introduced a 0-cycle
+                self.dag.rm_edge(from, to, data);
+                Err(DagError::ZeroCostCycle(cycle))
+            },
             // No 0-cycles.
+            None => Ok(())
+        }
+    }
+    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>> {
+        if !self.dag.owns(from) || !self.dag.owns(to) {
+            Err(DagError::ForeignHandle)
+        } else if self.dag.rm_edge(from, to, data) {
             Ok(())
+        } else {
+            Err(DagError::NoSuchEdge)
         }
     }
-    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), ()> {
-        self.dag.rm_edge(from, to, data);
-        Ok(())
+    fn iter_topo(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        self.dag.iter_topo(from)
+    }
+    fn iter_topo_rev(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        self.dag.iter_topo_rev(from)
+    }
+    fn children(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        let from = node.clone();
+        self.dag.children(node).map(move |half| FullEdge::new(from.clone(), half))
+    }
+    fn parents(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        self.dag.parents(node)
+    }
+    fn contains(&self, node: &Self::NodeHandle) -> bool {
+        // A live handle keeps its node alive, so ownership is all that needs checking.
+        self.dag.owns(node)
+    }
+    fn node_count(&self) -> usize {
+        self.dag.node_count()
+    }
+    fn edge_count(&self) -> usize {
+        self.dag.edge_count()
+    }
+}
+
+impl <N, E> CostGraph<N, E> for PosCostDag<N, E> {
+    type HalfEdge = HalfEdge<N, E>;
+}
+
+impl <N, E> Default for PosCostDag<N, E> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -62,26 +115,90 @@ impl <N, E> PosCostDag<N, E> {
     }
 }
 
-impl <N, E : Eq> PosCostDag<N, E> {
-    pub fn iter_topo(&self, from: &NodeHandle<N, E>) -> impl Iterator<Item=NodeHandle<N, E>> {
+impl <N, E: Eq> PosCostDag<N, E> {
+    /// Lazily iterate `from` and every node reachable from it, parents before children.
+    /// Unlike `OnDag::iter_topo`, the iterator's type can be named (e.g. to store it in a struct).
+    /// It holds handles to the nodes it has yet to yield, so it doesn't borrow the graph; edges
+    /// modified during iteration may or may not be reflected.
+    pub fn iter_topo(&self, from: &NodeHandle<N, E>) -> IterTopo<N, E> {
         self.dag.iter_topo(from)
     }
-    pub fn iter_topo_rev(&self, from: &NodeHandle<N, E>) -> impl Iterator<Item=NodeHandle<N, E>> {
+    /// Lazily iterate `from` and every node reachable from it, children before parents (as for
+    /// `iter_topo`).
+    pub fn iter_topo_rev(&self, from: &NodeHandle<N, E>) -> IterTopoRev<N, E> {
         self.dag.iter_topo_rev(from)
     }
 }
 
 impl <N, E : Eq + CostQueriable<N, E> + Clone> PosCostDag<N, E> {
-    fn is_zero_cost(&self, search: &HalfEdge<N, E>, base: &HalfEdge<N, E>) -> bool {
-        self.dag.children(base.to()).any(|edge| {
-            let is_this_edge_0 = E::is_zero_cost(&base, &edge, &self);
-            is_this_edge_0 && (&edge == search || self.is_zero_cost(search, &edge))
-        })
+    /// Copy every node & edge reachable from `roots` (e.g. in order to serialize them).
+    /// The copy can be loaded into a new graph via `DagSnapshot::restore`.
+    pub fn snapshot(&self, roots: &[NodeHandle<N, E>]) -> Result<DagSnapshot<N, E>, DagError<FullEdge<N, E>>>
+        where N: Clone
+    {
+        self.dag.snapshot(roots)
     }
-}
-
-impl <N, E : Eq + Clone> PosCostDag<N, E> {
-    pub fn children(&self, node: &NodeHandle<N, E>) -> impl Iterator<Item=HalfEdge<N, E>> {
-        self.dag.children(node)
+    /// Write every node & edge reachable from `roots` in Graphviz DOT format.
+    /// `node_label` & `edge_label` format the labels of each node & edge (e.g. from the node's
+    /// data & the edge's weight). The edges in `highlight` (e.g. the cycle returned by a rejected
+    /// insertion) are drawn highlighted; any that aren't in the graph (such as the rejected edge
+    /// itself) are also dashed.
+    pub fn write_dot<Out, FN, FE>(&self, out: &mut Out, roots: &[NodeHandle<N, E>], node_label: FN, edge_label: FE, highlight: &[FullEdge<N, E>]) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> String, FE: Fn(&FullEdge<N, E>) -> String
+    {
+        self.dag.write_dot(out, roots, node_label, edge_label, highlight)
+    }
+    /// Remove every edge entering `node`, so that its parents no longer lead to it.
+    /// Returns the removed edges (which keep the parents alive until dropped).
+    /// Like `parents`, this panics if `node` belongs to another graph.
+    pub fn detach_from_parents(&mut self, node: &NodeHandle<N, E>) -> Vec<FullEdge<N, E>> {
+        self.dag.detach_from_parents(node)
+    }
+    /// Apply every modification in `batch` as a single step (see `EdgeBatch`).
+    /// If the result would contain a 0-cycle, the graph is left untouched and the error holds one
+    /// such cycle.
+    pub fn apply_batch(&mut self, batch: EdgeBatch<NodeHandle<N, E>, E>) -> Result<(), DagError<FullEdge<N, E>>> {
+        let applied = self.dag.apply_batch_unchecked(batch)?;
+        // As in add_edge: any new 0-cycle must pass through one of the new edges.
+        let cycle = applied.added.iter().filter_map(|edge| self.zero_cost_cycle_through(edge)).next();
+        match cycle {
+            Some(cycle) => {
+                self.dag.revert_batch(applied);
+                Err(DagError::ZeroCostCycle(cycle))
+            },
+            None => Ok(())
+        }
+    }
+    /// Return true if `to` is reachable from (or is) `from`.
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn is_reachable(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> bool {
+        self.find_path(from, to).is_some()
+    }
+    /// Find a path that leads from `from` to `to`.
+    /// Returns the edges along that path (empty if `from == to`), or None if `to` isn't reachable
+    /// from `from`.
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn find_path(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> Option<Vec<FullEdge<N, E>>> {
+        self.dag.find_path(to, from)
+    }
+    /// Return true if `add_edge(from, to, data)` would be rejected for closing a 0-cycle.
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn would_create_cycle(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, data: E) -> bool {
+        from.check_owner(&self.dag);
+        to.check_owner(&self.dag);
+        self.zero_cost_cycle_through(&FullEdge::new(from.clone(), HalfEdge::new(to.clone(), data))).is_some()
+    }
+    /// Replace the edge `from` -> `to` weighted `old` with one weighted `new`.
+    /// The new weight may make the edge 0-cost, so it's checked as in `add_edge`; if it would
+    /// close a 0-cycle, the old edge is left in place and the error holds that cycle.
+    pub fn set_edge_weight(&mut self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, old: E, new: E) -> Result<(), DagError<FullEdge<N, E>>> {
+        let mut batch = EdgeBatch::new();
+        batch.rm_edge(from.clone(), to.clone(), old).add_edge(from.clone(), to.clone(), new);
+        self.apply_batch(batch)
+    }
+    /// If `edge` (whether or not it's actually in the graph) would lie on a 0-cycle, return that
+    /// cycle, starting with `edge`.
+    fn zero_cost_cycle_through(&self, edge: &FullEdge<N, E>) -> Option<Vec<FullEdge<N, E>>> {
+        self.dag.zero_cost_cycle_through(edge, None, |edge, next| E::is_zero_cost(edge, next, self))
     }
 }
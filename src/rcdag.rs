@@ -1,41 +1,92 @@
+use std::io::{self, Write};
+
+use super::batch::EdgeBatch;
+use super::error::DagError;
 use super::ondag::OnDag;
 use super::rcdagbase::RcDagBase;
+use super::snapshot::DagSnapshot;
 
-pub use super::rcdagbase::{HalfEdge, NodeHandle, WeakNodeHandle};
+pub use super::rcdagbase::{HalfEdge, FullEdge, IterTopo, IterTopoRev, NodeHandle, WeakNodeHandle};
 
 
 
 /// Note: these graphs don't necessarily have explicit roots. It's the user's job to keep handles
 /// to root nodes in order to iterate them, etc.
+///
+/// A topological order of the nodes is maintained online (Pearce-Kelly), so that inserting an
+/// edge which already agrees with the order needs no search, and reachability queries between
+/// nodes that the order puts the wrong way around are answered immediately. Removing edges or
+/// dropping nodes never invalidates the order, so those need no repair.
+/// Edges are also linked up into chains as they're inserted (each node can continue one chain
+/// through one of its edges), labelled such that a node reaches everything after it on its chain
+/// without a search. Removing a chain's edge splits the chain, relabelling the shorter part.
+/// Dropping a node needs no repair, since nothing before it on its chain can still be alive.
+/// Any other query searches only the nodes ordered between its endpoints, & stops as soon as it
+/// meets the chain leading to its target.
 pub struct RcDag<N, E> {
     dag: RcDagBase<N, E>,
 }
 
-impl <N, E : Eq> OnDag<N, E> for RcDag<N, E> {
+impl <N, E : Eq + Clone> OnDag<N, E> for RcDag<N, E> {
     type NodeHandle = NodeHandle<N, E>;
+    type Edge = FullEdge<N, E>;
     fn add_node(&mut self, node_data: N) -> Self::NodeHandle {
         self.dag.add_node(node_data)
     }
-    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(),()> {
+    fn add_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>> {
         // the edge must connect two nodes owned by *this* graph.
-        // NOTE: if to IS reachable from from, then &from and &to are at least in the same graph
-        // (though not neccessarily this one). TODO: It *would* be better to make this assertion
-        // unconditionally.
-        // assert_eq!(from.owner, self as *const Self);
-        // assert_eq!(to.owner, self as *const Self);
-        if self.dag.is_reachable(&from, &to) {
-            // there is a path from `to` to `from`, so adding an edge `from` -> `to` will introduce
-            // a cycle.
-            Err(())
-        } else {
-            // add the parent -> child link:
-            self.dag.add_edge_unchecked(from, to, data);
+        if !self.dag.owns(from) || !self.dag.owns(to) {
+            return Err(DagError::ForeignHandle);
+        }
+        let edge = FullEdge::new(from.clone(), HalfEdge::new(to.clone(), data));
+        match self.cycle_through(&edge) {
+            Some(cycle) => Err(DagError::Cycle(cycle)),
+            None => {
+                self.dag.order_edge(from, to);
+                // add the parent -> child link:
+                self.dag.add_edge_unchecked(from, to, edge.weight().clone());
+                self.dag.link_chain(from, to);
+                Ok(())
+            }
+        }
+    }
+    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), DagError<Self::Edge>> {
+        if !self.dag.owns(from) || !self.dag.owns(to) {
+            Err(DagError::ForeignHandle)
+        } else if self.dag.rm_edge(from, to, data) {
             Ok(())
+        } else {
+            Err(DagError::NoSuchEdge)
         }
     }
-    fn rm_edge(&mut self, from: &Self::NodeHandle, to: &Self::NodeHandle, data: E) -> Result<(), ()> {
-        self.dag.rm_edge(from, to, data);
-        Ok(())
+    fn iter_topo(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        self.dag.iter_topo(from)
+    }
+    fn iter_topo_rev(&self, from: &Self::NodeHandle) -> impl Iterator<Item=Self::NodeHandle> {
+        self.dag.iter_topo_rev(from)
+    }
+    fn children(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        let from = node.clone();
+        self.dag.children(node).map(move |half| FullEdge::new(from.clone(), half))
+    }
+    fn parents(&self, node: &Self::NodeHandle) -> impl Iterator<Item=Self::Edge> {
+        self.dag.parents(node)
+    }
+    fn contains(&self, node: &Self::NodeHandle) -> bool {
+        // A live handle keeps its node alive, so ownership is all that needs checking.
+        self.dag.owns(node)
+    }
+    fn node_count(&self) -> usize {
+        self.dag.node_count()
+    }
+    fn edge_count(&self) -> usize {
+        self.dag.edge_count()
+    }
+}
+
+impl <N, E : Eq> Default for RcDag<N, E> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -45,16 +96,122 @@ impl <N, E : Eq> RcDag<N, E> {
             dag: RcDagBase::new()
         }
     }
-    pub fn iter_topo(&self, from: &NodeHandle<N, E>) -> impl Iterator<Item=NodeHandle<N, E>> {
+    /// Lazily iterate `from` and every node reachable from it, parents before children.
+    /// Unlike `OnDag::iter_topo`, the iterator's type can be named (e.g. to store it in a struct).
+    /// It holds handles to the nodes it has yet to yield, so it doesn't borrow the graph; edges
+    /// modified during iteration may or may not be reflected.
+    pub fn iter_topo(&self, from: &NodeHandle<N, E>) -> IterTopo<N, E> {
         self.dag.iter_topo(from)
     }
-    pub fn iter_topo_rev(&self, from: &NodeHandle<N, E>) -> impl Iterator<Item=NodeHandle<N, E>> {
+    /// Lazily iterate `from` and every node reachable from it, children before parents (as for
+    /// `iter_topo`).
+    pub fn iter_topo_rev(&self, from: &NodeHandle<N, E>) -> IterTopoRev<N, E> {
         self.dag.iter_topo_rev(from)
     }
 }
 
 impl <N, E : Eq + Clone> RcDag<N, E> {
-    pub fn children(&self, node: &NodeHandle<N, E>) -> impl Iterator<Item=HalfEdge<N, E>> {
-        self.dag.children(node)
+    /// Copy every node & edge reachable from `roots` (e.g. in order to serialize them).
+    /// The copy can be loaded into a new graph via `DagSnapshot::restore`.
+    pub fn snapshot(&self, roots: &[NodeHandle<N, E>]) -> Result<DagSnapshot<N, E>, DagError<FullEdge<N, E>>>
+        where N: Clone
+    {
+        self.dag.snapshot(roots)
+    }
+    /// Write every node & edge reachable from `roots` in Graphviz DOT format.
+    /// `node_label` & `edge_label` format the labels of each node & edge (e.g. from the node's
+    /// data & the edge's weight). The edges in `highlight` (e.g. the cycle returned by a rejected
+    /// insertion) are drawn highlighted; any that aren't in the graph (such as the rejected edge
+    /// itself) are also dashed.
+    pub fn write_dot<Out, FN, FE>(&self, out: &mut Out, roots: &[NodeHandle<N, E>], node_label: FN, edge_label: FE, highlight: &[FullEdge<N, E>]) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> String, FE: Fn(&FullEdge<N, E>) -> String
+    {
+        self.dag.write_dot(out, roots, node_label, edge_label, highlight)
+    }
+    /// Remove every edge entering `node`, so that its parents no longer lead to it.
+    /// Returns the removed edges (which keep the parents alive until dropped).
+    /// Like `parents`, this panics if `node` belongs to another graph.
+    pub fn detach_from_parents(&mut self, node: &NodeHandle<N, E>) -> Vec<FullEdge<N, E>> {
+        self.dag.detach_from_parents(node)
+    }
+    /// Apply every modification in `batch` as a single step (see `EdgeBatch`).
+    /// If the result would contain a cycle, the graph is left untouched and the error holds one
+    /// such cycle.
+    pub fn apply_batch(&mut self, batch: EdgeBatch<NodeHandle<N, E>, E>) -> Result<(), DagError<FullEdge<N, E>>> {
+        let applied = self.dag.apply_batch_unchecked(batch)?;
+        // Any new cycle must pass through one of the new edges.
+        let cycle = applied.added.iter().filter_map(|edge| self.cycle_through(edge)).next();
+        match cycle {
+            Some(cycle) => {
+                self.dag.revert_batch(applied);
+                Err(DagError::Cycle(cycle))
+            },
+            None => {
+                // The new edges that disagree with the order were inserted without updating it, so
+                // take them back out & insert them one at a time, which is always valid since the
+                // end result is acyclic.
+                let unordered = applied.added.iter().filter(|edge| {
+                    self.dag.order(edge.from()) > self.dag.order(edge.to())
+                }).cloned().collect::<Vec<_>>();
+                for edge in unordered.iter() {
+                    self.dag.rm_edge(edge.from(), edge.to(), edge.weight().clone());
+                }
+                for edge in unordered {
+                    self.dag.order_edge(edge.from(), edge.to());
+                    self.dag.add_edge_unchecked(edge.from(), edge.to(), edge.weight().clone());
+                }
+                for edge in applied.added.iter() {
+                    self.dag.link_chain(edge.from(), edge.to());
+                }
+                Ok(())
+            }
+        }
+    }
+    /// Return true if `to` is reachable from (or is) `from`.
+    /// This is answered without a search if the order puts `to` before `from`, or if `to` follows
+    /// `from` on a chain (see `RcDag`).
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn is_reachable(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> bool {
+        from.check_owner(&self.dag);
+        to.check_owner(&self.dag);
+        self.dag.chain_reaches(from, to) || self.find_path(from, to).is_some()
+    }
+    /// Find a path that leads from `from` to `to`.
+    /// Returns the edges along that path (empty if `from == to`), or None if `to` isn't reachable
+    /// from `from`.
+    /// Like `iter_topo`, this panics if either node belongs to another graph.
+    pub fn find_path(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> Option<Vec<FullEdge<N, E>>> {
+        from.check_owner(&self.dag);
+        to.check_owner(&self.dag);
+        let bound = self.dag.order(to);
+        // Every path agrees with the order.
+        if from != to && self.dag.order(from) > bound {
+            return None;
+        }
+        self.dag.find_path_within(to, from, bound, None)
+    }
+    /// Return true if `add_edge(from, to, ..)` would be rejected for closing a cycle.
+    pub fn would_create_cycle(&self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>) -> bool {
+        self.is_reachable(to, from)
+    }
+    /// Replace the edge `from` -> `to` weighted `old` with one weighted `new`.
+    /// The endpoints don't change, so this can't create a cycle.
+    pub fn set_edge_weight(&mut self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, old: E, new: E) -> Result<(), DagError<FullEdge<N, E>>> {
+        let mut batch = EdgeBatch::new();
+        batch.rm_edge(from.clone(), to.clone(), old).add_edge(from.clone(), to.clone(), new);
+        self.dag.apply_batch_unchecked(batch)?;
+        // Removing the old edge may have split the chain running along it.
+        self.dag.link_chain(from, to);
+        Ok(())
+    }
+    /// If `edge` (whether or not it's actually in the graph) would lie on a cycle, return that
+    /// cycle, starting with `edge`.
+    fn cycle_through(&self, edge: &FullEdge<N, E>) -> Option<Vec<FullEdge<N, E>>> {
+        // a path from `to` to `from` means that the edge `from` -> `to` closes a cycle.
+        self.find_path(edge.to(), edge.from()).map(|path| {
+            let mut cycle = vec![edge.clone()];
+            cycle.extend(path);
+            cycle
+        })
     }
 }
@@ -1,17 +1,29 @@
-/// Base functions for implementing *various* DAG types on top of a Rc Node format.
+//! Base functions for implementing *various* DAG types on top of a reference-counted Node format.
+//! Nodes are shared through the pointer type `P` (see `NodePtr`): `Rc` for `RcDag` & `PosCostDag`,
+//! or `Arc` for the thread-safe `ArcDag` & `ArcPosCostDag`.
 
-use std::cell::RefCell;
-use std::collections::HashSet;
+use std::cell::Ref;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
 use std::marker::PhantomData;
-use std::rc::{Rc, Weak};
-use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::mem;
+use std::sync::{Arc, RwLockReadGuard};
+use std::vec;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use super::batch::EdgeBatch;
+use super::dot::{self, DotEdge, EdgeStyle};
+use super::error::DagError;
+use super::nodeptr::{Local, NodePtr, Shared};
+use super::snapshot::DagSnapshot;
 
 /// Each DAG is given an ID upon creation to ensure nodes aren't intermixed between DAGs.
-static NEXT_DAG_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+static NEXT_DAG_ID: AtomicUsize = AtomicUsize::new(0);
 
-pub struct NodeHandle<N, E> {
-    node: Rc<RefCell<DagNode<N, E>>>,
+pub struct NodeHandle<N, E, P: NodePtr = Local> {
+    node: P::Strong<DagNode<N, E, P>>,
     // keep a pointer to the tree owner to enforce mutability rules across multiple trees.
     //owner: *const RcDagBase<N, E>,
     /// Associate with the owning tree to enforce mutability rules across multiple trees.
@@ -21,8 +33,8 @@ pub struct NodeHandle<N, E> {
 /// allows to uniquely identify a node, but without keeping it alive.
 /// The primary use-case for this is in a map structure where the client maps
 /// Node -> {data}, but wants to not keep the data alive if the node dies.
-pub struct WeakNodeHandle<N, E> {
-    node: Weak<RefCell<DagNode<N, E>>>,
+pub struct WeakNodeHandle<N, E, P: NodePtr = Local> {
+    node: P::Weak<DagNode<N, E, P>>,
     // need to preserve the raw ptr address for hashing, since we can't extract
     // *any* information from a dead Weak pointer.
     // TODO: Even a dead weak pointer has Shared memory allocation for the counts -
@@ -30,23 +42,127 @@ pub struct WeakNodeHandle<N, E> {
     //   the node_ptr separately.
     // NOTE: We need to store more than just the raw pointer because the memory
     // location of a pointer can be reused after the Rc dies.
-    node_ptr: *const RefCell<DagNode<N, E>>,
+    node_ptr: usize,
 }
 
-pub struct HalfEdge<N, E> {
-    to: NodeHandle<N, E>,
+pub struct HalfEdge<N, E, P: NodePtr = Local> {
+    to: NodeHandle<N, E, P>,
     weight: E,
 }
 
 /// hold all information related to an edge: its source, destination and weight.
-pub struct FullEdge<N, E> {
-    half: HalfEdge<N, E>,
-    from: NodeHandle<N, E>,
+pub struct FullEdge<N, E, P: NodePtr = Local> {
+    half: HalfEdge<N, E, P>,
+    from: NodeHandle<N, E, P>,
+}
+
+/// Read access to a node's data (see `NodeHandle::node_data_ref`), for graphs whose nodes are
+/// shared between threads. Other threads can't modify the node while this is held.
+pub struct NodeDataRef<'a, N: 'a, E: 'a> {
+    node: RwLockReadGuard<'a, DagNode<N, E, Shared>>,
 }
 
-struct DagNode<N, E> {
+struct DagNode<N, E, P: NodePtr> {
     value: N,
-    children: HashSet<HalfEdge<N, E>>,
+    children: HashSet<HalfEdge<N, E, P>>,
+    /// Back-references to the nodes with an edge leading here, along with that edge's weight.
+    /// These are weak so that children don't keep their parents alive; entries for parents
+    /// that have since been dropped are skipped (and pruned when possible).
+    parents: Vec<ParentLink<N, E, P>>,
+    /// Label in the topological order that `RcDag` maintains: every edge leads from a lower label
+    /// to a higher one. Other graphs assign it but don't keep it consistent.
+    order: u64,
+    /// The node's place on the chain it belongs to (see `ChainLabel`).
+    chain: ChainLabel<N, E, P>,
+    /// Shared with the owning DAG so it can track how many nodes/edges are alive.
+    stats: Arc<DagStats>,
+}
+
+/// Chains are paths of edges that `RcDag` links up as they're inserted (see
+/// `RcDagBase::link_chain`), so that reachability along them can be read off the labels: a node
+/// reaches (without searching) every node after it on its chain, i.e. each with the same `id` & a
+/// higher `pos`. Each node starts out on a chain of its own, & belongs to a single chain at a time.
+/// Other graphs never link any chains.
+struct ChainLabel<N, E, P: NodePtr> {
+    id: u64,
+    pos: u64,
+    /// The nodes before & after this one on the chain: there's an edge from `prev` to here, & from
+    /// here to `next`. A `prev` that has been dropped leaves this node at the start of the chain.
+    prev: Option<P::Weak<DagNode<N, E, P>>>,
+    next: Option<P::Weak<DagNode<N, E, P>>>,
+}
+
+/// Weak back-reference from a node to one of its parents.
+struct ParentLink<N, E, P: NodePtr> {
+    node: P::Weak<DagNode<N, E, P>>,
+    weight: E,
+}
+
+/// Record of the modifications made by `RcDagBase::apply_batch_unchecked`, used to check the
+/// new edges and, if need be, revert the batch.
+pub(super) struct AppliedBatch<N, E, P: NodePtr> {
+    /// Edges that were inserted (excluding any that were already present).
+    pub(super) added: Vec<FullEdge<N, E, P>>,
+    /// Edges that were removed.
+    removed: Vec<FullEdge<N, E, P>>,
+}
+
+/// The modifications of a batch that has been checked against the graph but not yet applied (see
+/// `RcDagBase::plan_batch`). Searches can be told to see the graph as if it had been applied, so
+/// that the thread-safe graphs can check for cycles before anyone else sees the new edges.
+pub(super) struct PendingBatch<N, E, P: NodePtr> {
+    /// Edges to insert (excluding any that are present & not being removed).
+    pub(super) added: Vec<FullEdge<N, E, P>>,
+    /// The same edges, by source.
+    added_from: EdgesFrom<N, E, P>,
+    removed: HashSet<FullEdge<N, E, P>>,
+}
+
+/// Result of a modification whose errors describe the edges involved (e.g. the cycle that an
+/// insertion would close).
+type EdgeResult<T, N, E, P> = Result<T, DagError<FullEdge<N, E, P>>>;
+
+/// Edges grouped by their source.
+type EdgesFrom<N, E, P> = HashMap<NodeHandle<N, E, P>, Vec<FullEdge<N, E, P>>>;
+
+/// A node on the path being searched by `IterTopoRev`, with the children it has yet to visit.
+type PathEntry<N, E, P> = (NodeHandle<N, E, P>, vec::IntoIter<NodeHandle<N, E, P>>);
+
+/// Lazily iterates the nodes reachable from a root, parents before children (see
+/// `RcDag::iter_topo`).
+/// Kahn's algorithm is used: the nodes reachable from the root are counted up front, along with
+/// how many edges enter each, but each node is only ordered as it's yielded.
+pub struct IterTopo<N, E, P: NodePtr = Local> {
+    /// Nodes whose parents have all been yielded, in the order they became ready.
+    ready: VecDeque<NodeHandle<N, E, P>>,
+    /// Number of edges from reachable nodes yet to be yielded, for each node (by address) that
+    /// isn't ready yet.
+    pending: HashMap<usize, usize>,
+    /// Every reachable node, in the order it was discovered.
+    discovered: Vec<NodeHandle<N, E, P>>,
+    /// Nodes of `discovered` before this index are known not to be pending.
+    next_discovered: usize,
+}
+
+/// Lazily iterates the nodes reachable from a root, children before parents (see
+/// `RcDag::iter_topo_rev`).
+/// This is a depth-first search that yields each node once everything beneath it has been
+/// yielded, keeping its own stack so that arbitrarily deep graphs can be iterated.
+pub struct IterTopoRev<N, E, P: NodePtr = Local> {
+    /// Each entry holds a node on the current path, along with its children that are yet to be
+    /// visited.
+    stack: Vec<PathEntry<N, E, P>>,
+    /// Addresses of the nodes that have been visited. They're marked on the way in, so that
+    /// cycles (which PosCost graphs allow) end the search.
+    marked: HashSet<usize>,
+}
+
+/// Nodes are owned by the client's handles rather than the DAG, so they report their own
+/// creation & destruction here.
+/// These are atomic so that the thread-safe graphs can share them too.
+struct DagStats {
+    nodes: AtomicUsize,
+    edges: AtomicUsize,
 }
 
 // TODO: use a small-size optimized Set, e.g. smallset
@@ -54,204 +170,952 @@ struct DagNode<N, E> {
 
 /// Note: these graphs don't necessarily have explicit roots. It's the user's job to keep handles
 /// to root nodes in order to iterate them, etc.
-pub struct RcDagBase<N, E> {
+pub struct RcDagBase<N, E, P: NodePtr = Local> {
     /// The DAG doesn't actually store any nodes/edges - it just creates them and hands out
     /// handles. PhantomData allows the type parameters to not be used in the struct (just impl)
     /// w/o error.
     node_type: PhantomData<N>,
     edge_type: PhantomData<E>,
+    ptr_type: PhantomData<P>,
     id: usize,
+    stats: Arc<DagStats>,
+    /// Label given to the next node created. Labels of dropped nodes are never reused, but that
+    /// leaves gaps in the order rather than inconsistencies.
+    next_order: AtomicU64,
+    /// Id given to the next chain, whether for a new node or for part of a chain that's split.
+    next_chain: AtomicU64,
 }
 
-impl <N, E : Eq> RcDagBase<N, E> {
-    pub fn add_node(&mut self, node_data: N) -> NodeHandle<N, E> {
-        let handle = NodeHandle::new(self, DagNode::new(node_data));
-        handle
+impl <N, E : Eq, P: NodePtr> RcDagBase<N, E, P> {
+    pub fn add_node(&self, node_data: N) -> NodeHandle<N, E, P> {
+        // The node has no edges yet, so it's free to go last in the order.
+        let order = self.next_order.fetch_add(1, Ordering::SeqCst);
+        let chain = self.next_chain.fetch_add(1, Ordering::SeqCst);
+        NodeHandle::new(self, DagNode::new(node_data, order, chain, self.stats.clone()))
+    }
+    /// Return true if the node was created by *this* graph.
+    pub(super) fn owns(&self, node: &NodeHandle<N, E, P>) -> bool {
+        node.owner_id == self.id
+    }
+    /// Return an error unless both nodes belong to this graph.
+    pub(super) fn check_owners(&self, from: &NodeHandle<N, E, P>, to: &NodeHandle<N, E, P>) -> EdgeResult<(), N, E, P> {
+        if self.owns(from) && self.owns(to) {
+            Ok(())
+        } else {
+            Err(DagError::ForeignHandle)
+        }
+    }
+    /// The node's label in the topological order (see `DagNode::order`).
+    pub(super) fn order(&self, node: &NodeHandle<N, E, P>) -> u64 {
+        node.borrow().order
+    }
+    /// Number of nodes created by this graph that are still alive.
+    pub(super) fn node_count(&self) -> usize {
+        self.stats.nodes.load(Ordering::SeqCst)
+    }
+    /// Number of edges between nodes of this graph that are still alive.
+    pub(super) fn edge_count(&self) -> usize {
+        self.stats.edges.load(Ordering::SeqCst)
     }
-    pub fn add_edge_unchecked(&mut self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, data: E) {
+}
+
+impl <N, E : Eq + Clone, P: NodePtr> RcDagBase<N, E, P> {
+    /// Returns true if the edge wasn't already present.
+    pub fn add_edge_unchecked(&self, from: &NodeHandle<N, E, P>, to: &NodeHandle<N, E, P>, data: E) -> bool {
         // the edge must connect two nodes owned by *this* graph.
-        from.check_owner(&self);
-        to.check_owner(&self);
+        from.check_owner(self);
+        to.check_owner(self);
         // add the parent -> child link:
-        from.node.borrow_mut().children.insert(HalfEdge::new(to.clone(), data));
+        let is_new = from.borrow_mut().children.insert(HalfEdge::new(to.clone(), data.clone()));
+        if is_new {
+            // and the child -> parent back-reference:
+            to.borrow_mut().parents.push(ParentLink {
+                node: P::downgrade(&from.node),
+                weight: data,
+            });
+            self.stats.edges.fetch_add(1, Ordering::SeqCst);
+        }
+        is_new
     }
-    pub fn rm_edge(&mut self, from: &NodeHandle<N, E>, to: &NodeHandle<N, E>, data: E) {
+    /// Returns true if the edge was previously present.
+    pub fn rm_edge(&self, from: &NodeHandle<N, E, P>, to: &NodeHandle<N, E, P>, data: E) -> bool {
         // the edge must belong to *this* graph.
-        from.check_owner(&self);
-        to.check_owner(&self);
+        from.check_owner(self);
+        to.check_owner(self);
         // delete the parent -> child relationship:
         // TODO: should be possible to remove w/o cloning the references.
-        from.node.borrow_mut().children.remove(&HalfEdge::new(to.clone(), data));
+        // Note: the removed edge may hold the last reference to `to`, so don't drop it while
+        // `from` is still borrowed.
+        let removed = from.borrow_mut().children.take(&HalfEdge::new(to.clone(), data));
+        match removed {
+            None => false,
+            Some(edge) => {
+                {
+                    let mut to_node = to.borrow_mut();
+                    let from_ptr = from.addr();
+                    let index = to_node.parents.iter().position(|parent| {
+                        P::weak_addr(&parent.node) == from_ptr && &parent.weight == edge.weight()
+                    });
+                    if let Some(index) = index {
+                        to_node.parents.swap_remove(index);
+                    }
+                }
+                // The chain can't run along an edge that's gone (unless another edge, with a
+                // different weight, still connects the same nodes).
+                let is_link = from.chain_next().as_ref() == Some(to)
+                    && !from.borrow().children.iter().any(|edge| &edge.to == to);
+                if is_link {
+                    self.split_chain(from, to);
+                }
+                self.stats.edges.fetch_sub(1, Ordering::SeqCst);
+                true
+            }
+        }
+    }
+    /// Make every modification in `batch`, without checking for cycles.
+    /// If a removal fails (or a handle belongs to another graph), nothing is modified.
+    /// Otherwise, the returned record can be passed to `revert_batch` to undo the changes.
+    pub(super) fn apply_batch_unchecked(&self, batch: EdgeBatch<NodeHandle<N, E, P>, E>) -> EdgeResult<AppliedBatch<N, E, P>, N, E, P> {
+        let EdgeBatch { removals, additions } = batch;
+        let all_owned = removals.iter().chain(additions.iter()).all(|(from, to, _)| {
+            self.owns(from) && self.owns(to)
+        });
+        if !all_owned {
+            return Err(DagError::ForeignHandle);
+        }
+        let mut applied = AppliedBatch {
+            added: vec![],
+            removed: vec![],
+        };
+        for (from, to, data) in removals {
+            if self.rm_edge(&from, &to, data.clone()) {
+                applied.removed.push(FullEdge::new(from, HalfEdge::new(to, data)));
+            } else {
+                self.revert_batch(applied);
+                return Err(DagError::NoSuchEdge);
+            }
+        }
+        for (from, to, data) in additions {
+            if self.add_edge_unchecked(&from, &to, data.clone()) {
+                applied.added.push(FullEdge::new(from, HalfEdge::new(to, data)));
+            }
+        }
+        Ok(applied)
+    }
+    /// Undo the modifications made by `apply_batch_unchecked`.
+    pub(super) fn revert_batch(&self, applied: AppliedBatch<N, E, P>) {
+        for edge in applied.added.iter().rev() {
+            self.rm_edge(edge.from(), edge.to(), edge.weight().clone());
+        }
+        for edge in applied.removed.iter().rev() {
+            self.add_edge_unchecked(edge.from(), edge.to(), edge.weight().clone());
+        }
+    }
+    /// Check that every modification in `batch` can be made, without making any of them.
+    /// Returns the modifications for `apply_pending`, which searches can also be told to take
+    /// into account.
+    pub(super) fn plan_batch(&self, batch: EdgeBatch<NodeHandle<N, E, P>, E>) -> EdgeResult<PendingBatch<N, E, P>, N, E, P> {
+        let EdgeBatch { removals, additions } = batch;
+        let all_owned = removals.iter().chain(additions.iter()).all(|(from, to, _)| {
+            self.owns(from) && self.owns(to)
+        });
+        if !all_owned {
+            return Err(DagError::ForeignHandle);
+        }
+        let mut pending = PendingBatch {
+            added: vec![],
+            added_from: HashMap::new(),
+            removed: HashSet::new(),
+        };
+        for (from, to, data) in removals {
+            let edge = FullEdge::new(from, HalfEdge::new(to, data));
+            // Each edge can only be removed once.
+            if !edge.is_present() || !pending.removed.insert(edge) {
+                return Err(DagError::NoSuchEdge);
+            }
+        }
+        for (from, to, data) in additions {
+            let edge = FullEdge::new(from, HalfEdge::new(to, data));
+            let is_new = pending.removed.contains(&edge) || !edge.is_present();
+            let added_from = pending.added_from.entry(edge.from().clone()).or_insert_with(Vec::new);
+            if is_new && !added_from.contains(&edge) {
+                added_from.push(edge.clone());
+                pending.added.push(edge);
+            }
+        }
+        Ok(pending)
+    }
+    /// Make the modifications planned by `plan_batch`: every removal, then every insertion.
+    /// Until it's done, the graph is a subgraph of either what it was or what it will be, so
+    /// anyone reading it meanwhile never sees a cycle that neither of those has.
+    pub(super) fn apply_pending(&self, pending: PendingBatch<N, E, P>) {
+        for edge in pending.removed.iter() {
+            self.rm_edge(edge.from(), edge.to(), edge.weight().clone());
+        }
+        for edge in pending.added {
+            self.add_edge_unchecked(edge.from(), edge.to(), edge.weight().clone());
+        }
+    }
+    /// iterate all of the incoming edges of this node.
+    pub(super) fn parents(&self, node: &NodeHandle<N, E, P>) -> impl Iterator<Item=FullEdge<N, E, P>> {
+        // we must own the node of interest.
+        node.check_owner(self);
+        let parents = node.borrow().parents.iter().filter_map(|parent| {
+            // skip parents that have been dropped.
+            P::upgrade(&parent.node).map(|parent_node| {
+                let parent_handle = NodeHandle { node: parent_node, owner_id: node.owner_id };
+                FullEdge::new(parent_handle, HalfEdge::new(node.clone(), parent.weight.clone()))
+            })
+        }).collect::<Vec<_>>();
+        parents.into_iter()
+    }
+    /// Remove every edge entering `node`, returning the removed edges.
+    pub(super) fn detach_from_parents(&self, node: &NodeHandle<N, E, P>) -> Vec<FullEdge<N, E, P>> {
+        let parents = self.parents(node).collect::<Vec<_>>();
+        for edge in parents.iter() {
+            self.rm_edge(edge.from(), edge.to(), edge.weight().clone());
+        }
+        parents
+    }
+    /// Link `from`'s chain up to `to`'s, if `from` ends its chain & `to` starts its own. The edge
+    /// `from` -> `to` must be in the graph (& stays on the chain until it's removed).
+    /// The labels of whichever chain is shorter are rewritten to continue the other.
+    pub(super) fn link_chain(&self, from: &NodeHandle<N, E, P>, to: &NodeHandle<N, E, P>) {
+        let starts_chain = to.borrow().chain.prev.as_ref().is_none_or(P::is_dead);
+        if from.borrow().chain.next.is_some() || !starts_chain || from == to {
+            return;
+        }
+        let (from_id, from_pos) = from.chain_label();
+        let (to_id, to_pos) = to.chain_label();
+        if Self::is_chain_shorter(from, to) {
+            Self::relabel_chain(from, false, to_id, Some(to_pos - 1));
+        } else {
+            Self::relabel_chain(to, true, from_id, Some(from_pos + 1));
+        }
+        from.borrow_mut().chain.next = Some(P::downgrade(&to.node));
+        to.borrow_mut().chain.prev = Some(P::downgrade(&from.node));
+    }
+    /// Split the chain between `from` & `to`, which come one after the other on it, giving
+    /// whichever part is shorter a new id.
+    fn split_chain(&self, from: &NodeHandle<N, E, P>, to: &NodeHandle<N, E, P>) {
+        from.borrow_mut().chain.next = None;
+        to.borrow_mut().chain.prev = None;
+        let id = self.next_chain.fetch_add(1, Ordering::SeqCst);
+        if Self::is_chain_shorter(from, to) {
+            Self::relabel_chain(from, false, id, None);
+        } else {
+            Self::relabel_chain(to, true, id, None);
+        }
+    }
+    /// Return true if the part of the chain up to `before` is shorter than the part from `after`
+    /// on. Both are walked in step, so this takes as long as the shorter one.
+    fn is_chain_shorter(before: &NodeHandle<N, E, P>, after: &NodeHandle<N, E, P>) -> bool {
+        let (mut before, mut after) = (before.clone(), after.clone());
+        loop {
+            match (before.chain_prev(), after.chain_next()) {
+                (None, _) => return true,
+                (_, None) => return false,
+                (Some(prev), Some(next)) => {
+                    before = prev;
+                    after = next;
+                },
+            }
+        }
+    }
+    /// Move `start` & every node after it (or before it, unless `forward`) on its chain to the
+    /// chain `id`. If `pos` is given, the nodes are renumbered from there, counting away from
+    /// `start`; otherwise they keep their positions.
+    fn relabel_chain(start: &NodeHandle<N, E, P>, forward: bool, id: u64, pos: Option<u64>) {
+        let mut node = Some(start.clone());
+        let mut distance = 0;
+        while let Some(current) = node {
+            {
+                let mut node = current.borrow_mut();
+                node.chain.id = id;
+                if let Some(pos) = pos {
+                    node.chain.pos = if forward { pos + distance } else { pos - distance };
+                }
+            }
+            node = if forward { current.chain_next() } else { current.chain_prev() };
+            distance += 1;
+        }
+    }
+    /// Return true if `to` is `from` or comes after it on its chain, so that it's reachable
+    /// without searching.
+    pub(super) fn chain_reaches(&self, from: &NodeHandle<N, E, P>, to: &NodeHandle<N, E, P>) -> bool {
+        let (from_id, from_pos) = from.chain_label();
+        let (to_id, to_pos) = to.chain_label();
+        from_id == to_id && from_pos <= to_pos
+    }
+    /// The edges along the chain from `from` to `to`, which `chain_reaches`.
+    fn chain_path(&self, from: &NodeHandle<N, E, P>, to: &NodeHandle<N, E, P>) -> Vec<FullEdge<N, E, P>> {
+        let mut path = vec![];
+        let mut node = from.clone();
+        while &node != to {
+            let next = node.chain_next().expect("chain ended early");
+            let half = node.borrow().children.iter().find(|edge| edge.to == next).cloned().expect("chain link isn't an edge");
+            path.push(FullEdge::new(node, half));
+            node = next;
+        }
+        path
     }
 }
 
-impl <N, E: Eq> RcDagBase<N, E> {
-    /// Return true if and only if `search` is reachable from (or is equal to) `base`
-    pub(super) fn is_reachable(&self, search: &NodeHandle<N, E>, base: &NodeHandle<N, E>) -> bool {
-        (base == search) || base.node.borrow().children.iter().any(|ch| {
-            self.is_reachable(search, &ch.to)
+impl <N, E: Eq + Clone, P: NodePtr> RcDagBase<N, E, P> {
+    /// Find a path that leads from `base` to `search`.
+    /// Returns the edges along that path (empty if `base == search`), or None if `search` isn't
+    /// reachable from `base`.
+    /// Panics if either node belongs to another graph.
+    pub(super) fn find_path(&self, search: &NodeHandle<N, E, P>, base: &NodeHandle<N, E, P>) -> Option<Vec<FullEdge<N, E, P>>> {
+        self.find_path_within(search, base, u64::MAX, None)
+    }
+    /// Like `find_path`, but without passing through any node whose label exceeds `bound`, and
+    /// treating the graph as if `pending` had been applied.
+    /// When the order is consistent, no path to `search` passes through such a node if `bound` is
+    /// the label of `search`.
+    pub(super) fn find_path_within(&self, search: &NodeHandle<N, E, P>, base: &NodeHandle<N, E, P>, bound: u64, pending: Option<&PendingBatch<N, E, P>>) -> Option<Vec<FullEdge<N, E, P>>> {
+        search.check_owner(self);
+        base.check_owner(self);
+        let mut path = vec![];
+        if self.find_path_into(search, base, bound, pending, &mut path, &mut HashSet::new()) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+    /// Helper for `find_path`: `path` holds the edges leading up to `base`, and is left holding
+    /// the full path if (and only if) this returns true.
+    /// `visited` holds the nodes already searched, so that each is searched at most once (this
+    /// also guarantees termination for graphs that do contain cycles).
+    /// The search keeps its own stack, so that arbitrarily long paths can be followed.
+    fn find_path_into(&self, search: &NodeHandle<N, E, P>, base: &NodeHandle<N, E, P>, bound: u64, pending: Option<&PendingBatch<N, E, P>>, path: &mut Vec<FullEdge<N, E, P>>, visited: &mut HashSet<usize>) -> bool {
+        // The search can stop short wherever it meets the chain leading to `search`, unless the
+        // chain runs along an edge that's about to be removed.
+        let use_chains = pending.is_none();
+        if base == search || (use_chains && self.chain_reaches(base, search)) {
+            path.extend(self.chain_path(base, search));
+            return true;
+        }
+        if !visited.insert(base.addr()) {
+            return false;
+        }
+        // Each entry holds the edges leaving a node on the current path that are yet to be tried.
+        let mut stack = vec![Self::out_edges(base, pending)];
+        while let Some(edges) = stack.last_mut() {
+            match edges.next() {
+                Some(edge) => {
+                    if edge.to() == search || (use_chains && self.chain_reaches(edge.to(), search)) {
+                        let rest = self.chain_path(edge.to(), search);
+                        path.push(edge);
+                        path.extend(rest);
+                        return true;
+                    }
+                    if self.order(edge.to()) <= bound && visited.insert(edge.to().addr()) {
+                        stack.push(Self::out_edges(edge.to(), pending));
+                        path.push(edge);
+                    }
+                },
+                None => {
+                    // Every edge from this node is a dead end, so back up.
+                    stack.pop();
+                    if !stack.is_empty() {
+                        path.pop();
+                    }
+                }
+            }
+        }
+        false
+    }
+    /// Copy the edges leaving `node` (as they'd be once `pending` is applied), so that they can be
+    /// iterated without borrowing it.
+    fn out_edges(node: &NodeHandle<N, E, P>, pending: Option<&PendingBatch<N, E, P>>) -> vec::IntoIter<FullEdge<N, E, P>> {
+        let mut edges = node.borrow().children.iter().map(|ch| FullEdge::new(node.clone(), ch.clone())).collect::<Vec<_>>();
+        if let Some(pending) = pending {
+            edges.retain(|edge| !pending.removed.contains(edge));
+            edges.extend(pending.added_from.get(node).into_iter().flatten().cloned());
+        }
+        edges.into_iter()
+    }
+    /// If `edge` (whether or not it's actually in the graph) would lie on a 0-cycle, return that
+    /// cycle, starting with `edge`. The graph is treated as if `pending` had been applied.
+    /// `is_zero_cost(edge, next)` tells whether traversing `edge` on the way to `next` is free (see
+    /// `poscostdag::CostQueriable`).
+    pub(super) fn zero_cost_cycle_through<F>(&self, edge: &FullEdge<N, E, P>, pending: Option<&PendingBatch<N, E, P>>, is_zero_cost: F) -> Option<Vec<FullEdge<N, E, P>>>
+        where F: Fn(&HalfEdge<N, E, P>, &HalfEdge<N, E, P>) -> bool
+    {
+        let mut path = vec![];
+        if self.zero_cost_path(edge, edge, pending, &is_zero_cost, &mut path, &mut HashSet::new()) {
+            let mut cycle = vec![edge.clone()];
+            cycle.extend(path);
+            Some(cycle)
+        } else {
+            None
+        }
+    }
+    /// Search for a 0-cost path that leads from the edge `base` back to the edge `search`.
+    /// `path` holds the edges traversed after `base`; if this returns true, it is left holding
+    /// every edge up to (but excluding) `search`.
+    /// `visited` holds the edges already searched from, so that each is searched at most once
+    /// (this also guarantees termination when there are other 0-cycles, which can be the case
+    /// while applying a batch).
+    /// The search keeps its own stack, so that arbitrarily long paths can be followed.
+    fn zero_cost_path<F>(&self, search: &FullEdge<N, E, P>, base: &FullEdge<N, E, P>, pending: Option<&PendingBatch<N, E, P>>, is_zero_cost: &F, path: &mut Vec<FullEdge<N, E, P>>, visited: &mut HashSet<FullEdge<N, E, P>>) -> bool
+        where F: Fn(&HalfEdge<N, E, P>, &HalfEdge<N, E, P>) -> bool
+    {
+        // Each entry holds an edge on the current path, along with the edges that may follow it
+        // that are yet to be tried.
+        let mut stack = vec![(base.clone(), Self::successors(search, base, pending))];
+        while let Some(&mut (ref prev, ref mut successors)) = stack.last_mut() {
+            match successors.next() {
+                Some(edge) => {
+                    let is_this_edge_0 = is_zero_cost(prev.half(), edge.half());
+                    if is_this_edge_0 && &edge == search {
+                        return true;
+                    }
+                    if is_this_edge_0 && visited.insert(edge.clone()) {
+                        path.push(edge.clone());
+                        let successors = Self::successors(search, &edge, pending);
+                        stack.push((edge, successors));
+                    }
+                },
+                None => {
+                    // Every edge after this one is a dead end, so back up.
+                    stack.pop();
+                    if !stack.is_empty() {
+                        path.pop();
+                    }
+                }
+            }
+        }
+        false
+    }
+    /// The edges that may follow `base` while searching for `search`: those leaving its
+    /// destination, plus `search` itself if it leaves there too (since it may not be in the graph
+    /// yet).
+    fn successors(search: &FullEdge<N, E, P>, base: &FullEdge<N, E, P>, pending: Option<&PendingBatch<N, E, P>>) -> vec::IntoIter<FullEdge<N, E, P>> {
+        let next = if base.to() == search.from() { Some(search.clone()) } else { None };
+        Self::out_edges(base.to(), pending).chain(next).collect::<Vec<_>>().into_iter()
+    }
+    /// Update the order to account for the edge `from` -> `to`, which is about to be inserted.
+    /// The edge mustn't close a cycle.
+    pub(super) fn order_edge(&self, from: &NodeHandle<N, E, P>, to: &NodeHandle<N, E, P>) {
+        let (from_label, to_label) = (self.order(from), self.order(to));
+        if from_label < to_label {
+            // Already consistent.
+            return;
+        }
+        let forward = self.forward_region(to, from_label);
+        let backward = self.backward_region(from, to_label);
+        self.reorder(backward, forward);
+    }
+    /// Collect the nodes reachable from `start` without passing through any node whose label
+    /// exceeds `bound`.
+    fn forward_region(&self, start: &NodeHandle<N, E, P>, bound: u64) -> Vec<NodeHandle<N, E, P>> {
+        let mut region = vec![start.clone()];
+        let mut visited = HashSet::new();
+        visited.insert(start.addr());
+        let mut index = 0;
+        while index < region.len() {
+            for child in region[index].child_nodes() {
+                if self.order(&child) <= bound && visited.insert(child.addr()) {
+                    region.push(child);
+                }
+            }
+            index += 1;
+        }
+        region
+    }
+    /// Collect the nodes that reach `start` without passing through any node whose label is below
+    /// `bound`. Parents that have been dropped can't be reordered (or reached), so they're skipped.
+    fn backward_region(&self, start: &NodeHandle<N, E, P>, bound: u64) -> Vec<NodeHandle<N, E, P>> {
+        let mut region = vec![start.clone()];
+        let mut visited = HashSet::new();
+        visited.insert(start.addr());
+        let mut index = 0;
+        while index < region.len() {
+            let parents = region[index].borrow().parents.iter().filter_map(|parent| {
+                P::upgrade(&parent.node)
+            }).collect::<Vec<_>>();
+            for parent in parents {
+                let prev = NodeHandle { node: parent, owner_id: start.owner_id };
+                if self.order(&prev) > bound && visited.insert(prev.addr()) {
+                    region.push(prev);
+                }
+            }
+            index += 1;
+        }
+        region
+    }
+    /// Given the regions found while inserting an edge `from -> to`, where `backward` reaches
+    /// `from` and `forward` is reachable from `to`, shuffle the labels they occupy so that all of
+    /// `backward` precedes all of `forward`. Relative order within each region is preserved.
+    fn reorder(&self, mut backward: Vec<NodeHandle<N, E, P>>, mut forward: Vec<NodeHandle<N, E, P>>) {
+        backward.sort_by_key(|node| self.order(node));
+        forward.sort_by_key(|node| self.order(node));
+        let mut labels = backward.iter().chain(forward.iter()).map(|node| self.order(node)).collect::<Vec<_>>();
+        labels.sort();
+        for (node, label) in backward.into_iter().chain(forward).zip(labels) {
+            node.borrow_mut().order = label;
+        }
+    }
+}
+
+impl <N: Clone, E: Eq + Clone, P: NodePtr> RcDagBase<N, E, P> {
+    /// Copy every node & edge reachable from `roots`.
+    /// Nodes are numbered in the order they're discovered (breadth-first), and cycles are fine.
+    pub(super) fn snapshot(&self, roots: &[NodeHandle<N, E, P>]) -> EdgeResult<DagSnapshot<N, E>, N, E, P> {
+        if !roots.iter().all(|root| self.owns(root)) {
+            return Err(DagError::ForeignHandle);
+        }
+        let mut edges = vec![];
+        let (nodes, root_ids) = self.number_reachable(roots, |from, _from_node, edge, to| {
+            edges.push((from, to, edge.weight.clone()));
+        });
+        Ok(DagSnapshot {
+            nodes: nodes.iter().map(|node| node.borrow().value.clone()).collect(),
+            edges,
+            roots: root_ids,
         })
     }
-    /// Compute the topological ordering of `self`.
-    pub(super) fn iter_topo(&self, from: &NodeHandle<N, E>) -> impl Iterator<Item=NodeHandle<N, E>> {
-        // can only iterate over nodes owned by *this* graph.
-        from.check_owner(&self);
-        // just a depth-first sort, but then reverse the results.
-        let mut ordered = vec![];
-        self.depth_first_sort(from, &mut ordered, &mut HashSet::new());
-        // The depth-first ordering goes highest -> least depth, so reverse that.
-        ordered.into_iter().rev()
-    }
-    /// Compute the *reverse* topological ordering of `self`, i.e. children -> root
-    pub(super) fn iter_topo_rev(&self, from: &NodeHandle<N, E>) -> impl Iterator<Item=NodeHandle<N, E>> {
-        // can only iterate over nodes owned by *this* graph.
-        from.check_owner(&self);
-        // just a depth-first sort:
-        // TODO: we can achieve this with lower latency by moving it into an iterator.
-        let mut ordered = vec![];
-        self.depth_first_sort(from, &mut ordered, &mut HashSet::new());
-        // The depth-first ordering goes highest -> least depth
-        ordered.into_iter()
-    }
-    fn depth_first_sort(&self, node: &NodeHandle<N, E>, ordered: &mut Vec<NodeHandle<N, E>>, marked: &mut HashSet<*const DagNode<N, E>>) {
-        if !marked.contains(&(&*node.node.borrow() as *const DagNode<N, E>)) {
-            for edge in node.node.borrow().children.iter() {
-                self.depth_first_sort(&edge.to, ordered, marked);
+}
+
+impl <N, E: Eq + Clone, P: NodePtr> RcDagBase<N, E, P> {
+    /// Write every node & edge reachable from `roots` in DOT format.
+    /// `node_label` & `edge_label` format the labels of each node & edge, and the edges in
+    /// `highlight` are drawn highlighted.
+    pub(super) fn write_dot<Out, FN, FE>(&self, out: &mut Out, roots: &[NodeHandle<N, E, P>], node_label: FN, edge_label: FE, highlight: &[FullEdge<N, E, P>]) -> io::Result<()>
+        where Out: Write, FN: Fn(&N) -> String, FE: Fn(&FullEdge<N, E, P>) -> String
+    {
+        for root in roots {
+            root.check_owner(self);
+        }
+        let mut edges = vec![];
+        let (nodes, _root_numbers) = self.number_reachable(roots, |from, from_node, edge, to| {
+            let edge = FullEdge::new(from_node.clone(), edge.clone());
+            edges.push(DotEdge {
+                from: Some(from as u64),
+                to: Some(to as u64),
+                label: edge_label(&edge),
+                style: EdgeStyle::present(highlight.contains(&edge)),
+            });
+        });
+        // Highlighted edges that aren't in the graph are drawn too, so long as their endpoints are.
+        let numbers = nodes.iter().enumerate().map(|(number, node)| {
+            (node.addr(), number as u64)
+        }).collect::<HashMap<_, _>>();
+        for edge in highlight {
+            let (from, to) = (numbers.get(&edge.from().addr()), numbers.get(&edge.to().addr()));
+            if let (false, Some(from), Some(to)) = (edge.is_present(), from, to) {
+                edges.push(DotEdge {
+                    from: Some(*from),
+                    to: Some(*to),
+                    label: edge_label(edge),
+                    style: EdgeStyle::Absent,
+                });
             }
-            marked.insert(&*node.node.borrow());
-            ordered.push(node.clone());
         }
+        let nodes = nodes.iter().enumerate().map(|(number, node)| {
+            (number as u64, node_label(&node.borrow().value))
+        }).collect();
+        dot::write_graph(out, nodes, edges)
+    }
+    /// Number every node reachable from `roots` (breadth-first, in order of discovery), calling
+    /// `visit_edge(from, from_node, edge, to)` for each edge along the way, where `from` & `to`
+    /// are the numbers of its endpoints.
+    /// Returns the nodes (indexed by number), and the numbers of the roots.
+    fn number_reachable<F>(&self, roots: &[NodeHandle<N, E, P>], mut visit_edge: F) -> (Vec<NodeHandle<N, E, P>>, Vec<usize>)
+        where F: FnMut(usize, &NodeHandle<N, E, P>, &HalfEdge<N, E, P>, usize)
+    {
+        let mut numbers = HashMap::new();
+        let mut nodes = vec![];
+        let root_numbers = roots.iter().map(|root| Self::number_of(root, &mut numbers, &mut nodes)).collect();
+        // `nodes` grows as new children are discovered.
+        let mut index = 0;
+        while index < nodes.len() {
+            let node = nodes[index].clone();
+            let edges = node.borrow().children.iter().cloned().collect::<Vec<_>>();
+            for edge in edges.iter() {
+                let to = Self::number_of(&edge.to, &mut numbers, &mut nodes);
+                visit_edge(index, &node, edge, to);
+            }
+            index += 1;
+        }
+        (nodes, root_numbers)
+    }
+    /// Return the number of `node`, assigning it the next one if it hasn't been seen yet.
+    fn number_of(node: &NodeHandle<N, E, P>, numbers: &mut HashMap<usize, usize>, nodes: &mut Vec<NodeHandle<N, E, P>>) -> usize {
+        let next = nodes.len();
+        let number = *numbers.entry(node.addr()).or_insert(next);
+        if number == next {
+            nodes.push(node.clone());
+        }
+        number
+    }
+}
+
+impl <N, E: Eq, P: NodePtr> RcDagBase<N, E, P> {
+    /// Iterate the topological ordering of the nodes reachable from `from`.
+    pub(super) fn iter_topo(&self, from: &NodeHandle<N, E, P>) -> IterTopo<N, E, P> {
+        // can only iterate over nodes owned by *this* graph.
+        from.check_owner(self);
+        IterTopo::new(from)
+    }
+    /// Iterate the *reverse* topological ordering of the nodes reachable from `from`, i.e.
+    /// children -> root
+    pub(super) fn iter_topo_rev(&self, from: &NodeHandle<N, E, P>) -> IterTopoRev<N, E, P> {
+        // can only iterate over nodes owned by *this* graph.
+        from.check_owner(self);
+        IterTopoRev::new(from)
     }
 }
-impl <N, E: Eq + Clone> RcDagBase<N, E> {
+impl <N, E: Eq + Clone, P: NodePtr> RcDagBase<N, E, P> {
     /// iterate all of the outgoing edges of this node.
-    pub(super) fn children(&self, node: &NodeHandle<N, E>) -> impl Iterator<Item=HalfEdge<N, E>> {
+    pub(super) fn children(&self, node: &NodeHandle<N, E, P>) -> impl Iterator<Item=HalfEdge<N, E, P>> {
         // we must own the node of interest.
-        node.check_owner(&self);
+        node.check_owner(self);
         // TODO: make an iterator object that borrows self & avoids cloning children
-        node.node.borrow().children.clone().into_iter()
+        node.borrow().children.clone().into_iter()
     }
 }
 
-impl <N, E> RcDagBase<N, E> {
+impl <N, E, P: NodePtr> RcDagBase<N, E, P> {
     pub(super) fn new() -> Self {
         RcDagBase {
             node_type: PhantomData,
             edge_type: PhantomData,
+            ptr_type: PhantomData,
             // SeqCst = strongest ordering; will behave intuitively
             // Add 1 so ordering starts at 1 (0=null)
-            id: 1+NEXT_DAG_ID.fetch_add(1, Ordering::SeqCst)
+            id: 1+NEXT_DAG_ID.fetch_add(1, Ordering::SeqCst),
+            stats: Arc::new(DagStats::new()),
+            next_order: AtomicU64::new(0),
+            next_chain: AtomicU64::new(0),
         }
     }
 }
 
-impl<N, E : Eq> DagNode<N, E> {
-    fn new(value: N) -> Self {
+impl<N, E : Eq, P: NodePtr> DagNode<N, E, P> {
+    fn new(value: N, order: u64, chain: u64, stats: Arc<DagStats>) -> Self {
+        stats.nodes.fetch_add(1, Ordering::SeqCst);
         DagNode {
-            value: value,
+            value,
             children: HashSet::new(),
+            parents: Vec::new(),
+            order,
+            // Start in the middle, so that chains can grow in either direction.
+            chain: ChainLabel {
+                id: chain,
+                pos: u64::MAX / 2,
+                prev: None,
+                next: None,
+            },
+            stats,
+        }
+    }
+}
+
+impl<N, E, P: NodePtr> DagNode<N, E, P> {
+    /// Remove every outgoing edge, returning the children they led to.
+    fn take_children(&mut self) -> Vec<NodeHandle<N, E, P>> {
+        self.stats.edges.fetch_sub(self.children.len(), Ordering::SeqCst);
+        mem::take(&mut self.children).into_iter().map(|edge| edge.to).collect()
+    }
+    /// Prune the back-references of each child to parents that have been dropped.
+    /// A child that's currently borrowed (or locked) is left to lazy pruning.
+    fn prune_parents(children: &[NodeHandle<N, E, P>]) {
+        for child in children.iter() {
+            if let Some(mut child) = P::try_borrow_mut(&child.node) {
+                child.parents.retain(|parent| !P::is_dead(&parent.node));
+            }
         }
     }
 }
 
-impl<N, E> Clone for NodeHandle<N, E> {
+impl<N, E, P: NodePtr> Drop for DagNode<N, E, P> {
+    fn drop(&mut self) {
+        self.stats.nodes.fetch_sub(1, Ordering::SeqCst);
+        // This node's strong count is already 0, so this prunes our entries from each child's
+        // back-references.
+        let mut worklist = self.take_children();
+        Self::prune_parents(&worklist);
+        // Dropping a child that nothing else keeps alive would drop its own children, and so on,
+        // recursing as deep as the graph goes. Instead, the children of each such node are taken
+        // out before it's dropped, and dismantled in turn from this worklist.
+        while let Some(node) = worklist.pop() {
+            // If anything else still holds the node, this just releases our reference. Otherwise
+            // the node is taken (such that no other thread can revive it via one of its
+            // children's back-references), and dropped once its children are out.
+            let children = match P::try_unwrap(node.node) {
+                Some(mut node) => node.take_children(),
+                None => continue,
+            };
+            Self::prune_parents(&children);
+            worklist.extend(children);
+        }
+    }
+}
+
+impl<N, E, P: NodePtr> IterTopo<N, E, P> {
+    fn new(root: &NodeHandle<N, E, P>) -> Self {
+        let root_key = root.addr();
+        let mut pending = HashMap::new();
+        pending.insert(root_key, 0);
+        let mut discovered = vec![root.clone()];
+        // `discovered` grows as new children are found.
+        let mut index = 0;
+        while index < discovered.len() {
+            for child in discovered[index].child_nodes() {
+                let count = pending.entry(child.addr()).or_insert(0);
+                if *count == 0 && child != *root {
+                    discovered.push(child);
+                }
+                *count += 1;
+            }
+            index += 1;
+        }
+        let mut ready = VecDeque::new();
+        if pending[&root_key] == 0 {
+            pending.remove(&root_key);
+            ready.push_back(root.clone());
+        }
+        IterTopo {
+            ready,
+            pending,
+            discovered,
+            next_discovered: 0,
+        }
+    }
+}
+
+impl<N, E, P: NodePtr> Iterator for IterTopo<N, E, P> {
+    type Item = NodeHandle<N, E, P>;
+    fn next(&mut self) -> Option<NodeHandle<N, E, P>> {
+        if self.ready.is_empty() {
+            // Every node left lies on or beyond a cycle (which PosCost graphs allow), so none will
+            // ever be ready; yield the earliest discovered of them as if it were.
+            while self.next_discovered < self.discovered.len() {
+                let node = self.discovered[self.next_discovered].clone();
+                self.next_discovered += 1;
+                if self.pending.remove(&node.addr()).is_some() {
+                    self.ready.push_back(node);
+                    break;
+                }
+            }
+        }
+        let node = self.ready.pop_front()?;
+        for child in node.child_nodes() {
+            let key = child.addr();
+            // Nodes that were yielded early to break a cycle are no longer pending.
+            if let Some(count) = self.pending.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    self.pending.remove(&key);
+                    self.ready.push_back(child);
+                }
+            }
+        }
+        Some(node)
+    }
+}
+
+impl<N, E, P: NodePtr> IterTopoRev<N, E, P> {
+    fn new(root: &NodeHandle<N, E, P>) -> Self {
+        let mut marked = HashSet::new();
+        marked.insert(root.addr());
+        IterTopoRev {
+            stack: vec![(root.clone(), root.child_nodes())],
+            marked,
+        }
+    }
+}
+
+impl<N, E, P: NodePtr> Iterator for IterTopoRev<N, E, P> {
+    type Item = NodeHandle<N, E, P>;
+    fn next(&mut self) -> Option<NodeHandle<N, E, P>> {
+        while let Some(&mut (_, ref mut children)) = self.stack.last_mut() {
+            match children.next() {
+                Some(child) => {
+                    if self.marked.insert(child.addr()) {
+                        let grandchildren = child.child_nodes();
+                        self.stack.push((child, grandchildren));
+                    }
+                },
+                // Everything beneath the node has been yielded, so it can follow.
+                None => return self.stack.pop().map(|(node, _)| node),
+            }
+        }
+        None
+    }
+}
+
+impl DagStats {
+    fn new() -> Self {
+        DagStats {
+            nodes: AtomicUsize::new(0),
+            edges: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<N, E, P: NodePtr> Clone for NodeHandle<N, E, P> {
     fn clone(&self) -> Self {
         NodeHandle {
-            node: self.node.clone(),
+            node: P::clone(&self.node),
             owner_id: self.owner_id,
         }
     }
 }
 
-impl<N, E> Hash for NodeHandle<N, E> {
+impl<N, E, P: NodePtr> Hash for NodeHandle<N, E, P> {
     fn hash<H>(&self, state: &mut H)  where H: Hasher {
-        (&*self.node as *const RefCell<DagNode<N, E>>).hash(state)
+        self.addr().hash(state)
     }
 }
-impl<N, E> PartialEq for NodeHandle<N, E> {
+impl<N, E, P: NodePtr> PartialEq for NodeHandle<N, E, P> {
     fn eq(&self, other: &Self) -> bool {
-        Rc::ptr_eq(&self.node, &other.node)
-        //&*self.node as *const RefCell<DagNode<N, E>> == &*other.node as *const RefCell<DagNode<N, E>>
+        self.addr() == other.addr()
     }
 }
-impl<N, E> Eq for NodeHandle<N, E> {}
+impl<N, E, P: NodePtr> Eq for NodeHandle<N, E, P> {}
 
-impl<N, E> NodeHandle<N, E> {
-    fn new(owner: &RcDagBase<N, E>, node: DagNode<N, E>) -> Self {
+// Nodes are identified by address; this avoids requiring N : Debug.
+impl<N, E, P: NodePtr> fmt::Debug for NodeHandle<N, E, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NodeHandle({:#x})", self.addr())
+    }
+}
+
+impl<N, E, P: NodePtr> NodeHandle<N, E, P> {
+    fn new(owner: &RcDagBase<N, E, P>, node: DagNode<N, E, P>) -> Self {
         NodeHandle {
-            node: Rc::new(RefCell::new(node)),
+            node: P::new(node),
             owner_id: owner.id,
         }
     }
 }
-impl<N: Default, E: Eq> NodeHandle<N, E> {
+impl<N: Default, E: Eq, P: NodePtr> NodeHandle<N, E, P> {
     /// Create a null handle that can be used instead by clients in place of Option<>.
     /// Note: trying to use this in a query to a tree that expects a non-null NodeHandle WILL
     /// error.
     pub fn null() -> Self {
         NodeHandle {
-            node: Rc::new(RefCell::new(
-                          DagNode::new(Default::default())
-            )),
+            node: P::new(DagNode::new(Default::default(), 0, 0, Arc::new(DagStats::new()))),
             owner_id: 0,
         }
     }
 }
 
-impl<N : Clone, E> NodeHandle<N, E> {
+impl<N : Clone, E, P: NodePtr> NodeHandle<N, E, P> {
     /// Access the node's data via cloning it (potentially costly). Doesn't require a ref to the tree.
     pub fn node_data(&self) -> N {
-        self.node.borrow().value.clone()
+        self.borrow().value.clone()
+    }
+}
+
+/// Access to the node's data without copying it. Like `node_data`, these don't require a ref to
+/// the tree, since the data isn't part of the graph's structure.
+/// The node is borrowed for as long as the guard (or closure) lives: modifying its edges in the
+/// meantime, or accessing its data mutably while it's borrowed elsewhere, panics (or, for nodes
+/// shared between threads, blocks until the other borrow ends).
+impl<N, E, P: NodePtr> NodeHandle<N, E, P> {
+    pub fn with_data<F, R>(&self, f: F) -> R
+        where F: FnOnce(&N) -> R
+    {
+        f(&self.borrow().value)
+    }
+    pub fn with_data_mut<F, R>(&self, f: F) -> R
+        where F: FnOnce(&mut N) -> R
+    {
+        f(&mut self.borrow_mut().value)
+    }
+    /// Replace the node's data, returning the old value.
+    pub fn replace_data(&self, node_data: N) -> N {
+        mem::replace(&mut self.borrow_mut().value, node_data)
+    }
+}
+impl<N, E> NodeHandle<N, E, Local> {
+    pub fn node_data_ref(&self) -> Ref<'_, N> {
+        Ref::map(self.borrow(), |node| &node.value)
+    }
+}
+impl<N, E> NodeHandle<N, E, Shared> {
+    pub fn node_data_ref(&self) -> NodeDataRef<'_, N, E> {
+        NodeDataRef { node: self.borrow() }
+    }
+}
+
+impl<'a, N, E> ::std::ops::Deref for NodeDataRef<'a, N, E> {
+    type Target = N;
+    fn deref(&self) -> &N {
+        &self.node.value
     }
 }
 
-impl<N, E> NodeHandle<N, E> {
-    pub fn weak(&self) -> WeakNodeHandle<N, E> {
+impl<N, E, P: NodePtr> NodeHandle<N, E, P> {
+    pub fn weak(&self) -> WeakNodeHandle<N, E, P> {
         WeakNodeHandle{
-            node: Rc::downgrade(&self.node),
-            node_ptr: &*self.node,
+            node: P::downgrade(&self.node),
+            node_ptr: self.addr(),
         }
     }
-    pub(super) fn check_owner(&self, expected: &RcDagBase<N, E>) {
+    pub(super) fn check_owner(&self, expected: &RcDagBase<N, E, P>) {
         assert_eq!(self.owner_id, expected.id, "NodeHandle owner mismatch");
     }
+    /// Copy the children of this node, so that they can be iterated without borrowing it.
+    fn child_nodes(&self) -> vec::IntoIter<NodeHandle<N, E, P>> {
+        self.borrow().children.iter().map(|edge| edge.to.clone()).collect::<Vec<_>>().into_iter()
+    }
+    /// The node's chain id & position on it (see `ChainLabel`).
+    fn chain_label(&self) -> (u64, u64) {
+        let node = self.borrow();
+        (node.chain.id, node.chain.pos)
+    }
+    /// The node before this one on its chain, unless it starts the chain.
+    fn chain_prev(&self) -> Option<NodeHandle<N, E, P>> {
+        let prev = self.borrow().chain.prev.as_ref().and_then(P::upgrade);
+        prev.map(|node| NodeHandle { node, owner_id: self.owner_id })
+    }
+    /// The node after this one on its chain, unless it ends the chain.
+    fn chain_next(&self) -> Option<NodeHandle<N, E, P>> {
+        let next = self.borrow().chain.next.as_ref().and_then(P::upgrade);
+        next.map(|node| NodeHandle { node, owner_id: self.owner_id })
+    }
+    /// Address of the node, which identifies it for as long as it's alive.
+    fn addr(&self) -> usize {
+        P::addr(&self.node)
+    }
+    fn borrow(&self) -> P::Ref<'_, DagNode<N, E, P>> {
+        P::borrow(&self.node)
+    }
+    fn borrow_mut(&self) -> P::RefMut<'_, DagNode<N, E, P>> {
+        P::borrow_mut(&self.node)
+    }
 }
 
-impl<N, E> Hash for WeakNodeHandle<N, E> {
+impl<N, E, P: NodePtr> Hash for WeakNodeHandle<N, E, P> {
     fn hash<H>(&self, state: &mut H)  where H: Hasher {
         self.node_ptr.hash(state);
     }
 }
-impl<N, E> PartialEq for WeakNodeHandle<N, E> {
+impl<N, E, P: NodePtr> PartialEq for WeakNodeHandle<N, E, P> {
     fn eq(&self, other: &Self) -> bool {
-        match self.node.upgrade() {
-            None => {
-                if let Some(_) = other.node.upgrade() {
-                    false
-                } else {
-                    true
-                }
-            },
-            Some(my_rc) => {
-                if let Some(other_rc) = other.node.upgrade() {
-                    Rc::ptr_eq(&my_rc, &other_rc)
-                } else {
-                    false
-                }
-            }
+        match (P::upgrade(&self.node), P::upgrade(&other.node)) {
+            (None, None) => true,
+            (Some(mine), Some(other)) => P::addr(&mine) == P::addr(&other),
+            _ => false,
         }
     }
 }
-impl<N, E> Eq for WeakNodeHandle<N, E> {}
+impl<N, E, P: NodePtr> Eq for WeakNodeHandle<N, E, P> {}
 
-impl<N, E> HalfEdge<N, E> {
-    pub fn new(to: NodeHandle<N, E>, weight: E) -> Self {
-        HalfEdge{ to: to, weight: weight }
+impl<N, E, P: NodePtr> HalfEdge<N, E, P> {
+    pub fn new(to: NodeHandle<N, E, P>, weight: E) -> Self {
+        HalfEdge{ to, weight }
     }
 }
 
-impl<N, E> HalfEdge<N, E> {
-    pub fn to(&self) -> &NodeHandle<N, E> {
+impl<N, E, P: NodePtr> HalfEdge<N, E, P> {
+    pub fn to(&self) -> &NodeHandle<N, E, P> {
         &self.to
     }
     pub fn weight(&self) -> &E {
@@ -259,7 +1123,7 @@ impl<N, E> HalfEdge<N, E> {
     }
 }
 
-impl<N, E> Hash for HalfEdge<N, E> {
+impl<N, E, P: NodePtr> Hash for HalfEdge<N, E, P> {
     fn hash<H>(&self, state: &mut H)  where H: Hasher {
         // we don't really need to hash the edge; few use-cases have many
         // overlapping edges with different weights.
@@ -269,7 +1133,7 @@ impl<N, E> Hash for HalfEdge<N, E> {
 
 // Yes, this is identical to the default Clone implementation,
 // but the default impl requires N to also be cloneable.
-impl<N, E : Clone> Clone for HalfEdge<N, E> {
+impl<N, E : Clone, P: NodePtr> Clone for HalfEdge<N, E, P> {
     fn clone(&self) -> Self {
         HalfEdge {
             to: self.to.clone(),
@@ -279,29 +1143,80 @@ impl<N, E : Clone> Clone for HalfEdge<N, E> {
 }
 
 // Identical to default Eq, again, but we don't want N : Eq requirement.
-impl<N, E : Eq> PartialEq for HalfEdge<N, E> {
+impl<N, E : Eq, P: NodePtr> PartialEq for HalfEdge<N, E, P> {
     fn eq(&self, other: &Self) -> bool {
         self.to == other.to && self.weight == other.weight
     }
 }
-impl<N, E : Eq> Eq for HalfEdge<N, E>{}
+impl<N, E : Eq, P: NodePtr> Eq for HalfEdge<N, E, P>{}
 
-impl<N, E> FullEdge<N, E> {
-    /*
-    pub(super) fn new(from: NodeHandle<N, E>, half: HalfEdge<N, E>) -> Self {
+impl<N, E, P: NodePtr> FullEdge<N, E, P> {
+    pub(super) fn new(from: NodeHandle<N, E, P>, half: HalfEdge<N, E, P>) -> Self {
         FullEdge {
-            half: half,
-            from: from,
+            half,
+            from,
         }
     }
-    */
-    pub fn from(&self) -> &NodeHandle<N, E> {
+    pub fn from(&self) -> &NodeHandle<N, E, P> {
         &self.from
     }
-    pub fn to(&self) -> &NodeHandle<N, E> {
+    /// The portion of the edge that's stored with its source node.
+    pub fn half(&self) -> &HalfEdge<N, E, P> {
+        &self.half
+    }
+    pub fn to(&self) -> &NodeHandle<N, E, P> {
         self.half.to()
     }
     pub fn weight(&self) -> &E {
         self.half.weight()
     }
 }
+
+impl<N, E : Eq, P: NodePtr> FullEdge<N, E, P> {
+    /// Return true if the edge is currently in the graph.
+    fn is_present(&self) -> bool {
+        self.from.borrow().children.contains(&self.half)
+    }
+}
+
+impl<N, E : Clone, P: NodePtr> Clone for FullEdge<N, E, P> {
+    fn clone(&self) -> Self {
+        FullEdge {
+            half: self.half.clone(),
+            from: self.from.clone(),
+        }
+    }
+}
+
+impl<N, E, P: NodePtr> Hash for FullEdge<N, E, P> {
+    fn hash<H>(&self, state: &mut H)  where H: Hasher {
+        self.from.hash(state);
+        self.half.hash(state);
+    }
+}
+
+impl<N, E : Eq, P: NodePtr> PartialEq for FullEdge<N, E, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.from == other.from && self.half == other.half
+    }
+}
+impl<N, E : Eq, P: NodePtr> Eq for FullEdge<N, E, P>{}
+
+impl<N, E : fmt::Debug, P: NodePtr> fmt::Debug for HalfEdge<N, E, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HalfEdge")
+            .field("to", &self.to)
+            .field("weight", &self.weight)
+            .finish()
+    }
+}
+
+impl<N, E : fmt::Debug, P: NodePtr> fmt::Debug for FullEdge<N, E, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FullEdge")
+            .field("from", &self.from)
+            .field("to", self.to())
+            .field("weight", self.weight())
+            .finish()
+    }
+}
@@ -0,0 +1,85 @@
+//! Allocation of node handles for `IODag` & `IODagFull`.
+//! A handle names a slot, along with the slot's generation. When a node is deleted, its slot is
+//! freed for reuse and its generation is bumped, so that handles to the deleted node never refer
+//! to whichever node reuses the slot.
+//! Free slots are reused lowest first, so the handles given out next depend only on the
+//! generation of each slot & which slots are occupied. That's all that's needed to save a graph
+//! and read it back with the same handles.
+//! Generations never go backwards: rolling back a transaction (or undoing a step) deletes the
+//! nodes it created like any other, so their handles stay stale for good.
+
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Clone, Debug)]
+pub(super) struct Slots {
+    /// Generation of each slot: that of its node, or else that of the next node to occupy it.
+    generations: Vec<u64>,
+    /// Slots without a node.
+    free: BTreeSet<u64>,
+    /// Occupied slots whose node was restored under an older generation than the slot had
+    /// reached, mapped to the generation the slot had reached. Releasing the slot resumes from
+    /// there, so that the handles given out in between are never reissued.
+    reached: HashMap<u64, u64>,
+}
+
+impl Slots {
+    pub(super) fn new() -> Self {
+        Slots {
+            generations: vec![],
+            free: BTreeSet::new(),
+            reached: HashMap::new(),
+        }
+    }
+    /// Slots with the given generations, all of them free.
+    pub(super) fn with_generations(generations: Vec<u64>) -> Self {
+        Slots {
+            free: (0..generations.len() as u64).collect(),
+            generations,
+            reached: HashMap::new(),
+        }
+    }
+    pub(super) fn generations(&self) -> &[u64] {
+        &self.generations
+    }
+    pub(super) fn len(&self) -> u64 {
+        self.generations.len() as u64
+    }
+    /// Current generation of the slot, or None if there's no such slot.
+    pub(super) fn generation(&self, index: u64) -> Option<u64> {
+        self.generations.get(index as usize).cloned()
+    }
+    /// Occupy a slot, returning its index & generation.
+    pub(super) fn allocate(&mut self) -> (u64, u64) {
+        match self.free.iter().next().cloned() {
+            Some(index) => {
+                self.free.remove(&index);
+                (index, self.generations[index as usize])
+            },
+            None => {
+                self.generations.push(0);
+                (self.len() - 1, 0)
+            },
+        }
+    }
+    /// Occupy the given slot at the given generation (e.g. to restore a deleted node under its
+    /// original handle). Slots are created as needed, free & at generation 0.
+    /// Panics if the slot is already occupied.
+    pub(super) fn claim(&mut self, index: u64, generation: u64) {
+        while self.len() <= index {
+            self.free.insert(self.len());
+            self.generations.push(0);
+        }
+        assert!(self.free.remove(&index), "Slot {} is already occupied", index);
+        let reached = self.generations[index as usize];
+        if generation < reached {
+            self.reached.insert(index, reached);
+        }
+        self.generations[index as usize] = generation;
+    }
+    /// Free the slot of a node that was deleted.
+    pub(super) fn release(&mut self, index: u64) {
+        let next = self.generations[index as usize] + 1;
+        self.generations[index as usize] = self.reached.remove(&index).map_or(next, |reached| reached.max(next));
+        assert!(self.free.insert(index), "Slot {} is already free", index);
+    }
+}
@@ -0,0 +1,56 @@
+//! Self-contained copies of the Rc-based graphs (`RcDag`, `PosCostDag`), for saving & loading.
+//! Those graphs don't store their nodes; they only exist as links reachable from the client's
+//! handles. So a snapshot is taken from a set of root handles, and restoring it hands back new
+//! handles to those roots.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::error::DagError;
+use super::ondag::OnDag;
+
+/// The portion of a graph reachable from a set of roots.
+/// Each node is identified by its index into `nodes`, and appears once no matter how many paths
+/// lead to it.
+///
+/// N=Node data
+/// E=Edge weight
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DagSnapshot<N, E> {
+    pub(super) nodes: Vec<N>,
+    /// (from, to, weight)
+    pub(super) edges: Vec<(usize, usize, E)>,
+    /// The ids of the roots the snapshot was taken from, in the order they were given.
+    pub(super) roots: Vec<usize>,
+}
+
+impl<N, E> DagSnapshot<N, E> {
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+    /// Recreate the nodes & edges within `dag` (typically a new graph), returning handles to the
+    /// roots the snapshot was taken from (in the same order).
+    /// Every edge is inserted via `OnDag::add_edge`, so it's an error for the snapshot to contain a
+    /// cycle that `dag` doesn't allow, or to refer to nodes that it doesn't contain. For the
+    /// Rc-based graphs, the new nodes are only kept alive by the returned handles, so on error
+    /// `dag` is left as it was.
+    pub fn restore<D>(self, dag: &mut D) -> Result<Vec<D::NodeHandle>, DagError<D::Edge>>
+        where D: OnDag<N, E>, D::NodeHandle: Clone
+    {
+        let DagSnapshot { nodes, edges, roots } = self;
+        let handles = nodes.into_iter().map(|node_data| dag.add_node(node_data)).collect::<Vec<_>>();
+        for (from, to, weight) in edges {
+            match (handles.get(from), handles.get(to)) {
+                (Some(from), Some(to)) => dag.add_edge(from, to, weight)?,
+                _ => return Err(DagError::NoSuchNode),
+            }
+        }
+        roots.into_iter().map(|root| {
+            handles.get(root).cloned().ok_or(DagError::NoSuchNode)
+        }).collect()
+    }
+}
@@ -1,4 +1,10 @@
 #[macro_use]
 mod testondag;
+mod testarcdag;
+mod testarcposcostdag;
+mod testconcurrentiodag;
+mod testiodag;
+mod testiodagfull;
+mod testiodaghistory;
 mod testposcostdag;
 mod testrcdag;
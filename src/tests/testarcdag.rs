@@ -0,0 +1,245 @@
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use ::arcdag::{ArcDag, FullEdge, NodeHandle};
+use ::batch::EdgeBatch;
+use ::error::DagError;
+use ::ondag::OnDag;
+use super::testondag::OnDagTestable;
+
+type MyDag = ArcDag<u32, u32>;
+
+
+// Declare tests for common OnDag functionality (inserting/removing nodes and edges).
+def_ondag_tests!{MyDag}
+def_ondag_long_tests!{MyDag}
+
+impl OnDagTestable<u32> for MyDag {
+    fn weight(w: u32) -> u32 {
+        w
+    }
+    fn edge_weight(edge: &FullEdge<u32, u32>) -> u32 {
+        *edge.weight()
+    }
+    fn node_value(&self, node: &NodeHandle<u32, u32>) -> u32 {
+        node.node_data()
+    }
+}
+
+
+#[test]
+/// Graph should not allow cycles - should be an error when adding a cycle & structure should be
+/// unmodified.
+fn test_cycles() {
+    // 12 -> 1 -> 2
+    let dag = MyDag::new();
+    let root = dag.add_node(12);
+    let n2 = dag.add_node(2);
+    let n1 = dag.add_node(1);
+    dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+    dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
+    match dag.add_edge(&n2, &root, 1003) {
+        Err(DagError::Cycle(path)) => {
+            assert_eq!(path.iter().map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1003, 1001, 1002]);
+        },
+        _ => panic!("Expected a cycle"),
+    }
+    assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12, 1, 2]);
+    assert_eq!(dag.edge_count(), 2);
+    // Handles from other graphs are rejected.
+    let other = MyDag::new().add_node(3);
+    assert_eq!(dag.add_edge(&root, &other, 1004).err(), Some(DagError::ForeignHandle));
+}
+
+
+#[test]
+/// Paths should follow the edges' direction, as for RcDag.
+fn test_find_path() {
+    // 12 -> 1 -> 2
+    let dag = MyDag::new();
+    let root = dag.add_node(12);
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+    dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
+    let path = dag.find_path(&root, &n2).expect("Failed to find path");
+    assert_eq!(path.iter().map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1001, 1002]);
+    assert!(dag.is_reachable(&n1, &n2) && !dag.is_reachable(&n2, &root));
+    assert!(dag.would_create_cycle(&n2, &root) && !dag.would_create_cycle(&root, &n2));
+}
+
+#[test]
+/// A graph built on one thread should be usable from another, with nodes living for as long as
+/// their handles.
+fn test_send() {
+    let (dag, root) = thread::spawn(|| {
+        let dag = MyDag::new();
+        let root = dag.add_node(12);
+        let n1 = dag.add_node(1);
+        dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+        (dag, root)
+    }).join().unwrap();
+    assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12, 1]);
+    assert_eq!((dag.node_count(), dag.edge_count()), (2, 1));
+    drop(root);
+    assert_eq!((dag.node_count(), dag.edge_count()), (0, 0));
+}
+
+#[test]
+/// Racing insertions of a -> b & b -> a should never both succeed.
+fn test_concurrent_cycle() {
+    for _ in 0..100 {
+        let dag = Arc::new(MyDag::new());
+        let a = dag.add_node(1);
+        let b = dag.add_node(2);
+        let barrier = Arc::new(Barrier::new(2));
+        let threads = vec![(a.clone(), b.clone()), (b.clone(), a.clone())].into_iter().map(|(from, to)| {
+            let (dag, barrier) = (dag.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                dag.add_edge(&from, &to, 1001).is_ok()
+            })
+        }).collect::<Vec<_>>();
+        let added = threads.into_iter().map(|thread| thread.join().unwrap()).filter(|&ok| ok).count();
+        assert_eq!(added, 1);
+        assert_eq!(dag.edge_count(), 1);
+    }
+}
+
+#[test]
+/// Many threads extending a chain at once should leave a single acyclic chain.
+fn test_concurrent_chain() {
+    let dag = Arc::new(MyDag::new());
+    let nodes = (0..20).map(|value| dag.add_node(value)).collect::<Vec<_>>();
+    let threads = (0..4).map(|offset| {
+        let (dag, nodes) = (dag.clone(), nodes.clone());
+        thread::spawn(move || {
+            // Every thread tries every edge in both directions, offset to interleave them.
+            for index in 0..nodes.len() - 1 {
+                let index = (index + offset * 5) % (nodes.len() - 1);
+                let _ = dag.add_edge(&nodes[index + 1], &nodes[index], 0);
+                let _ = dag.add_edge(&nodes[index], &nodes[index + 1], 0);
+            }
+        })
+    }).collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(dag.edge_count(), nodes.len() - 1);
+    // With one edge per pair, in whichever direction, there's no cycle: every node is ordered.
+    for node in nodes.iter() {
+        let reachable = dag.iter_topo(node).collect::<Vec<_>>();
+        assert!(reachable.iter().all(|other| other == node || dag.iter_topo(other).all(|back| &back != node)));
+    }
+}
+
+#[test]
+/// Dropping the only handle to the head of a long chain should free the whole chain without
+/// recursing down it.
+fn test_drop_long_chain() {
+    // 0 -> 1 -> ... -> 999999, holding a handle to only 0.
+    let dag = MyDag::new();
+    let head = dag.add_node(0);
+    let mut prev = head.clone();
+    for value in 1..1000000 {
+        let next = dag.add_node(value);
+        dag.add_edge(&prev, &next, 1000).expect("Failed to add edge");
+        prev = next;
+    }
+    drop(prev);
+    assert_eq!((dag.node_count(), dag.edge_count()), (1000000, 999999));
+    drop(head);
+    assert_eq!((dag.node_count(), dag.edge_count()), (0, 0));
+}
+
+#[test]
+/// A batch should be applied in a single step, so long as the end result is acyclic, and leave
+/// the graph untouched otherwise.
+fn test_batch() {
+    // 12 -> 1 -> 2 becomes 12 -> 2 -> 1
+    let dag = MyDag::new();
+    let root = dag.add_node(12);
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+    dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(n2.clone(), n1.clone(), 1002)
+        .add_edge(root.clone(), n2.clone(), 1001)
+        .rm_edge(n1.clone(), n2.clone(), 1002)
+        .rm_edge(root.clone(), n1.clone(), 1001);
+    dag.apply_batch(batch).expect("Failed to apply batch");
+    assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12, 2, 1]);
+    // Removing 12 -> 2 doesn't break the 2 -> 1 -> 2 cycle.
+    let mut batch = EdgeBatch::new();
+    batch.rm_edge(root.clone(), n2.clone(), 1001)
+        .add_edge(root.clone(), n1.clone(), 1003)
+        .add_edge(n1.clone(), n2.clone(), 1004);
+    match dag.apply_batch(batch) {
+        Err(DagError::Cycle(cycle)) => assert_eq!(cycle.len(), 2),
+        _ => panic!("Failed to detect cycle"),
+    }
+    // Removing an edge twice fails.
+    let mut batch = EdgeBatch::new();
+    batch.rm_edge(n2.clone(), n1.clone(), 1002)
+        .rm_edge(n2.clone(), n1.clone(), 1002);
+    assert_eq!(dag.apply_batch(batch).err(), Some(DagError::NoSuchEdge));
+    assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12, 2, 1]);
+    assert_eq!(dag.edge_count(), 2);
+    dag.set_edge_weight(&n2, &n1, 1002, 1005).expect("Failed to set weight");
+    assert_eq!(dag.parents(&n1).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1005]);
+    assert_eq!(dag.set_edge_weight(&n2, &n1, 1002, 1006), Err(DagError::NoSuchEdge));
+}
+
+#[test]
+/// Snapshots & DOT output should include everything reachable from the roots, as for RcDag.
+fn test_snapshot() {
+    // 1 -> 2 -> 3, 1 -> 3
+    let dag = MyDag::new();
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    let n3 = dag.add_node(3);
+    dag.add_edge(&n1, &n2, 1001).expect("Failed to add edge");
+    dag.add_edge(&n2, &n3, 1002).expect("Failed to add edge");
+    dag.add_edge(&n1, &n3, 1003).expect("Failed to add edge");
+    let snapshot = dag.snapshot(&[n2]).expect("Failed to take snapshot");
+    assert_eq!((snapshot.node_count(), snapshot.edge_count()), (2, 1));
+    let mut loaded = MyDag::new();
+    let roots = snapshot.restore(&mut loaded).expect("Failed to restore snapshot");
+    assert_eq!(loaded.iter_topo(&roots[0]).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![2, 3]);
+    let mut out = vec![];
+    dag.write_dot(&mut out, &[n1], |data| data.to_string(), |edge| edge.weight().to_string(), &[])
+        .expect("Failed to write graph");
+    assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2 + 3 + 3);
+}
+
+#[test]
+/// Node data should be accessible by reference, and modifiable, from any thread.
+fn test_node_data_access() {
+    let dag = MyDag::new();
+    let a = dag.add_node(1);
+    assert_eq!(*a.node_data_ref(), 1);
+    let handle = a.clone();
+    thread::spawn(move || handle.with_data_mut(|value| *value += 1)).join().unwrap();
+    assert_eq!(a.with_data(|value| *value), 2);
+    assert_eq!(a.replace_data(3), 2);
+    assert_eq!(a.node_data(), 3);
+}
+
+#[test]
+/// Dropping a long chain, of which only the root is held, shouldn't recurse down it.
+fn test_drop_chain_from_root() {
+    // 0 -> 1 -> ... -> 99999
+    let dag = MyDag::new();
+    let root = dag.add_node(0);
+    let mut prev = root.clone();
+    for value in 1..100000 {
+        let next = dag.add_node(value);
+        dag.add_edge(&prev, &next, 1000).expect("Failed to add edge");
+        prev = next;
+    }
+    drop(prev);
+    assert_eq!(dag.node_count(), 100000);
+    drop(root);
+    assert_eq!((dag.node_count(), dag.edge_count()), (0, 0));
+}
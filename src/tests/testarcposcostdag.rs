@@ -0,0 +1,174 @@
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use ::arcposcostdag::{ArcPosCostDag, CostQueriable, FullEdge, HalfEdge, NodeHandle};
+use ::batch::EdgeBatch;
+use ::error::DagError;
+use ::ondag::OnDag;
+use super::testondag::OnDagTestable;
+
+type MyDag = ArcPosCostDag<u32, u32>;
+
+
+// Declare tests for common OnDag functionality (inserting/removing nodes and edges).
+def_ondag_tests!{MyDag}
+
+impl OnDagTestable<u32> for MyDag {
+    fn weight(w: u32) -> u32 {
+        w
+    }
+    fn edge_weight(edge: &FullEdge<u32, u32>) -> u32 {
+        *edge.weight()
+    }
+    fn node_value(&self, node: &NodeHandle<u32, u32>) -> u32 {
+        node.node_data()
+    }
+}
+
+impl CostQueriable<u32, u32, MyDag> for u32 {
+    /// For testing, the edge cost is identical to its weight.
+    fn is_zero_cost(edge: &HalfEdge<u32, u32>, _next: &HalfEdge<u32, u32>, _dag: &MyDag) -> bool {
+        edge.weight() == &0
+    }
+}
+
+
+#[test]
+/// Graph should not allow ZERO-COST cycles, and rejected edges should never be inserted.
+fn test_cycles() {
+    // A --4--> B --0--> C --0--> A, then A --0--> B closes a 0-cycle.
+    let dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &b, 4).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 0).expect("Failed to add edge");
+    dag.add_edge(&c, &a, 0).expect("Failed to add edge");
+    match dag.add_edge(&a, &b, 0) {
+        Err(DagError::ZeroCostCycle(path)) => {
+            assert_eq!(path.iter().map(|edge| { edge.from().node_data() }).collect::<Vec<u32>>(), vec![11, 12, 13]);
+        },
+        _ => panic!("Expected a zero-cost cycle"),
+    }
+    assert_eq!(dag.children(&a).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![4]);
+    // A 0-cost self-loop is a 0-cycle too.
+    dag.add_edge(&a, &a, 0).expect_err("Failed to detect cycle");
+    dag.add_edge(&a, &a, 1).expect("Failed to add edge");
+}
+
+
+#[test]
+/// Only edges that would close a 0-cycle should be reported as creating one.
+fn test_find_path() {
+    // A --4--> B --0--> C
+    let dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &b, 4).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 0).expect("Failed to add edge");
+    assert_eq!(dag.find_path(&a, &c).map(|path| path.len()), Some(2));
+    assert!(dag.is_reachable(&a, &c) && !dag.is_reachable(&c, &a));
+    assert!(!dag.would_create_cycle(&c, &a, 0));
+    assert!(dag.would_create_cycle(&c, &b, 0) && !dag.would_create_cycle(&c, &b, 1));
+}
+
+#[test]
+/// Racing 0-cost insertions of a -> b & b -> a should never both succeed.
+fn test_concurrent_cycle() {
+    for _ in 0..100 {
+        let dag = Arc::new(MyDag::new());
+        let a = dag.add_node(1);
+        let b = dag.add_node(2);
+        let barrier = Arc::new(Barrier::new(2));
+        let threads = vec![(a.clone(), b.clone()), (b.clone(), a.clone())].into_iter().map(|(from, to)| {
+            let (dag, barrier) = (dag.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                dag.add_edge(&from, &to, 0).is_ok()
+            })
+        }).collect::<Vec<_>>();
+        let added = threads.into_iter().map(|thread| thread.join().unwrap()).filter(|&ok| ok).count();
+        assert_eq!(added, 1);
+        // Edges with a cost can still close the loop.
+        dag.add_edge(&a, &b, 1).expect("Failed to add edge");
+        dag.add_edge(&b, &a, 1).expect("Failed to add edge");
+    }
+}
+
+#[test]
+/// A batch should only be rejected if its end result has a 0-cycle, as for PosCostDag.
+fn test_batch() {
+    // A --4--> B --0--> C --0--> A
+    let dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &b, 4).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 0).expect("Failed to add edge");
+    dag.add_edge(&c, &a, 0).expect("Failed to add edge");
+    // Moving the cost from A -> B onto C -> A passes through a 0-cycle.
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(a.clone(), b.clone(), 0)
+        .add_edge(c.clone(), a.clone(), 4)
+        .rm_edge(a.clone(), b.clone(), 4)
+        .rm_edge(c.clone(), a.clone(), 0);
+    dag.apply_batch(batch).expect("Failed to apply batch");
+    assert_eq!(dag.children(&c).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![4]);
+    // But removing the cost altogether isn't allowed.
+    match dag.set_edge_weight(&c, &a, 4, 0) {
+        Err(DagError::ZeroCostCycle(cycle)) => assert_eq!(cycle.len(), 3),
+        _ => panic!("Failed to detect cycle"),
+    }
+    assert_eq!(dag.children(&c).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![4]);
+    assert_eq!(dag.edge_count(), 3);
+}
+
+#[test]
+/// Dropping a long chain, of which only the root is held, shouldn't recurse down it.
+fn test_drop_chain_from_root() {
+    // 0 -> 1 -> ... -> 99999
+    let dag = MyDag::new();
+    let root = dag.add_node(0);
+    let mut prev = root.clone();
+    for value in 1..100000 {
+        let next = dag.add_node(value);
+        dag.add_edge(&prev, &next, 1000).expect("Failed to add edge");
+        prev = next;
+    }
+    drop(prev);
+    assert_eq!(dag.node_count(), 100000);
+    drop(root);
+    assert_eq!((dag.node_count(), dag.edge_count()), (0, 0));
+}
+
+/// A weight whose cost function reads the graph it's given, as one that depends on an edge's
+/// surroundings would. Its cost is its value, as for u32.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Surveyed(u32);
+
+impl CostQueriable<u32, Surveyed, ArcPosCostDag<u32, Surveyed>> for Surveyed {
+    fn is_zero_cost(edge: &HalfEdge<u32, Surveyed>, next: &HalfEdge<u32, Surveyed>, dag: &ArcPosCostDag<u32, Surveyed>) -> bool {
+        assert!(dag.is_reachable(edge.to(), edge.to()));
+        assert!(dag.snapshot(&[next.to().clone()]).is_ok());
+        edge.weight().0 == 0
+    }
+}
+
+#[test]
+/// The cost function should be able to read the graph while an edge or batch is being checked.
+fn test_cost_reads_graph() {
+    // A --0--> B --0--> C, then C --0--> A closes a 0-cycle.
+    let dag = ArcPosCostDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &b, Surveyed(0)).expect("Failed to add edge");
+    dag.add_edge(&b, &c, Surveyed(0)).expect("Failed to add edge");
+    assert!(dag.would_create_cycle(&c, &a, Surveyed(0)));
+    dag.add_edge(&c, &a, Surveyed(0)).expect_err("Failed to detect cycle");
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(c.clone(), a.clone(), Surveyed(1)).add_edge(c.clone(), b.clone(), Surveyed(0));
+    dag.apply_batch(batch).expect_err("Failed to detect cycle");
+    dag.add_edge(&c, &a, Surveyed(1)).expect("Failed to add edge");
+}
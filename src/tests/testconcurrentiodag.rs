@@ -0,0 +1,166 @@
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use ::concurrentiodag::{ConcurrentIODag, Edge, NodeHandle};
+use ::error::DagError;
+use ::ondag::OnDag;
+use super::testondag::OnDagTestable;
+
+type MyDag = ConcurrentIODag<u32, u32>;
+
+
+// Declare tests for common OnDag functionality (inserting/removing nodes and edges).
+def_ondag_tests!{MyDag}
+def_ondag_long_tests!{MyDag}
+
+impl OnDagTestable<u32> for MyDag {
+    fn weight(w: u32) -> u32 {
+        w
+    }
+    fn edge_weight(edge: &Edge<u32>) -> u32 {
+        *edge.weight()
+    }
+    fn node_value(&self, node: &NodeHandle) -> u32 {
+        self.node_data(*node).unwrap()
+    }
+}
+
+/// Every edge entering a node reaches every edge leaving it.
+fn connected(_in: &Edge<u32>, _out: &Edge<u32>) -> bool {
+    true
+}
+
+
+#[test]
+/// Graph should not allow cycles, as for IODag, and deleted nodes shouldn't accept edges.
+fn test_cycles() {
+    // null -> a -> b -> c -> null
+    let dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1001), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), Some(c), 1002), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(c), None, 1003), &connected).expect("Failed to add edge");
+    let cycle = dag.add_edge(Edge::new(Some(c), Some(a), 1004), &connected).expect_err("Failed to detect cycle");
+    assert_eq!(cycle, DagError::Cycle(vec![
+        Edge::new(Some(c), Some(a), 1004),
+        Edge::new(Some(a), Some(b), 1001),
+        Edge::new(Some(b), Some(c), 1002),
+    ]));
+    // The loop is allowed when b doesn't connect its edges.
+    dag.add_edge(Edge::new(Some(c), Some(a), 1004), &|edge_in, _out| edge_in.to() != &Some(b)).expect("Failed to add edge");
+    assert_eq!(dag.iter_edges().count(), 5);
+    assert_eq!(dag.del_node(a), Err(DagError::NodeHasEdges));
+    let d = dag.add_node(14);
+    dag.del_node(d).unwrap();
+    assert_eq!(dag.add_edge(Edge::new(Some(a), Some(d), 1005), &connected), Err(DagError::NoSuchNode));
+    assert_eq!(dag.node_count(), 3);
+    // d's handle stays stale once converted.
+    let mut dag = dag.into_iodag();
+    assert!(!dag.is_live(d) && dag.is_live(a));
+    assert!(dag.add_node(14) != d);
+}
+
+
+#[test]
+/// Paths should only pass through nodes where the predicate connects their edges, as for IODag.
+fn test_find_path() {
+    let dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    // Edges entering b with weight 0 don't reach anything.
+    let pred = |edge_in: &Edge<u32>, _out: &Edge<u32>| *edge_in.weight() != 0;
+    dag.add_edge(Edge::new(Some(a), Some(b), 0), &pred).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), Some(c), 1), &pred).expect("Failed to add edge");
+    assert_eq!(dag.find_path(a, c, &pred), None);
+    assert_eq!(dag.find_path(a, c, &connected).map(|path| path.len()), Some(2));
+    assert!(dag.is_reachable(a, b, &pred) && !dag.is_reachable(b, a, &connected));
+    assert!(dag.would_create_cycle(&Edge::new(Some(c), Some(a), 2), &connected));
+    assert!(!dag.would_create_cycle(&Edge::new(Some(c), Some(a), 2), &pred));
+}
+
+#[test]
+/// Racing insertions of a -> b & b -> a should never both succeed.
+fn test_concurrent_cycle() {
+    for _ in 0..100 {
+        let dag = Arc::new(MyDag::new());
+        let a = dag.add_node(1);
+        let b = dag.add_node(2);
+        let barrier = Arc::new(Barrier::new(2));
+        let threads = vec![(a, b), (b, a)].into_iter().map(|(from, to)| {
+            let (dag, barrier) = (dag.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                dag.add_edge(Edge::new(Some(from), Some(to), 1001), &connected).is_ok()
+            })
+        }).collect::<Vec<_>>();
+        let added = threads.into_iter().map(|thread| thread.join().unwrap()).filter(|&ok| ok).count();
+        assert_eq!(added, 1);
+        assert_eq!(dag.iter_edges().count(), 1);
+    }
+}
+
+#[test]
+/// Threads adding nodes & edges at once should build a graph equivalent to a sequential one.
+fn test_concurrent_build() {
+    let dag = Arc::new(MyDag::new());
+    let shared = (0..20).map(|value| dag.add_node(value)).collect::<Vec<_>>();
+    let threads = (0..4).map(|offset| {
+        let (dag, shared) = (dag.clone(), shared.clone());
+        thread::spawn(move || {
+            // Each thread builds its own chain from null to null...
+            let own = (0..50).map(|value| dag.add_node(1000 * (offset + 1) + value)).collect::<Vec<_>>();
+            dag.add_edge(Edge::new(None, Some(own[0]), 0), &connected).unwrap();
+            for pair in own.windows(2) {
+                dag.add_edge(Edge::new(Some(pair[0]), Some(pair[1]), 0), &connected).unwrap();
+            }
+            dag.add_edge(Edge::new(Some(own[49]), None, 0), &connected).unwrap();
+            // ...and contends with the others to link the shared nodes, in either direction.
+            for index in 0..shared.len() - 1 {
+                let index = (index + offset as usize * 5) % (shared.len() - 1);
+                let _ = dag.add_edge(Edge::new(Some(shared[index + 1]), Some(shared[index]), 0), &connected);
+                let _ = dag.add_edge(Edge::new(Some(shared[index]), Some(shared[index + 1]), 0), &connected);
+            }
+        })
+    }).collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(dag.node_count(), 220);
+    // 4 chains of 51 edges, and one edge between each pair of shared nodes.
+    assert_eq!(dag.edge_count(), 4 * 51 + 19);
+    let dag = Arc::try_unwrap(dag).ok().unwrap().into_iodag();
+    assert_eq!(dag.node_count(), 220);
+    assert_eq!(dag.edge_count(), 4 * 51 + 19);
+    assert_eq!(*dag.node_data(shared[3]), 3);
+    // Since there's no cycle, the order accounts for every edge.
+    let position = dag.iter_topo().enumerate().map(|(position, &node)| (node, position)).collect::<::std::collections::HashMap<_, _>>();
+    assert!(dag.iter_edges().filter(|edge| edge.from().is_some() && edge.to().is_some()).all(|edge| {
+        position[&edge.from().unwrap()] < position[&edge.to().unwrap()]
+    }));
+}
+
+#[test]
+/// Converting a large graph whose nodes were created against its edges should order it in one
+/// pass, rather than edge by edge (which would take quadratic time).
+fn test_into_iodag_large() {
+    // 99999 -> 99998 -> ... -> 0, plus a loop from 0 back to 1 that the pred disconnects.
+    let count = 100000;
+    let dag = MyDag::new();
+    let nodes = (0..count).map(|value| dag.add_node(value)).collect::<Vec<_>>();
+    // Linking from the end of the chain keeps each insertion's cycle search short.
+    for index in (0..count as usize - 1).rev() {
+        dag.add_edge(Edge::new(Some(nodes[index + 1]), Some(nodes[index]), 0), &connected).expect("Failed to add edge");
+    }
+    let pred = |edge_in: &Edge<u32>, edge_out: &Edge<u32>| edge_in.weight() == edge_out.weight();
+    dag.add_edge(Edge::new(Some(nodes[0]), Some(nodes[1]), 1), &pred).expect("Failed to add edge");
+    let dag = dag.into_iodag();
+    assert_eq!(dag.node_count(), count as usize);
+    assert_eq!(dag.edge_count(), count as usize);
+    assert_eq!(dag.iter_topo().cloned().collect::<Vec<_>>(), nodes.iter().rev().cloned().collect::<Vec<_>>());
+    // The loop goes against the order, but is still accounted for.
+    assert!(dag.is_reachable(nodes[0], nodes[1], &connected));
+}
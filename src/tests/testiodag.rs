@@ -0,0 +1,791 @@
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "serde")]
+use serde_json;
+
+use ::batch::EdgeBatch;
+use ::binary::{self, BinaryError};
+use ::dot::DotError;
+use ::error::DagError;
+#[cfg(feature = "graphml")]
+use ::graphml::GraphmlError;
+use ::iodag::{Edge, IODag, NodeHandle};
+use ::ondag::OnDag;
+use super::testondag::OnDagTestable;
+
+type MyDag = IODag<u32, u32>;
+
+
+// Declare tests for common OnDag functionality (inserting/removing nodes and edges).
+def_ondag_tests!{MyDag}
+def_ondag_long_tests!{MyDag}
+
+impl OnDagTestable<u32> for MyDag {
+    fn weight(w: u32) -> u32 {
+        w
+    }
+    fn edge_weight(edge: &Edge<u32>) -> u32 {
+        *edge.weight()
+    }
+    fn node_value(&self, node: &NodeHandle) -> u32 {
+        *self.node_data(*node)
+    }
+}
+
+/// Every edge entering a node reaches every edge leaving it.
+fn connected(_in: &Edge<u32>, _out: &Edge<u32>) -> bool {
+    true
+}
+
+
+#[test]
+/// Graph should not allow cycles - the error should name the cycle & structure should be
+/// unmodified.
+fn test_cycles() {
+    // null -> a -> b -> c -> null
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1001), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), Some(c), 1002), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(c), None, 1003), &connected).expect("Failed to add edge");
+    let cycle = dag.add_edge(Edge::new(Some(c), Some(a), 1004), &connected).expect_err("Failed to detect cycle");
+    assert_eq!(cycle, DagError::Cycle(vec![
+        Edge::new(Some(c), Some(a), 1004),
+        Edge::new(Some(a), Some(b), 1001),
+        Edge::new(Some(b), Some(c), 1002),
+    ]));
+    assert_eq!(dag.iter_edges().count(), 4);
+}
+
+#[test]
+/// If the predicate disconnects the path through a node, the loop isn't a cycle.
+fn test_cycles_pred() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    // Edges entering `b` with weight 0 don't reach anything.
+    let pred = |edge_in: &Edge<u32>, _out: &Edge<u32>| *edge_in.weight() != 0;
+    dag.add_edge(Edge::new(Some(a), Some(b), 0), &pred).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), Some(a), 1), &pred).expect("Failed to add edge");
+}
+
+
+#[test]
+/// traverse should follow paths far longer than the call stack could.
+fn test_traverse_long_chain() {
+    // null -> 0 -> 1 -> ... -> 999999 -> null
+    let mut dag = MyDag::new();
+    let nodes = (0..1000000).map(|value| dag.add_node(value)).collect::<Vec<_>>();
+    dag.add_edge_unchecked(Edge::new(None, Some(nodes[0]), 0));
+    for pair in nodes.windows(2) {
+        dag.add_edge_unchecked(Edge::new(Some(pair[0]), Some(pair[1]), 0));
+    }
+    dag.add_edge_unchecked(Edge::new(Some(nodes[999999]), None, 0));
+    let mut visited = 0;
+    dag.traverse(&mut |_edge: &Edge<u32>| {
+        visited += 1;
+        true
+    });
+    assert_eq!(visited, 1000001);
+}
+
+#[test]
+/// Paths should only pass through nodes where the predicate connects their edges.
+fn test_find_path() {
+    // a -> b -> c, where edges entering b with weight 0 don't reach anything.
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    let pred = |edge_in: &Edge<u32>, _out: &Edge<u32>| *edge_in.weight() != 0;
+    dag.add_edge(Edge::new(None, Some(a), 1000), &pred).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 0), &pred).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), Some(c), 1), &pred).expect("Failed to add edge");
+    assert_eq!(dag.find_path(a, c, &pred), None);
+    assert_eq!(dag.find_path(a, c, &connected), Some(vec![
+        Edge::new(Some(a), Some(b), 0),
+        Edge::new(Some(b), Some(c), 1),
+    ]));
+    assert_eq!(dag.find_path(a, b, &pred), Some(vec![Edge::new(Some(a), Some(b), 0)]));
+    assert!(dag.is_reachable(c, c, &pred) && !dag.is_reachable(c, a, &connected));
+    assert!(!dag.would_create_cycle(&Edge::new(Some(c), Some(a), 2), &pred));
+    assert!(dag.would_create_cycle(&Edge::new(Some(c), Some(a), 2), &connected));
+    assert!(!dag.would_create_cycle(&Edge::new(Some(c), None, 2), &connected));
+    // A second path through b connects.
+    dag.add_edge(Edge::new(Some(a), Some(b), 2), &pred).expect("Failed to add edge");
+    assert_eq!(dag.find_path(a, c, &pred).map(|path| path.len()), Some(2));
+    dag.del_edge(Edge::new(Some(b), Some(c), 1)).expect("Failed to delete edge");
+    dag.del_node(c).expect("Failed to delete node");
+    assert!(!dag.is_reachable(a, c, &connected));
+}
+
+#[test]
+/// Edges touching missing nodes & deletion of missing edges should be reported.
+fn test_missing() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let edge = Edge::new(Some(a), Some(b), 1001);
+    assert_eq!(dag.del_edge(edge.clone()), Err(DagError::NoSuchEdge));
+    dag.add_edge(edge.clone(), &connected).expect("Failed to add edge");
+    assert_eq!(dag.del_node(b), Err(DagError::NodeHasEdges));
+    dag.del_edge(edge.clone()).expect("Failed to delete edge");
+    dag.del_node(b).expect("Failed to delete node");
+    assert_eq!(dag.add_edge(edge, &connected), Err(DagError::NoSuchNode));
+}
+
+#[test]
+/// Edges to & from null show up among a node's parents & children.
+fn test_null_edges() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), None, 1001), &connected).expect("Failed to add edge");
+    assert_eq!(dag.parents(&a).collect::<Vec<_>>(), vec![Edge::new(None, Some(a), 1000)]);
+    assert_eq!(dag.children(&a).collect::<Vec<_>>(), vec![Edge::new(Some(a), None, 1001)]);
+    assert_eq!(dag.edge_count(), 2);
+}
+
+/// Check that every node is iterated once by iter_topo, and that every edge between nodes agrees
+/// with that order.
+fn assert_topo(dag: &MyDag) {
+    let order = dag.iter_topo().cloned().collect::<Vec<NodeHandle>>();
+    assert_eq!(order.len(), dag.node_count());
+    let position = |node: &Option<NodeHandle>| order.iter().position(|n| Some(*n) == *node).unwrap();
+    for edge in dag.iter_edges().filter(|edge| edge.from().is_some() && edge.to().is_some()) {
+        assert!(position(edge.from()) < position(edge.to()), "edge disagrees with topological order");
+    }
+}
+
+#[test]
+/// iter_topo should stay consistent as edges are added against the order nodes were created in.
+fn test_iter_topo() {
+    let mut dag = MyDag::new();
+    let nodes = (0..6).map(|i| dag.add_node(i)).collect::<Vec<NodeHandle>>();
+    assert_eq!(dag.iter_topo().cloned().collect::<Vec<NodeHandle>>(), nodes);
+    // 5 -> 3 -> 1, 4 -> 2 -> 0, 3 -> 2
+    for &(from, to) in [(3, 1), (5, 3), (2, 0), (4, 2), (3, 2)].iter() {
+        dag.add_edge(Edge::new(Some(nodes[from]), Some(nodes[to]), 0), &connected).expect("Failed to add edge");
+        assert_topo(&dag);
+    }
+    // Closing a loop should be rejected & leave the order alone.
+    let before = dag.iter_topo().cloned().collect::<Vec<NodeHandle>>();
+    dag.add_edge(Edge::new(Some(nodes[0]), Some(nodes[5]), 0), &connected).expect_err("Failed to detect cycle");
+    assert_eq!(dag.iter_topo().cloned().collect::<Vec<NodeHandle>>(), before);
+    // Deleted nodes leave the order.
+    dag.del_node(nodes[1]).expect_err("Node still has edges");
+    dag.del_edge(Edge::new(Some(nodes[3]), Some(nodes[1]), 0)).expect("Failed to delete edge");
+    dag.del_node(nodes[1]).expect("Failed to delete node");
+    assert_topo(&dag);
+}
+
+#[test]
+/// Loops the predicate allows are left out of the order, but still count when checking for
+/// cycles.
+fn test_iter_topo_feedback() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    // Edges of weight 0 don't reach anything past the node they enter.
+    let pred = |edge_in: &Edge<u32>, _out: &Edge<u32>| *edge_in.weight() != 0;
+    dag.add_edge(Edge::new(Some(a), Some(b), 1), &pred).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), Some(a), 0), &pred).expect("Failed to add edge");
+    assert_eq!(dag.iter_topo().cloned().collect::<Vec<NodeHandle>>(), vec![a, b, c]);
+    // c -> a agrees with neither the order nor the existing loop, & would close a real cycle
+    // through b -> c.
+    dag.add_edge(Edge::new(Some(b), Some(c), 1), &pred).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(c), Some(a), 1), &pred).expect_err("Failed to detect cycle");
+    // Once the feedback edge is gone, the order covers every edge again.
+    dag.del_edge(Edge::new(Some(b), Some(a), 0)).expect("Failed to delete edge");
+    assert_topo(&dag);
+}
+
+#[test]
+/// A batch may pass through cyclic states, so long as the end result is acyclic; otherwise it
+/// should leave the graph untouched.
+fn test_batch() {
+    // null -> a -> b -> null becomes null -> b -> a -> null
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1001), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), None, 1002), &connected).expect("Failed to add edge");
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(Some(b), Some(a), 1001)
+        .add_edge(None, Some(b), 1000)
+        .add_edge(Some(a), None, 1002)
+        .rm_edge(None, Some(a), 1000)
+        .rm_edge(Some(a), Some(b), 1001)
+        .rm_edge(Some(b), None, 1002);
+    dag.apply_batch(batch, &connected).expect("Failed to apply batch");
+    assert_eq!(dag.iter_topo().cloned().collect::<Vec<NodeHandle>>(), vec![b, a]);
+    assert_topo(&dag);
+
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(Some(a), Some(b), 1003);
+    match dag.apply_batch(batch, &connected) {
+        Err(DagError::Cycle(cycle)) => assert_eq!(cycle.len(), 2),
+        _ => panic!("Failed to detect cycle"),
+    }
+    assert_eq!(dag.edge_count(), 3);
+    assert_topo(&dag);
+}
+
+#[test]
+/// The predicate may depend on the weight, so a new weight that closes a cycle should be
+/// rejected, leaving the old edge in place.
+fn test_set_edge_weight() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    // Edges entering `b` with weight 0 don't reach anything.
+    let pred = |edge_in: &Edge<u32>, _out: &Edge<u32>| *edge_in.weight() != 0;
+    dag.add_edge(Edge::new(Some(a), Some(b), 0), &pred).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), Some(a), 1), &pred).expect("Failed to add edge");
+    match dag.set_edge_weight(Some(a), Some(b), 0, 2, &pred) {
+        Err(DagError::Cycle(cycle)) => assert_eq!(cycle.len(), 2),
+        _ => panic!("Failed to detect cycle"),
+    }
+    assert!(dag.iter_edges().any(|edge| edge == &Edge::new(Some(a), Some(b), 0)));
+    assert_eq!(dag.set_edge_weight(Some(a), Some(b), 2, 3, &pred), Err(DagError::NoSuchEdge));
+    dag.set_edge_weight(Some(b), Some(a), 1, 3, &pred).expect("Failed to set weight");
+    assert!(dag.iter_edges().any(|edge| edge == &Edge::new(Some(b), Some(a), 3)));
+    assert_eq!(dag.edge_count(), 2);
+}
+
+/// Summarize the graph's nodes (with their data) & edges, for comparison.
+fn snapshot(dag: &MyDag) -> (HashSet<(NodeHandle, u32)>, HashSet<Edge<u32>>) {
+    let nodes = dag.iter_nodes().map(|node| (*node, *dag.node_data(*node))).collect();
+    let edges = dag.iter_edges().cloned().collect();
+    (nodes, edges)
+}
+
+#[test]
+/// Dropping/rolling back a transaction should restore the graph's nodes & edges exactly.
+fn test_transaction_rollback() {
+    // null -> a -> b -> null, with an isolated node c
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1001), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), None, 1002), &connected).expect("Failed to add edge");
+    let before = snapshot(&dag);
+
+    let d = {
+        let mut txn = dag.transaction();
+        let d = txn.add_node(14);
+        txn.del_node(c).expect("Failed to delete node");
+        txn.del_edge(Edge::new(Some(a), Some(b), 1001)).expect("Failed to delete edge");
+        txn.add_edge(Edge::new(Some(b), Some(a), 1003), &connected).expect("Failed to add edge");
+        txn.add_edge(Edge::new(Some(a), Some(d), 1004), &connected).expect("Failed to add edge");
+        // failed operations aren't recorded.
+        txn.add_edge(Edge::new(Some(d), Some(b), 1005), &connected).expect_err("Failed to detect cycle");
+        txn.del_node(a).expect_err("Deleted node with edges");
+        // the graph can be read mid-transaction.
+        assert_eq!(txn.iter_nodes().count(), 3);
+        assert_topo(&txn);
+        d
+    };
+    assert_eq!(snapshot(&dag), before);
+    assert_topo(&dag);
+    // the handle given out during the transaction isn't reused.
+    assert!(!dag.is_live(d));
+    let d = dag.add_node(15);
+
+    let mut txn = dag.transaction();
+    txn.del_edge(Edge::new(Some(b), None, 1002)).expect("Failed to delete edge");
+    txn.del_node(d).expect("Failed to delete node");
+    txn.rollback();
+    assert_eq!(*dag.node_data(d), 15);
+    assert_eq!(dag.iter_edges().count(), 3);
+}
+
+#[test]
+/// Handles of deleted nodes should be reported as stale, even once their slot is reused.
+fn test_stale_handles() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.del_node(a).expect("Failed to delete node");
+    assert!(!dag.is_live(a));
+    assert_eq!(dag.try_node_data(a), None);
+    // a's slot is reused, under a new handle.
+    let c = dag.add_node(13);
+    assert!(c != a);
+    assert!(dag.is_live(c));
+    assert_eq!(dag.try_node_data(c), Some(&13));
+    assert_eq!(dag.try_node_data(a), None);
+    assert_eq!(dag.add_edge(Edge::new(Some(a), Some(b), 1000), &connected), Err(DagError::NoSuchNode));
+    // The stale handle has no edges, rather than those of the node in its slot.
+    assert!(!OnDag::contains(&dag, &a));
+    assert_eq!((OnDag::children(&dag, &a).count(), OnDag::parents(&dag, &a).count()), (0, 0));
+    // Deleting through the stale handle doesn't touch the node in its slot.
+    dag.del_node(a).expect("Failed to delete node");
+    assert!(dag.is_live(c));
+    assert_eq!(dag.iter_nodes().count(), 2);
+    // Slots are reused rather than leaked.
+    for value in 0..10 {
+        let node = dag.add_node(value);
+        dag.del_node(node).expect("Failed to delete node");
+    }
+    let d = dag.add_node(14);
+    assert_eq!(dag.iter_nodes().count(), 3);
+
+    // Rolling back restores the slots too: d's slot is reused during the transaction, and d
+    // comes back under its original handle.
+    {
+        let mut txn = dag.transaction();
+        txn.del_node(d).expect("Failed to delete node");
+        let e = txn.add_node(15);
+        assert!(e != d && !txn.is_live(d));
+    }
+    assert_eq!(dag.try_node_data(d), Some(&14));
+    let e = dag.add_node(15);
+    assert!(e != d && dag.is_live(d));
+}
+
+#[test]
+/// Handles given out during a rolled-back transaction should stay stale, even once their slots
+/// are reused.
+fn test_rollback_handles_stay_stale() {
+    let mut dag = MyDag::new();
+    let a = {
+        let mut txn = dag.transaction();
+        txn.add_node(1)
+    };
+    let b = dag.add_node(2);
+    assert!(b != a);
+    assert!(!dag.is_live(a));
+    assert_eq!(dag.try_node_data(a), None);
+    assert_eq!(dag.try_node_data(b), Some(&2));
+
+    // The same goes for a node created in the slot of one deleted during the transaction, which
+    // comes back under its original handle.
+    let c = {
+        let mut txn = dag.transaction();
+        txn.del_node(b).expect("Failed to delete node");
+        txn.add_node(3)
+    };
+    assert!(dag.is_live(b));
+    assert!(!dag.is_live(c));
+    dag.del_node(b).expect("Failed to delete node");
+    let d = dag.add_node(4);
+    assert!(d != a && d != b && d != c);
+    assert_eq!(dag.try_node_data(c), None);
+    assert_eq!(dag.try_node_data(d), Some(&4));
+}
+
+#[test]
+/// A committed transaction should keep its modifications.
+fn test_transaction_commit() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let mut txn = dag.transaction();
+    let b = txn.add_node(12);
+    txn.add_edge(Edge::new(Some(a), Some(b), 1000), &connected).expect("Failed to add edge");
+    txn.commit();
+    assert_eq!(*dag.node_data(b), 12);
+    assert_eq!(dag.iter_edges().count(), 1);
+    assert!(dag.add_node(13) != b);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+/// Serializing & deserializing should preserve the graph, including handles.
+fn test_serde() {
+    // null -> a -> b -> null, where a node created before a was deleted.
+    let mut dag = MyDag::new();
+    let deleted = dag.add_node(10);
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.del_node(deleted).expect("Failed to delete node");
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1001), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), None, 1002), &connected).expect("Failed to add edge");
+    let json = serde_json::to_string(&dag).expect("Failed to serialize");
+    let mut loaded: MyDag = serde_json::from_str(&json).expect("Failed to deserialize");
+    assert_eq!(snapshot(&loaded), snapshot(&dag));
+    assert_topo(&loaded);
+    assert_eq!(loaded.add_node(13), dag.add_node(13));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+/// Deserializing should reject graphs that are cyclic or whose edges are inconsistent.
+fn test_serde_invalid() {
+    // a <-> b, which is only valid if the weights aren't connected.
+    let pred = |edge_in: &Edge<u32>, edge_out: &Edge<u32>| edge_in.weight() == edge_out.weight();
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.add_edge(Edge::new(Some(a), Some(b), 1), &pred).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), Some(a), 2), &pred).expect("Failed to add edge");
+    let json = serde_json::to_value(&dag).expect("Failed to serialize");
+    assert!(serde_json::from_value::<MyDag>(json.clone()).is_err(), "Failed to detect cycle");
+    let loaded = MyDag::deserialize_with(json.clone(), &pred).expect("Failed to deserialize");
+    assert_eq!(snapshot(&loaded), snapshot(&dag));
+
+    // Drop the inbound half of an edge.
+    let mut corrupt = json.clone();
+    for entry in corrupt["edges"].as_array_mut().unwrap() {
+        entry[1]["inbound"] = serde_json::Value::Array(vec![]);
+    }
+    assert!(MyDag::deserialize_with(corrupt, &pred).is_err(), "Failed to detect inconsistent edges");
+    // Refer to a slot that doesn't exist.
+    let mut corrupt = json.clone();
+    corrupt["slots"] = serde_json::Value::from(vec![0]);
+    assert!(MyDag::deserialize_with(corrupt, &pred).is_err(), "Failed to detect invalid handle");
+}
+
+#[test]
+/// DOT output should include the null terminals, and draw the highlighted cycle (including the
+/// rejected edge) in red.
+fn test_dot() {
+    // null -> a -> b -> null
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1001), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), None, 1002), &connected).expect("Failed to add edge");
+    let cycle = dag.add_edge(Edge::new(Some(b), Some(a), 1003), &connected).expect_err("Failed to detect cycle");
+    let mut out = vec![];
+    dag.write_dot(&mut out, |data| format!("\"{}\"", data), |edge| edge.weight().to_string(), cycle.cycle().unwrap())
+        .expect("Failed to write graph");
+    assert_eq!(String::from_utf8(out).unwrap(), [
+        "digraph {",
+        "    null_in [label=\"in\", shape=invhouse];",
+        "    null_out [label=\"out\", shape=house];",
+        "    n0 [label=\"\\\"11\\\"\"];",
+        "    n1 [label=\"\\\"12\\\"\"];",
+        "    null_in -> n0 [label=\"1000\"];",
+        "    n0 -> n1 [label=\"1001\", color=red, penwidth=2];",
+        "    n1 -> null_out [label=\"1002\"];",
+        "    n1 -> n0 [label=\"1003\", color=red, penwidth=2, style=dashed];",
+        "}",
+        "",
+    ].join("\n"));
+}
+
+/// Parse node labels as numbers.
+fn parse_label(label: &str) -> Result<u32, String> {
+    label.parse().map_err(|_| format!("bad label {}", label))
+}
+
+/// Parse edge weights from their labels (defaulting to 0).
+fn parse_weight(attrs: &HashMap<String, String>) -> Result<u32, String> {
+    attrs.get("label").map_or(Ok(0), |label| parse_label(label))
+}
+
+#[test]
+/// Reading DOT should create the named nodes & edges, mapping the reserved names to null.
+fn test_read_dot() {
+    let text = r#"
+        // a comment
+        strict digraph "test" {
+            rankdir = LR;
+            edge [label=5]
+            null_in -> a -> b -> null_out;
+            /* a multi-line
+               comment */
+            a -> "c" [label="7", color=red];
+            a [label=11]; b [label=12] c [label=13]
+        }
+    "#;
+    let import = MyDag::read_dot(text, parse_label, parse_weight, &connected).expect("Failed to read graph");
+    let dag = import.graph;
+    let (a, b, c) = (import.nodes["a"], import.nodes["b"], import.nodes["c"]);
+    assert_eq!(import.nodes.len(), 3);
+    assert_eq!((*dag.node_data(a), *dag.node_data(b), *dag.node_data(c)), (11, 12, 13));
+    let edges = dag.iter_edges().cloned().collect::<HashSet<_>>();
+    assert_eq!(edges, [
+        Edge::new(None, Some(a), 5),
+        Edge::new(Some(a), Some(b), 5),
+        Edge::new(Some(b), None, 5),
+        Edge::new(Some(a), Some(c), 7),
+    ].iter().cloned().collect());
+    assert_topo(&dag);
+}
+
+#[test]
+/// Graphs written as DOT should read back the same (aside from handles).
+fn test_dot_round_trip() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1001), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), None, 1002), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(None, None, 1003), &connected).expect("Failed to add edge");
+    let mut out = vec![];
+    dag.write_dot(&mut out, |data| data.to_string(), |edge| edge.weight().to_string(), &[]).expect("Failed to write graph");
+    let text = String::from_utf8(out).unwrap();
+    let loaded = MyDag::read_dot(&text, parse_label, parse_weight, &connected).expect("Failed to read graph").graph;
+    // Handles are assigned in the same order here.
+    assert_eq!(snapshot(&loaded), snapshot(&dag));
+}
+
+#[test]
+/// Errors should report the line responsible.
+fn test_read_dot_errors() {
+    let cyclic = "digraph {\n a -> b [label=1]\n b -> a [label=2]\n}";
+    match MyDag::read_dot(cyclic, parse_label_or_zero, parse_weight, &connected) {
+        Err(DotError::Cycle { line, cycle }) => {
+            assert_eq!(line, 3);
+            assert_eq!(cycle.len(), 2);
+        },
+        _ => panic!("Failed to detect cycle"),
+    }
+    // Unless the pred disconnects it.
+    let pred = |edge_in: &Edge<u32>, edge_out: &Edge<u32>| edge_in.weight() == edge_out.weight();
+    MyDag::read_dot(cyclic, parse_label_or_zero, parse_weight, &pred).expect("Failed to read graph");
+
+    let bad_label = "digraph {\n a\n b [label=x]\n}";
+    let err = MyDag::read_dot(bad_label, parse_label, parse_weight, &connected).err().unwrap();
+    assert_eq!(err.line(), 2);
+    let undirected = "digraph {\n a -> b\n b -- c\n}";
+    let err = MyDag::read_dot(undirected, parse_label_or_zero, parse_weight, &connected).err().unwrap();
+    assert_eq!(err.line(), 3);
+    let unterminated = "digraph {\n a -> b\n";
+    MyDag::read_dot(unterminated, parse_label_or_zero, parse_weight, &connected).err().unwrap();
+    // Null terminals may only be used on their own end of an edge.
+    for misplaced in ["a -> null_in", "null_out -> a", "null_out -> null_in"].iter() {
+        let text = format!("digraph {{\n a\n {} [label=1]\n}}", misplaced);
+        match MyDag::read_dot(&text, parse_label_or_zero, parse_weight, &connected) {
+            Err(DotError::Invalid { line, .. }) => assert_eq!(line, 3),
+            _ => panic!("Failed to reject {}", misplaced),
+        }
+    }
+}
+
+/// Parse node labels as numbers, treating unlabeled nodes (whose label is their name) as 0.
+fn parse_label_or_zero(label: &str) -> Result<u32, String> {
+    Ok(label.parse().unwrap_or(0))
+}
+
+/// Convert a number to a single attribute & back (the attribute is required).
+#[cfg(feature = "graphml")]
+fn to_attrs(name: &str, value: u32) -> HashMap<String, String> {
+    [(name.to_string(), value.to_string())].iter().cloned().collect()
+}
+#[cfg(feature = "graphml")]
+fn from_attrs(name: &str, attrs: &HashMap<String, String>) -> Result<u32, String> {
+    attrs.get(name).ok_or(format!("missing {}", name)).and_then(|value| parse_label(value))
+}
+
+#[cfg(feature = "graphml")]
+#[test]
+/// Writing & reading GraphML should preserve the graph, including handles.
+fn test_graphml() {
+    // null -> a -> b -> null, where a node created before a was deleted.
+    let mut dag = MyDag::new();
+    let deleted = dag.add_node(10);
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.del_node(deleted).expect("Failed to delete node");
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1001), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), None, 1002), &connected).expect("Failed to add edge");
+    let mut out = vec![];
+    dag.write_graphml(&mut out, |&data| to_attrs("value", data), |&weight| to_attrs("weight", weight)).expect("Failed to write graph");
+    let import = MyDag::read_graphml(&out[..], |attrs| from_attrs("value", attrs), |attrs| from_attrs("weight", attrs), &connected)
+        .expect("Failed to read graph");
+    let mut loaded = import.graph;
+    assert_eq!(import.nodes["n1"], a);
+    assert_eq!(snapshot(&loaded), snapshot(&dag));
+    assert_topo(&loaded);
+    assert_eq!(loaded.add_node(13), dag.add_node(13));
+}
+
+#[cfg(feature = "graphml")]
+#[test]
+/// Files from other tools should be readable: with other ids, default values & unnamed (graphics)
+/// attributes. Cycles should be reported along with their line.
+fn test_read_graphml() {
+    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns" xmlns:y="http://www.yworks.com/xml/graphml">
+  <key id="d0" for="node" attr.name="value" attr.type="int"/>
+  <key id="d1" for="edge" attr.name="weight" attr.type="int">
+    <default>5</default>
+  </key>
+  <key id="d2" for="node" yfiles.type="nodegraphics"/>
+  <graph id="G" edgedefault="directed">
+    <node id="first"><data key="d0">11</data><data key="d2"><y:ShapeNode/></data></node>
+    <node id="second"><data key="d0">12</data></node>
+    <edge source="null_in" target="first"/>
+    <edge source="first" target="second"><data key="d1">7</data></edge>
+  </graph>
+</graphml>"#;
+    let import = MyDag::read_graphml(text.as_bytes(), |attrs| from_attrs("value", attrs), |attrs| from_attrs("weight", attrs), &connected)
+        .expect("Failed to read graph");
+    let (first, second) = (import.nodes["first"], import.nodes["second"]);
+    assert_eq!((*import.graph.node_data(first), *import.graph.node_data(second)), (11, 12));
+    assert_eq!(import.graph.iter_edges().cloned().collect::<HashSet<_>>(), [
+        Edge::new(None, Some(first), 5),
+        Edge::new(Some(first), Some(second), 7),
+    ].iter().cloned().collect());
+
+    let cyclic = text.replace(r#"source="null_in" target="first""#, r#"source="second" target="first""#);
+    match MyDag::read_graphml(cyclic.as_bytes(), |attrs| from_attrs("value", attrs), |attrs| from_attrs("weight", attrs), &connected) {
+        Err(GraphmlError::Cycle { line, cycle }) => {
+            // The cycle is closed by the second edge.
+            assert_eq!(line, 12);
+            assert_eq!(cycle.len(), 2);
+        },
+        _ => panic!("Failed to detect cycle"),
+    }
+    let undirected = text.replace(r#"edgedefault="directed""#, r#"edgedefault="undirected""#);
+    let err = MyDag::read_graphml(undirected.as_bytes(), |attrs| from_attrs("value", attrs), |attrs| from_attrs("weight", attrs), &connected)
+        .err().unwrap();
+    assert_eq!(err.line(), 11);
+}
+
+#[cfg(feature = "graphml")]
+#[test]
+/// Defaults & data in elements that can't hold them should be reported along with their line.
+fn test_read_graphml_misplaced() {
+    let misplaced = [
+        // A key declared inside the graph.
+        (r#"<graph><key id="d0"><default>5</default></key></graph>"#, 3),
+        // Nodes & edges outside of the graph.
+        (r#"<node id="n0"><data key="d0">5</data></node><graph/>"#, 3),
+        (r#"<edge source="n0" target="n1"><data key="d0">5</data></edge><graph/>"#, 3),
+    ];
+    for &(body, line) in misplaced.iter() {
+        let text = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<graphml>\n{}\n</graphml>", body);
+        match MyDag::read_graphml(text.as_bytes(), |attrs| from_attrs("value", attrs), |attrs| from_attrs("weight", attrs), &connected) {
+            Err(GraphmlError::Invalid { line: found, .. }) => assert_eq!(found, line),
+            _ => panic!("Failed to reject {}", body),
+        }
+    }
+}
+
+#[cfg(feature = "graphml")]
+#[test]
+/// Edges should only connect declared nodes, & the terminals only on their own end.
+fn test_read_graphml_endpoints() {
+    for &(source, target) in [("null_out", "n0"), ("n0", "null_in"), ("n0", "n2")].iter() {
+        let text = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml>
+  <key id="d0" for="all" attr.name="value"><default>5</default></key>
+  <graph>
+    <node id="n0"/>
+    <node id="n1"/>
+    <edge source="{}" target="{}"/>
+  </graph>
+</graphml>"#, source, target);
+        match MyDag::read_graphml(text.as_bytes(), |attrs| from_attrs("value", attrs), |attrs| from_attrs("value", attrs), &connected) {
+            Err(GraphmlError::Invalid { line, .. }) => assert_eq!(line, 7),
+            _ => panic!("Failed to reject {} -> {}", source, target),
+        }
+    }
+}
+
+/// Encode a number in 4 bytes & back.
+fn encode_u32(value: &u32) -> Vec<u8> {
+    value.to_le_bytes().to_vec()
+}
+fn decode_u32(bytes: &[u8]) -> Result<u32, String> {
+    match bytes.len() {
+        4 => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        len => Err(format!("expected 4 bytes, found {}", len)),
+    }
+}
+
+#[test]
+/// Writing & reading the binary format should preserve the graph, including handles.
+fn test_binary() {
+    // null -> a -> b -> null & a -> c, where a node created before a was deleted.
+    let mut dag = MyDag::new();
+    let deleted = dag.add_node(10);
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.del_node(deleted).expect("Failed to delete node");
+    dag.add_edge(Edge::new(None, Some(a), 1000), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1001), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(b), 1002), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(a), Some(c), 1003), &connected).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), None, 1004), &connected).expect("Failed to add edge");
+    let mut out = vec![];
+    dag.write_binary(&mut out, encode_u32, encode_u32).expect("Failed to write graph");
+    assert!(out.starts_with(binary::MAGIC));
+    let mut loaded = MyDag::read_binary(&out[..], decode_u32, decode_u32, &connected).expect("Failed to read graph");
+    assert_eq!(snapshot(&loaded), snapshot(&dag));
+    assert_topo(&loaded);
+    assert_eq!(loaded.add_node(14), dag.add_node(14));
+
+    // Every prefix of the input is truncated.
+    for len in 0..out.len() {
+        match MyDag::read_binary(&out[..len], decode_u32, decode_u32, &connected) {
+            Err(BinaryError::Truncated) => {},
+            _ => panic!("Failed to detect truncation at {} bytes", len),
+        }
+    }
+    let mut corrupt = out.clone();
+    corrupt[0] = b'X';
+    match MyDag::read_binary(&corrupt[..], decode_u32, decode_u32, &connected) {
+        Err(BinaryError::BadMagic) => {},
+        _ => panic!("Failed to detect bad magic"),
+    }
+    corrupt = out.clone();
+    corrupt[binary::MAGIC.len()] = 99;
+    match MyDag::read_binary(&corrupt[..], decode_u32, decode_u32, &connected) {
+        Err(BinaryError::UnsupportedVersion(99)) => {},
+        _ => panic!("Failed to detect unsupported version"),
+    }
+}
+
+#[test]
+/// Reading the binary format should reject cycles, according to the predicate.
+fn test_binary_cycles() {
+    // a <-> b, which is only valid if the weights aren't connected.
+    let pred = |edge_in: &Edge<u32>, edge_out: &Edge<u32>| edge_in.weight() == edge_out.weight();
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.add_edge(Edge::new(Some(a), Some(b), 1), &pred).expect("Failed to add edge");
+    dag.add_edge(Edge::new(Some(b), Some(a), 2), &pred).expect("Failed to add edge");
+    let mut out = vec![];
+    dag.write_binary(&mut out, encode_u32, encode_u32).expect("Failed to write graph");
+    match MyDag::read_binary(&out[..], decode_u32, decode_u32, &connected) {
+        Err(BinaryError::Cycle(cycle)) => assert_eq!(cycle.len(), 2),
+        _ => panic!("Failed to detect cycle"),
+    }
+    let loaded = MyDag::read_binary(&out[..], decode_u32, decode_u32, &pred).expect("Failed to read graph");
+    assert_eq!(snapshot(&loaded), snapshot(&dag));
+}
+
+#[test]
+/// Reading a large graph whose slots are ordered against its edges should restore the saved order
+/// rather than rebuild it (which would take quadratic time).
+fn test_binary_large() {
+    // 0 -> 1 -> ... -> 99999, plus i -> i + 2, where each node occupies a lower slot than the
+    // nodes before it (the slots freed by deleting placeholders are reused lowest first).
+    let count = 100000;
+    let mut dag = MyDag::new();
+    let mut placeholders = (0..count).map(|value| dag.add_node(value)).collect::<Vec<_>>();
+    let mut nodes: Vec<NodeHandle> = vec![];
+    for value in 0..count {
+        dag.del_node(placeholders.pop().unwrap()).expect("Failed to delete node");
+        let node = dag.add_node(value);
+        let len = nodes.len();
+        for &prev in nodes[len.saturating_sub(2)..].iter() {
+            dag.add_edge(Edge::new(Some(prev), Some(node), value), &connected).expect("Failed to add edge");
+        }
+        nodes.push(node);
+    }
+    let mut out = vec![];
+    dag.write_binary(&mut out, encode_u32, encode_u32).expect("Failed to write graph");
+    let loaded = MyDag::read_binary(&out[..], decode_u32, decode_u32, &connected).expect("Failed to read graph");
+    assert_eq!(loaded.node_count(), count as usize);
+    assert_eq!(loaded.edge_count(), 2 * count as usize - 3);
+    assert_eq!(loaded.iter_topo().cloned().collect::<Vec<_>>(), nodes);
+}
@@ -0,0 +1,227 @@
+use ::error::DagError;
+use ::iodagfull::{Edge, EdgeFrom, EdgeTo, FromNode, FromNull, IODagFull, NodeHandle, ToNode, ToNull};
+use ::ondag::OnDag;
+use super::testondag::OnDagTestable;
+
+#[cfg(feature = "graphml")]
+use ::graphml::GraphmlError;
+#[cfg(feature = "graphml")]
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde_json;
+
+/// Node -> node edges carry a (u32, u32) weight; edges to/from null carry a str.
+type MyDag = IODagFull<u32, u32, &'static str, u32, &'static str>;
+type MyEdge = Edge<u32, &'static str, u32, &'static str>;
+
+
+// Declare tests for common OnDag functionality (inserting/removing nodes and edges).
+def_ondag_tests!{MyDag}
+
+impl OnDagTestable<(u32, u32)> for MyDag {
+    /// Use the same weight for both components.
+    fn weight(w: u32) -> (u32, u32) {
+        (w, w)
+    }
+    fn edge_weight(edge: &MyEdge) -> u32 {
+        match *edge.from() {
+            EdgeFrom::Node(ref from) => *from.weight(),
+            EdgeFrom::Null(_) => panic!("Expected an edge from a node"),
+        }
+    }
+    fn node_value(&self, node: &NodeHandle) -> u32 {
+        *self.node_data(*node)
+    }
+}
+
+fn mid_edge(from: NodeHandle, to: NodeHandle, weight: u32) -> MyEdge {
+    Edge::new(EdgeFrom::Node(FromNode::new(from, weight)), EdgeTo::Node(ToNode::new(to, weight)))
+}
+
+
+#[test]
+/// Graph should not allow cycles - the error should name the cycle & structure should be
+/// unmodified.
+fn test_cycles() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(mid_edge(a, b, 1001)).expect("Failed to add edge");
+    dag.add_edge(mid_edge(b, c, 1002)).expect("Failed to add edge");
+    let cycle = dag.add_edge(mid_edge(c, a, 1003)).expect_err("Failed to detect cycle");
+    assert_eq!(cycle, DagError::Cycle(vec![mid_edge(c, a, 1003), mid_edge(a, b, 1001), mid_edge(b, c, 1002)]));
+    assert_eq!(dag.edge_count(), 2);
+}
+
+
+#[test]
+/// Paths should follow the edges' direction, ignoring edges to & from null.
+fn test_find_path() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(mid_edge(a, b, 1001)).expect("Failed to add edge");
+    dag.add_edge(mid_edge(b, c, 1002)).expect("Failed to add edge");
+    dag.add_edge(Edge::new(EdgeFrom::Node(FromNode::new(a, 1)), EdgeTo::Null(ToNull::new("out")))).expect("Failed to add edge");
+    assert_eq!(dag.find_path(a, c), Some(vec![mid_edge(a, b, 1001), mid_edge(b, c, 1002)]));
+    assert_eq!(dag.find_path(c, a), None);
+    assert!(dag.is_reachable(b, b) && !dag.is_reachable(c, b));
+    assert!(dag.would_create_cycle(c, a) && !dag.would_create_cycle(a, c));
+}
+
+#[test]
+/// Edges to & from null show up among a node's parents & children, and a node can only be
+/// deleted once they're gone.
+fn test_null_edges() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let input = Edge::new(EdgeFrom::Null(FromNull::new("in")), EdgeTo::Node(ToNode::new(a, 1)));
+    let output = Edge::new(EdgeFrom::Node(FromNode::new(a, 2)), EdgeTo::Null(ToNull::new("out")));
+    dag.add_edge(input.clone()).expect("Failed to add edge");
+    dag.add_edge(output.clone()).expect("Failed to add edge");
+    assert_eq!(dag.parents(&a).collect::<Vec<_>>(), vec![input.clone()]);
+    assert_eq!(dag.children(&a).collect::<Vec<_>>(), vec![output.clone()]);
+    assert_eq!(dag.edge_count(), 2);
+
+    assert_eq!(dag.del_node(a), Err(DagError::NodeHasEdges));
+    dag.del_edge(output).expect("Failed to delete edge");
+    assert_eq!(dag.del_node(a), Err(DagError::NodeHasEdges));
+    dag.del_edge(input.clone()).expect("Failed to delete edge");
+    assert_eq!(dag.del_edge(input), Err(DagError::NoSuchEdge));
+    dag.del_node(a).expect("Failed to delete node");
+    assert!(!dag.contains(&a));
+}
+
+#[test]
+/// Handles of deleted nodes should be reported as stale, even once their slot is reused.
+fn test_stale_handles() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.del_node(a).expect("Failed to delete node");
+    let c = dag.add_node(13);
+    assert!(c != a);
+    assert!(!dag.is_live(a) && dag.is_live(c));
+    assert_eq!((dag.try_node_data(a), dag.try_node_data(c)), (None, Some(&13)));
+    assert_eq!(dag.add_edge(mid_edge(a, b, 1001)), Err(DagError::NoSuchNode));
+    // The stale handle has no edges, rather than those of the node in its slot.
+    assert!(!OnDag::contains(&dag, &a));
+    assert_eq!((OnDag::children(&dag, &a).count(), OnDag::parents(&dag, &a).count()), (0, 0));
+    dag.del_node(a).expect("Failed to delete node");
+    assert_eq!(dag.node_count(), 2);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+/// Serializing & deserializing should preserve the graph, including handles; corrupted input
+/// should be rejected.
+fn test_serde() {
+    // Borrowed weights can't outlive the serialized text, so use owned ones.
+    type OwnedDag = IODagFull<u32, u32, String, u32, String>;
+    let mut dag = OwnedDag::new();
+    let deleted = dag.add_node(10);
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.del_node(deleted).expect("Failed to delete node");
+    let input = Edge::new(EdgeFrom::Null(FromNull::new("in".to_string())), EdgeTo::Node(ToNode::new(a, 1)));
+    let output = Edge::new(EdgeFrom::Node(FromNode::new(b, 2)), EdgeTo::Null(ToNull::new("out".to_string())));
+    let mid = Edge::new(EdgeFrom::Node(FromNode::new(a, 3)), EdgeTo::Node(ToNode::new(b, 4)));
+    dag.add_edge(input.clone()).expect("Failed to add edge");
+    dag.add_edge(output.clone()).expect("Failed to add edge");
+    dag.add_edge(mid.clone()).expect("Failed to add edge");
+    let json = serde_json::to_value(&dag).expect("Failed to serialize");
+    let mut loaded: OwnedDag = serde_json::from_value(json.clone()).expect("Failed to deserialize");
+    assert_eq!(loaded.node_count(), 2);
+    assert_eq!(*loaded.node_data(b), 12);
+    assert_eq!(loaded.parents(&a).collect::<Vec<_>>(), vec![input]);
+    assert_eq!(loaded.parents(&b).collect::<Vec<_>>(), vec![mid.clone()]);
+    assert_eq!(loaded.children(&b).collect::<Vec<_>>(), vec![output]);
+    assert_eq!(loaded.add_node(13), dag.add_node(13));
+
+    // Drop the inbound half of each edge.
+    let mut corrupt = json.clone();
+    for entry in corrupt["nodes"].as_array_mut().unwrap() {
+        entry[1]["inbound"] = serde_json::Value::Array(vec![]);
+    }
+    assert!(serde_json::from_value::<OwnedDag>(corrupt).is_err(), "Failed to detect inconsistent edges");
+    // Close a cycle b -> a.
+    let mut corrupt = json.clone();
+    let cycle = serde_json::to_value(mid_edge(b, a, 5)).unwrap();
+    for entry in corrupt["nodes"].as_array_mut().unwrap() {
+        let set = if entry[0]["index"] == 1 { "inbound" } else { "outbound" };
+        entry[1][set].as_array_mut().unwrap().push(cycle.clone());
+    }
+    assert!(serde_json::from_value::<OwnedDag>(corrupt).is_err(), "Failed to detect cycle");
+}
+
+#[test]
+/// DOT output should draw null endpoints as terminals, with the edges labeled as requested.
+fn test_dot() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let input = Edge::new(EdgeFrom::Null(FromNull::new("in")), EdgeTo::Node(ToNode::new(a, 1)));
+    dag.add_edge(input.clone()).expect("Failed to add edge");
+    let mut out = vec![];
+    let edge_label = |edge: &MyEdge| match (edge.from(), edge.to()) {
+        (EdgeFrom::Null(from), EdgeTo::Node(to)) => format!("{}/{}", from.weight(), to.weight()),
+        _ => "?".to_string(),
+    };
+    dag.write_dot(&mut out, |data| data.to_string(), edge_label, &[input]).expect("Failed to write graph");
+    assert_eq!(String::from_utf8(out).unwrap(), [
+        "digraph {",
+        "    null_in [label=\"in\", shape=invhouse];",
+        "    n0 [label=\"11\"];",
+        "    null_in -> n0 [label=\"in/1\", color=red, penwidth=2];",
+        "}",
+        "",
+    ].join("\n"));
+}
+
+#[cfg(feature = "graphml")]
+#[test]
+/// Writing & reading GraphML should preserve the graph (including handles), with the four weight
+/// types kept apart.
+fn test_graphml() {
+    type OwnedDag = IODagFull<u32, u32, String, u32, String>;
+    let mut dag = OwnedDag::new();
+    let deleted = dag.add_node(10);
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    dag.del_node(deleted).expect("Failed to delete node");
+    let input = Edge::new(EdgeFrom::Null(FromNull::new("in".to_string())), EdgeTo::Node(ToNode::new(a, 1)));
+    let output = Edge::new(EdgeFrom::Node(FromNode::new(b, 2)), EdgeTo::Null(ToNull::new("out".to_string())));
+    let mid = Edge::new(EdgeFrom::Node(FromNode::new(a, 3)), EdgeTo::Node(ToNode::new(b, 4)));
+    dag.add_edge(input.clone()).expect("Failed to add edge");
+    dag.add_edge(output.clone()).expect("Failed to add edge");
+    dag.add_edge(mid.clone()).expect("Failed to add edge");
+
+    // Every converter uses the same attribute name.
+    let attrs = |value: String| [("w".to_string(), value)].iter().cloned().collect::<HashMap<_, _>>();
+    let number = |attrs: &HashMap<String, String>| attrs["w"].parse::<u32>().map_err(|err| err.to_string());
+    let text = |attrs: &HashMap<String, String>| Ok(attrs["w"].clone());
+    let mut out = vec![];
+    dag.write_graphml(&mut out, |data| attrs(data.to_string()), |w| attrs(w.to_string()), |w| attrs(w.clone()),
+        |w| attrs(w.to_string()), |w| attrs(w.clone())).expect("Failed to write graph");
+    let xml = String::from_utf8(out).unwrap();
+    assert!(xml.contains("attr.name=\"from_node.w\"") && xml.contains("attr.name=\"to_null.w\""));
+    let mut loaded = OwnedDag::read_graphml(xml.as_bytes(), number, number, text, number, text).expect("Failed to read graph").graph;
+    assert_eq!(loaded.node_count(), 2);
+    assert_eq!(*loaded.node_data(b), 12);
+    assert_eq!(loaded.parents(&a).collect::<Vec<_>>(), vec![input]);
+    assert_eq!(loaded.parents(&b).collect::<Vec<_>>(), vec![mid]);
+    assert_eq!(loaded.children(&b).collect::<Vec<_>>(), vec![output]);
+    assert_eq!(loaded.add_node(13), dag.add_node(13));
+
+    // Edges can't enter the input terminal, leave the output terminal or name undeclared nodes.
+    for &(from, to) in [("source=\"null_in\"", "source=\"null_out\""), ("target=\"null_out\"", "target=\"null_in\""),
+        ("target=\"null_out\"", "target=\"n9\"")].iter() {
+        let changed = xml.replace(from, to);
+        match OwnedDag::read_graphml(changed.as_bytes(), number, number, text, number, text) {
+            Err(GraphmlError::Invalid { .. }) => {},
+            _ => panic!("Failed to reject {}", to),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use ::error::DagError;
+use ::iodag::{Edge, IODag};
+use ::iodaghistory::IODagHistory;
+
+type MyHistory = IODagHistory<u32, u32>;
+
+/// Every edge entering a node reaches every edge leaving it.
+fn connected(_in: &Edge<u32>, _out: &Edge<u32>) -> bool {
+    true
+}
+
+/// Edges are only connected through a node if their weights match.
+fn same_weight(edge_in: &Edge<u32>, edge_out: &Edge<u32>) -> bool {
+    edge_in.weight() == edge_out.weight()
+}
+
+
+#[test]
+/// Undo should revert a whole step, restoring node data & edge weights; redo re-applies it.
+fn test_undo_redo() {
+    let mut history = MyHistory::new(IODag::new());
+    let a = history.add_node(11);
+    history.begin_step("connect");
+    let b = history.add_node(12);
+    history.add_edge(Edge::new(Some(a), Some(b), 1000), &connected).expect("Failed to add edge");
+    history.add_edge(Edge::new(Some(b), None, 1001), &connected).expect("Failed to add edge");
+    history.end_step();
+    history.del_edge(Edge::new(Some(b), None, 1001)).expect("Failed to delete edge");
+    history.del_edge(Edge::new(Some(a), Some(b), 1000)).expect("Failed to delete edge");
+    history.del_node(b).expect("Failed to delete node");
+    assert_eq!(history.iter_nodes().count(), 1);
+
+    assert_eq!(history.undo_name(), Some("del_node"));
+    for _ in 0..3 {
+        assert_eq!(history.undo(&connected), Ok(true));
+    }
+    assert_eq!(*history.node_data(b), 12);
+    assert_eq!(history.iter_edges().count(), 2);
+    assert_eq!(history.undo_name(), Some("connect"));
+    assert_eq!(history.undo(&connected), Ok(true));
+    assert_eq!(history.iter_nodes().collect::<Vec<_>>(), vec![&a]);
+    assert_eq!(history.iter_edges().count(), 0);
+
+    assert_eq!(history.redo_name(), Some("connect"));
+    assert_eq!(history.redo(&connected), Ok(true));
+    assert!(history.iter_edges().any(|edge| edge == &Edge::new(Some(a), Some(b), 1000)));
+    assert!(history.iter_edges().any(|edge| edge == &Edge::new(Some(b), None, 1001)));
+    assert_eq!(history.undo(&connected), Ok(true));
+    assert_eq!(history.undo(&connected), Ok(true));
+    assert_eq!(history.undo(&connected), Ok(false));
+    assert_eq!(history.iter_nodes().count(), 0);
+    // a new edit discards the undone steps.
+    history.add_node(13);
+    assert_eq!(history.redo_name(), None);
+    assert_eq!(history.redo(&connected), Ok(false));
+}
+
+#[test]
+/// Undo/redo should fail cleanly (leaving the graph & history as they were) if they would
+/// create a cycle.
+fn test_undo_redo_cycles() {
+    let mut history = MyHistory::new(IODag::new());
+    let a = history.add_node(11);
+    let b = history.add_node(12);
+    // a <-> b, which isn't a cycle so long as the differing weights aren't connected.
+    history.begin_step("loop");
+    history.add_edge(Edge::new(Some(a), Some(b), 1), &same_weight).expect("Failed to add edge");
+    history.add_edge(Edge::new(Some(b), Some(a), 2), &same_weight).expect("Failed to add edge");
+    history.end_step();
+    history.undo(&same_weight).expect("Failed to undo");
+    match history.redo(&connected) {
+        Err(DagError::Cycle(cycle)) => assert_eq!(cycle.len(), 2),
+        _ => panic!("Failed to detect cycle"),
+    }
+    assert_eq!(history.iter_edges().count(), 0);
+    assert_eq!(history.redo_name(), Some("loop"));
+    assert_eq!(history.redo(&same_weight), Ok(true));
+    assert_eq!(history.iter_edges().count(), 2);
+
+    history.del_edge(Edge::new(Some(b), Some(a), 2)).expect("Failed to delete edge");
+    history.undo(&connected).expect_err("Failed to detect cycle");
+    assert_eq!(history.iter_edges().count(), 1);
+    assert_eq!(history.undo_name(), Some("del_edge"));
+    assert_eq!(history.undo(&same_weight), Ok(true));
+    assert_eq!(history.iter_edges().count(), 2);
+}
@@ -1,24 +1,38 @@
+use ::ondag::OnDag;
+
+/// Lets the shared tests describe node data & edge weights as plain u32s, whatever types the
+/// DAG under test actually uses.
+pub trait OnDagTestable<E>: OnDag<u32, E> {
+    /// Build an edge weight from a u32.
+    fn weight(w: u32) -> E;
+    /// Recover the u32 that `weight` was built from.
+    fn edge_weight(edge: &Self::Edge) -> u32;
+    fn node_value(&self, node: &Self::NodeHandle) -> u32;
+}
+
 /// Macros to test basic functionality that everything adhering to the OnDag trait
 /// should achieve.
-/// Usage: def_ondag_tests!{MyDagType}
+/// The trait methods are called explicitly, since some DAGs have inherent methods of the same
+/// name but with a different signature.
+/// Usage: def_ondag_tests!{MyDagType}, where MyDagType implements OnDagTestable.
 macro_rules! def_ondag_tests {
     ($DagType:ident) => {
         #[test]
         /// After construction and adding a root, iter_topo should iterate only that root
         fn test_root() {
             let mut dag = $DagType::new();
-            let root = dag.add_node(12);
-            assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12]);
+            let root = OnDag::add_node(&mut dag, 12);
+            assert_eq!(OnDag::iter_topo(&dag, &root).map(|handle| { dag.node_value(&handle) }).collect::<Vec<u32>>(), vec![12]);
         }
 
         #[test]
         /// After adding some nodes, with no edges, they should *not* be included in a topo_sort
         fn test_orphans() {
-            let mut dag = MyDag::new();
-            let root = dag.add_node(12);
-            dag.add_node(2);
-            dag.add_node(1);
-            assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12]);
+            let mut dag = $DagType::new();
+            let root = OnDag::add_node(&mut dag, 12);
+            OnDag::add_node(&mut dag, 2);
+            OnDag::add_node(&mut dag, 1);
+            assert_eq!(OnDag::iter_topo(&dag, &root).map(|handle| { dag.node_value(&handle) }).collect::<Vec<u32>>(), vec![12]);
         }
 
         #[test]
@@ -29,13 +43,13 @@ macro_rules! def_ondag_tests {
             //     1
             //     v
             //     2
-            let mut dag = MyDag::new();
+            let mut dag = $DagType::new();
             let root = dag.add_node(12);
             let n2 = dag.add_node(2);
             let n1 = dag.add_node(1);
-            dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
-            dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
-            assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12, 1, 2]);
+            OnDag::add_edge(&mut dag, &root, &n1, $DagType::weight(1001)).expect("Failed to add edge");
+            OnDag::add_edge(&mut dag, &n1, &n2, $DagType::weight(1002)).expect("Failed to add edge");
+            assert_eq!(OnDag::iter_topo(&dag, &root).map(|handle| { dag.node_value(&handle) }).collect::<Vec<u32>>(), vec![12, 1, 2]);
         }
 
         #[test]
@@ -46,20 +60,117 @@ macro_rules! def_ondag_tests {
             //     1
             //     v
             //     2
-            let mut dag = MyDag::new();
+            let mut dag = $DagType::new();
             let root = dag.add_node(12);
             let n2 = dag.add_node(2);
             let n1 = dag.add_node(1);
-            dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
-            dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
-            assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12, 1, 2]);
+            OnDag::add_edge(&mut dag, &root, &n1, $DagType::weight(1001)).expect("Failed to add edge");
+            OnDag::add_edge(&mut dag, &n1, &n2, $DagType::weight(1002)).expect("Failed to add edge");
+            assert_eq!(OnDag::iter_topo(&dag, &root).map(|handle| { dag.node_value(&handle) }).collect::<Vec<u32>>(), vec![12, 1, 2]);
             // rm link to 2.
-            dag.rm_edge(&n1, &n2, 1002).expect("Failed to rm edge");
-            assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12, 1]);
+            OnDag::rm_edge(&mut dag, &n1, &n2, $DagType::weight(1002)).expect("Failed to rm edge");
+            assert_eq!(OnDag::iter_topo(&dag, &root).map(|handle| { dag.node_value(&handle) }).collect::<Vec<u32>>(), vec![12, 1]);
             // add link back & remove link to 1.
-            dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
-            dag.rm_edge(&root, &n1, 1001).expect("Failed to rm edge");
-            assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12]);
+            OnDag::add_edge(&mut dag, &n1, &n2, $DagType::weight(1002)).expect("Failed to add edge");
+            OnDag::rm_edge(&mut dag, &root, &n1, $DagType::weight(1001)).expect("Failed to rm edge");
+            assert_eq!(OnDag::iter_topo(&dag, &root).map(|handle| { dag.node_value(&handle) }).collect::<Vec<u32>>(), vec![12]);
+        }
+
+        #[test]
+        /// children and parents should mirror each other as edges come and go.
+        fn test_children_parents() {
+            //     12
+            //     v  \
+            //     1   |
+            //     v  /
+            //     2
+            let mut dag = $DagType::new();
+            let root = dag.add_node(12);
+            let n1 = dag.add_node(1);
+            let n2 = dag.add_node(2);
+            OnDag::add_edge(&mut dag, &root, &n1, $DagType::weight(1001)).expect("Failed to add edge");
+            OnDag::add_edge(&mut dag, &n1, &n2, $DagType::weight(1002)).expect("Failed to add edge");
+            OnDag::add_edge(&mut dag, &root, &n2, $DagType::weight(1003)).expect("Failed to add edge");
+            let mut weights = dag.children(&root).map(|edge| { $DagType::edge_weight(&edge) }).collect::<Vec<u32>>();
+            weights.sort();
+            assert_eq!(weights, vec![1001, 1003]);
+            let mut weights = dag.parents(&n2).map(|edge| { $DagType::edge_weight(&edge) }).collect::<Vec<u32>>();
+            weights.sort();
+            assert_eq!(weights, vec![1002, 1003]);
+            assert_eq!(dag.parents(&root).count(), 0);
+            assert_eq!(dag.children(&n2).count(), 0);
+            OnDag::rm_edge(&mut dag, &root, &n2, $DagType::weight(1003)).expect("Failed to rm edge");
+            assert_eq!(dag.parents(&n2).map(|edge| { $DagType::edge_weight(&edge) }).collect::<Vec<u32>>(), vec![1002]);
+        }
+
+        #[test]
+        /// node_count, edge_count and contains should track the graph's contents.
+        fn test_counts() {
+            let mut dag = $DagType::new();
+            assert_eq!((dag.node_count(), dag.edge_count()), (0, 0));
+            let root = dag.add_node(12);
+            let n1 = dag.add_node(1);
+            assert!(dag.contains(&root) && dag.contains(&n1));
+            OnDag::add_edge(&mut dag, &root, &n1, $DagType::weight(1001)).expect("Failed to add edge");
+            assert_eq!((dag.node_count(), dag.edge_count()), (2, 1));
+            OnDag::rm_edge(&mut dag, &root, &n1, $DagType::weight(1001)).expect("Failed to rm edge");
+            assert_eq!((dag.node_count(), dag.edge_count()), (2, 0));
+        }
+
+        #[test]
+        /// Cycle checks should search each node once, rather than once per path through it: on a
+        /// stack of diamonds, the number of paths doubles with each level.
+        fn test_diamond_stack() {
+            // top -> left, right -> next top -> ... -> bottom
+            let mut dag = $DagType::new();
+            let tops = (0..101).map(|level| OnDag::add_node(&mut dag, level)).collect::<Vec<_>>();
+            // Insert from the bottom up, so that every insertion checks the whole stack beneath it.
+            for level in (0..100).rev() {
+                let left = OnDag::add_node(&mut dag, 1000);
+                let right = OnDag::add_node(&mut dag, 2000);
+                for side in [&left, &right].iter() {
+                    OnDag::add_edge(&mut dag, side, &tops[level + 1], $DagType::weight(0)).expect("Failed to add edge");
+                    OnDag::add_edge(&mut dag, &tops[level], side, $DagType::weight(0)).expect("Failed to add edge");
+                }
+            }
+            assert!(OnDag::add_edge(&mut dag, &tops[100], &tops[0], $DagType::weight(0)).is_err());
+            assert_eq!(OnDag::iter_topo(&dag, &tops[0]).count(), 301);
+        }
+    }
+}
+
+/// Tests of deep graphs, which take too long to run for every OnDag type, declared once per
+/// family of implementations that share their searches. The largest are ignored by default: run
+/// them with `cargo test -- --ignored`.
+/// Usage: def_ondag_long_tests!{MyDagType}, where MyDagType implements OnDagTestable.
+macro_rules! def_ondag_long_tests {
+    ($DagType:ident) => {
+        /// Build the chain `0 -> 1 -> ... -> len - 1`, checking that searches & sorts get through
+        /// it.
+        fn check_long_chain(len: u32) {
+            let mut dag = $DagType::new();
+            // Handles are kept for every node, so that the chain is freed from the front.
+            let nodes = (0..len).map(|value| OnDag::add_node(&mut dag, value)).collect::<Vec<_>>();
+            for pair in nodes.windows(2) {
+                OnDag::add_edge(&mut dag, &pair[0], &pair[1], $DagType::weight(0)).expect("Failed to add edge");
+            }
+            let last = len as usize - 1;
+            assert!(OnDag::add_edge(&mut dag, &nodes[last], &nodes[0], $DagType::weight(0)).is_err());
+            assert_eq!(OnDag::iter_topo(&dag, &nodes[0]).count(), len as usize);
+            assert_eq!(OnDag::iter_topo_rev(&dag, &nodes[0]).next().map(|node| { dag.node_value(&node) }), Some(len - 1));
+        }
+
+        #[test]
+        /// Searches & sorts should handle paths far longer than the call stack could.
+        fn test_long_chain() {
+            check_long_chain(100000);
+        }
+
+        #[test]
+        #[ignore]
+        /// As `test_long_chain`, at a size that takes too long to run every time.
+        fn test_very_long_chain() {
+            check_long_chain(1000000);
         }
     }
 }
@@ -1,11 +1,27 @@
+use ::batch::EdgeBatch;
+use ::error::DagError;
 use ::ondag::OnDag;
-use ::poscostdag::{CostQueriable, HalfEdge, PosCostDag};
+use ::poscostdag::{CostQueriable, FullEdge, HalfEdge, NodeHandle, PosCostDag};
+use super::testondag::OnDagTestable;
 
 type MyDag = PosCostDag<u32, u32>;
 
 
 // Declare tests for common OnDag functionality (inserting/removing nodes and edges).
 def_ondag_tests!{MyDag}
+def_ondag_long_tests!{MyDag}
+
+impl OnDagTestable<u32> for MyDag {
+    fn weight(w: u32) -> u32 {
+        w
+    }
+    fn edge_weight(edge: &FullEdge<u32, u32>) -> u32 {
+        *edge.weight()
+    }
+    fn node_value(&self, node: &NodeHandle<u32, u32>) -> u32 {
+        node.node_data()
+    }
+}
 
 
 #[test]
@@ -23,7 +39,94 @@ fn test_cycles() {
     dag.add_edge(&a, &b, 4).expect("Failed to add edge");
     dag.add_edge(&b, &c, 0).expect("Failed to add edge");
     dag.add_edge(&c, &a, 0).expect("Failed to add edge");
-    dag.add_edge(&a, &b, 0).err().expect("Failed to detect cycle");
+    let cycle = dag.add_edge(&a, &b, 0).expect_err("Failed to detect cycle");
+    // The witness is the 0-cycle: the new edge, then back around to a.
+    match cycle {
+        DagError::ZeroCostCycle(path) => {
+            assert_eq!(path.iter().map(|edge| { edge.from().node_data() }).collect::<Vec<u32>>(), vec![11, 12, 13]);
+            assert!(path.iter().all(|edge| { edge.weight() == &0 }));
+        },
+        _ => panic!("Expected a zero-cost cycle"),
+    }
+    // The structure should be unmodified: only the 4-cost edge leaves a.
+    assert_eq!(dag.children(&a).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![4]);
+}
+
+
+#[test]
+/// Paths may have any cost, but only edges that would close a 0-cycle should be reported as
+/// creating one.
+fn test_find_path() {
+    // A --4--> B --0--> C
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &b, 4).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 0).expect("Failed to add edge");
+    let path = dag.find_path(&a, &c).expect("Failed to find path");
+    assert_eq!(path.iter().map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![4, 0]);
+    assert!(dag.is_reachable(&a, &c) && !dag.is_reachable(&c, &a));
+    // C --0--> A only closes a positive-cost cycle, but C --0--> B closes a 0-cycle.
+    assert!(!dag.would_create_cycle(&c, &a, 0));
+    assert!(dag.would_create_cycle(&c, &b, 0) && !dag.would_create_cycle(&c, &b, 1));
+    assert_eq!(dag.edge_count(), 2);
+}
+
+#[test]
+/// A batch should only be rejected if its end result has a 0-cycle, and then leave the graph
+/// untouched.
+fn test_batch() {
+    // A --4--> B --0--> C --0--> A
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &b, 4).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 0).expect("Failed to add edge");
+    dag.add_edge(&c, &a, 0).expect("Failed to add edge");
+    // Moving the cost from A -> B onto C -> A passes through a 0-cycle.
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(a.clone(), b.clone(), 0)
+        .add_edge(c.clone(), a.clone(), 4)
+        .rm_edge(a.clone(), b.clone(), 4)
+        .rm_edge(c.clone(), a.clone(), 0);
+    dag.apply_batch(batch).expect("Failed to apply batch");
+    assert_eq!(dag.children(&c).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![4]);
+    // But removing the cost altogether isn't allowed.
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(c.clone(), a.clone(), 0)
+        .rm_edge(c.clone(), a.clone(), 4);
+    match dag.apply_batch(batch) {
+        Err(DagError::ZeroCostCycle(cycle)) => assert_eq!(cycle.len(), 3),
+        _ => panic!("Failed to detect cycle"),
+    }
+    assert_eq!(dag.children(&c).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![4]);
+    assert_eq!(dag.edge_count(), 3);
+}
+
+#[test]
+/// A new weight that closes a 0-cycle should be rejected, leaving the old edge in place.
+fn test_set_edge_weight() {
+    // A --4--> B --0--> C --0--> A
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &b, 4).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 0).expect("Failed to add edge");
+    dag.add_edge(&c, &a, 0).expect("Failed to add edge");
+    dag.set_edge_weight(&a, &b, 4, 2).expect("Failed to set weight");
+    match dag.set_edge_weight(&a, &b, 2, 0) {
+        Err(DagError::ZeroCostCycle(cycle)) => assert_eq!(cycle.len(), 3),
+        _ => panic!("Failed to detect cycle"),
+    }
+    assert_eq!(dag.children(&a).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![2]);
+    assert_eq!(dag.set_edge_weight(&a, &b, 4, 1), Err(DagError::NoSuchEdge));
+    // Once the cost moves elsewhere on the cycle, the edge can be 0-cost.
+    dag.set_edge_weight(&c, &a, 0, 2).expect("Failed to set weight");
+    dag.set_edge_weight(&a, &b, 2, 0).expect("Failed to set weight");
+    assert_eq!(dag.edge_count(), 3);
 }
 
 impl CostQueriable<u32, u32> for u32 {
@@ -32,3 +135,98 @@ impl CostQueriable<u32, u32> for u32 {
         edge.weight() == &0
     }
 }
+
+#[test]
+/// Snapshots should preserve positive-cost cycles, and reject 0-cycles.
+fn test_snapshot() {
+    // A --4--> B --0--> C --0--> A
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &b, 4).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 0).expect("Failed to add edge");
+    dag.add_edge(&c, &a, 0).expect("Failed to add edge");
+    // Every node is reachable from c.
+    let snapshot = dag.snapshot(&[c]).expect("Failed to take snapshot");
+    assert_eq!(snapshot.node_count(), 3);
+    assert_eq!(snapshot.edge_count(), 3);
+    let mut loaded = MyDag::new();
+    let roots = snapshot.clone().restore(&mut loaded).expect("Failed to restore snapshot");
+    assert_eq!(loaded.edge_count(), 3);
+    let c = roots[0].clone();
+    let a = loaded.children(&c).next().unwrap().to().clone();
+    let b = loaded.children(&a).next().unwrap().to().clone();
+    assert_eq!((a.node_data(), b.node_data(), c.node_data()), (11, 12, 13));
+    loaded.add_edge(&a, &b, 0).expect_err("Failed to detect cycle");
+
+    // Make the cycle zero-cost.
+    let mut zero_cost = snapshot;
+    for edge in zero_cost.edges.iter_mut() {
+        edge.2 = 0;
+    }
+    match zero_cost.restore(&mut MyDag::new()) {
+        Err(DagError::ZeroCostCycle(_)) => {},
+        _ => panic!("Failed to detect cycle"),
+    }
+}
+
+#[test]
+/// Detaching a node should remove every edge that enters it, including those closing a cycle.
+fn test_detach_from_parents() {
+    // A --4--> B --0--> A, C --0--> B
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &b, 4).expect("Failed to add edge");
+    dag.add_edge(&b, &a, 0).expect("Failed to add edge");
+    dag.add_edge(&c, &b, 0).expect("Failed to add edge");
+    assert_eq!(dag.detach_from_parents(&b).len(), 2);
+    assert_eq!(dag.parents(&b).count(), 0);
+    assert_eq!(dag.parents(&a).map(|edge| edge.from().node_data()).collect::<Vec<u32>>(), vec![12]);
+    assert_eq!(dag.edge_count(), 1);
+    // B -> A remains, so a 0-cost A -> B would still close a 0-cycle.
+    dag.add_edge(&a, &b, 0).expect_err("Failed to detect cycle");
+    dag.rm_edge(&b, &a, 0).expect("Failed to remove edge");
+    dag.add_edge(&a, &b, 0).expect("Failed to add edge");
+}
+
+#[test]
+/// Nodes on a positive-cost cycle have no topological order, but should still be iterated once.
+fn test_iter_topo_cycle() {
+    // R --1--> A --1--> B --1--> A, B --1--> C
+    let mut dag = MyDag::new();
+    let r = dag.add_node(10);
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&r, &a, 1).expect("Failed to add edge");
+    dag.add_edge(&a, &b, 1).expect("Failed to add edge");
+    dag.add_edge(&b, &a, 1).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 1).expect("Failed to add edge");
+    assert_eq!(dag.iter_topo(&r).map(|node| node.node_data()).collect::<Vec<u32>>(), vec![10, 11, 12, 13]);
+    assert_eq!(dag.iter_topo_rev(&r).map(|node| node.node_data()).collect::<Vec<u32>>(), vec![13, 12, 11, 10]);
+    // Starting on the cycle, the root goes first, but its children are in no particular order.
+    let mut order = dag.iter_topo(&b).map(|node| node.node_data()).collect::<Vec<u32>>();
+    order[1..].sort();
+    assert_eq!(order, vec![12, 11, 13]);
+}
+
+#[test]
+/// Dropping a long chain, of which only the root is held, shouldn't recurse down it.
+fn test_drop_chain_from_root() {
+    // 0 -> 1 -> ... -> 99999
+    let mut dag = MyDag::new();
+    let root = dag.add_node(0);
+    let mut prev = root.clone();
+    for value in 1..100000 {
+        let next = dag.add_node(value);
+        dag.add_edge(&prev, &next, 1000).expect("Failed to add edge");
+        prev = next;
+    }
+    drop(prev);
+    assert_eq!(dag.node_count(), 100000);
+    drop(root);
+    assert_eq!((dag.node_count(), dag.edge_count()), (0, 0));
+}
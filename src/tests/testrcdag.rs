@@ -1,11 +1,31 @@
+#[cfg(feature = "serde")]
+use serde_json;
+
+use ::batch::EdgeBatch;
+use ::error::DagError;
 use ::ondag::OnDag;
-use ::rcdag::RcDag;
+use ::rcdag::{FullEdge, IterTopo, IterTopoRev, NodeHandle, RcDag};
+use ::snapshot::DagSnapshot;
+use super::testondag::OnDagTestable;
 
 type MyDag = RcDag<u32, u32>;
 
 
 // Declare tests for common OnDag functionality (inserting/removing nodes and edges).
 def_ondag_tests!{MyDag}
+def_ondag_long_tests!{MyDag}
+
+impl OnDagTestable<u32> for MyDag {
+    fn weight(w: u32) -> u32 {
+        w
+    }
+    fn edge_weight(edge: &FullEdge<u32, u32>) -> u32 {
+        *edge.weight()
+    }
+    fn node_value(&self, node: &NodeHandle<u32, u32>) -> u32 {
+        node.node_data()
+    }
+}
 
 
 #[test]
@@ -23,5 +43,479 @@ fn test_cycles() {
     let n1 = dag.add_node(1);
     dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
     dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
-    dag.add_edge(&n2, &root, 1003).err().expect("Failed to detect cycle");
+    let cycle = dag.add_edge(&n2, &root, 1003).expect_err("Failed to detect cycle");
+    // The witness should start with the rejected edge and walk back around to its source.
+    match cycle {
+        DagError::Cycle(path) => {
+            assert_eq!(path.iter().map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1003, 1001, 1002]);
+            assert!(path[0].from() == &n2 && path[2].to() == &n2);
+        },
+        _ => panic!("Expected a cycle"),
+    }
+}
+
+
+#[test]
+/// Paths should follow the edges' direction, and an edge back up a path would close a cycle.
+fn test_find_path() {
+    // 12 -> 1 -> 2, and 12 -> 3
+    let mut dag = MyDag::new();
+    let root = dag.add_node(12);
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    let n3 = dag.add_node(3);
+    dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+    dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
+    dag.add_edge(&root, &n3, 1003).expect("Failed to add edge");
+    let path = dag.find_path(&root, &n2).expect("Failed to find path");
+    assert_eq!(path.iter().map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1001, 1002]);
+    assert_eq!(dag.find_path(&n2, &n2), Some(vec![]));
+    assert_eq!(dag.find_path(&n2, &root), None);
+    assert!(dag.is_reachable(&root, &n3) && !dag.is_reachable(&n3, &n2));
+    assert!(dag.would_create_cycle(&n2, &root) && dag.would_create_cycle(&n1, &n1));
+    assert!(!dag.would_create_cycle(&n3, &n2));
+}
+
+#[test]
+/// Reachability answers should stay correct as edges are added against the order the nodes were
+/// created in (which forces the maintained order to be shuffled), including via batches.
+fn test_reachability_after_reorder() {
+    // 5 -> 4 -> 3, 2 -> 1 -> 0, then joined by a batch into 5 -> .. -> 0.
+    let mut dag = MyDag::new();
+    let nodes = (0..6).map(|value| dag.add_node(value)).collect::<Vec<_>>();
+    for &(to, from) in [(4, 5), (3, 4), (1, 2), (0, 1)].iter() {
+        dag.add_edge(&nodes[from], &nodes[to], 1000 + from as u32).expect("Failed to add edge");
+    }
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(nodes[3].clone(), nodes[2].clone(), 1003);
+    dag.apply_batch(batch).expect("Failed to apply batch");
+    for from in 0..6 {
+        for to in 0..6 {
+            // Compare against a plain traversal.
+            let expected = dag.iter_topo(&nodes[from]).any(|node| node == nodes[to]);
+            assert_eq!(dag.is_reachable(&nodes[from], &nodes[to]), expected);
+            assert_eq!(expected, from >= to);
+        }
+    }
+    let path = dag.find_path(&nodes[5], &nodes[0]).expect("Failed to find path");
+    assert_eq!(path.iter().map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1005, 1004, 1003, 1002, 1001]);
+    assert!(dag.add_edge(&nodes[0], &nodes[5], 1006).is_err());
+    assert!(dag.add_edge(&nodes[1], &nodes[3], 1006).is_err());
+    // Edges that agree with the (reversed) order are still accepted.
+    dag.add_edge(&nodes[4], &nodes[1], 1007).expect("Failed to add edge");
+    assert_eq!(dag.iter_topo(&nodes[5]).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![5, 4, 3, 2, 1, 0]);
+}
+
+/// An iteration in progress; the iterators' types can be named, so they can be stored.
+struct Walk {
+    topo: IterTopo<u32, u32>,
+    topo_rev: IterTopoRev<u32, u32>,
+}
+
+#[test]
+/// The topological iterators should produce nodes as they go, and can be stopped early.
+fn test_iter_topo_lazy() {
+    // 12 -> 1 -> 2 -> 3, and 12 -> 2
+    let mut dag = MyDag::new();
+    let root = dag.add_node(12);
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    let n3 = dag.add_node(3);
+    dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+    dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
+    dag.add_edge(&root, &n2, 1003).expect("Failed to add edge");
+    dag.add_edge(&n2, &n3, 1004).expect("Failed to add edge");
+    let mut walk = Walk {
+        topo: dag.iter_topo(&root),
+        topo_rev: dag.iter_topo_rev(&root),
+    };
+    assert_eq!(walk.topo.next().map(|node| node.node_data()), Some(12));
+    assert_eq!(walk.topo_rev.next().map(|node| node.node_data()), Some(3));
+    assert_eq!(walk.topo.map(|node| node.node_data()).collect::<Vec<u32>>(), vec![1, 2, 3]);
+    assert_eq!(walk.topo_rev.map(|node| node.node_data()).collect::<Vec<u32>>(), vec![2, 1, 12]);
+    // Only the nodes reachable from the root are included.
+    assert_eq!(dag.iter_topo(&n2).map(|node| node.node_data()).collect::<Vec<u32>>(), vec![2, 3]);
+}
+
+#[test]
+/// Handles belonging to another graph should be rejected rather than linked in.
+fn test_foreign_handle() {
+    let mut dag = MyDag::new();
+    let mut other = MyDag::new();
+    let a = dag.add_node(1);
+    let b = other.add_node(2);
+    assert_eq!(dag.add_edge(&a, &b, 1001).err(), Some(DagError::ForeignHandle));
+    assert_eq!(dag.rm_edge(&a, &b, 1001).err(), Some(DagError::ForeignHandle));
+    assert!(dag.contains(&a) && !dag.contains(&b));
+}
+
+#[test]
+/// Removing an edge that isn't present should be reported.
+fn test_rm_missing_edge() {
+    let mut dag = MyDag::new();
+    let a = dag.add_node(1);
+    let b = dag.add_node(2);
+    assert_eq!(dag.rm_edge(&a, &b, 1001).err(), Some(DagError::NoSuchEdge));
+    dag.add_edge(&a, &b, 1001).expect("Failed to add edge");
+    assert_eq!(dag.rm_edge(&a, &b, 1002).err(), Some(DagError::NoSuchEdge));
+}
+
+#[test]
+/// Nodes are dropped along with their last handle, taking their outgoing edges with them.
+fn test_drop_counts() {
+    //     12
+    //     v
+    //     1
+    //     v
+    //     2
+    let mut dag = MyDag::new();
+    let root = dag.add_node(12);
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+    dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
+    // n1 is kept alive by root.
+    drop(n1);
+    assert_eq!((dag.node_count(), dag.edge_count()), (3, 2));
+    // Dropping root drops n1 too, and with it every edge.
+    drop(root);
+    assert_eq!((dag.node_count(), dag.edge_count()), (1, 0));
+    assert_eq!(dag.parents(&n2).count(), 0);
+}
+
+#[test]
+/// Dropping the head of a long chain should free it without recursing down the chain, stopping
+/// at nodes that are still held elsewhere.
+fn test_drop_long_chain() {
+    // 0 -> 1 -> ... -> 999999, holding handles to only 0 & 500000.
+    let mut dag = MyDag::new();
+    let head = dag.add_node(0);
+    let mut middle = None;
+    let mut prev = head.clone();
+    for value in 1..1000000 {
+        let next = dag.add_node(value);
+        dag.add_edge(&prev, &next, 1000).expect("Failed to add edge");
+        if value == 500000 {
+            middle = Some(next.clone());
+        }
+        prev = next;
+    }
+    drop(prev);
+    let middle = middle.unwrap();
+    drop(head);
+    assert_eq!((dag.node_count(), dag.edge_count()), (500000, 499999));
+    assert_eq!(dag.parents(&middle).count(), 0);
+    drop(middle);
+    assert_eq!((dag.node_count(), dag.edge_count()), (0, 0));
+}
+
+/// The (parent value, weight) of each edge entering `node`, sorted.
+fn parent_links(dag: &MyDag, node: &NodeHandle<u32, u32>) -> Vec<(u32, u32)> {
+    let mut links = dag.parents(node).map(|edge| (edge.from().node_data(), *edge.weight())).collect::<Vec<_>>();
+    links.sort();
+    links
+}
+
+#[test]
+/// Parent links should mirror the edges through every kind of modification, without keeping the
+/// parents alive.
+fn test_parents_consistency() {
+    // a -> c (twice) & b -> c
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    dag.add_edge(&a, &c, 1).expect("Failed to add edge");
+    dag.add_edge(&a, &c, 2).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 3).expect("Failed to add edge");
+    // Adding an existing edge doesn't duplicate its link.
+    dag.add_edge(&b, &c, 3).expect("Failed to add edge");
+    assert_eq!(parent_links(&dag, &c), vec![(11, 1), (11, 2), (12, 3)]);
+    // Rejected edges aren't linked.
+    dag.add_edge(&c, &a, 4).expect_err("Failed to detect cycle");
+    let mut batch = EdgeBatch::new();
+    batch.rm_edge(a.clone(), c.clone(), 1).add_edge(c.clone(), b.clone(), 5);
+    dag.apply_batch(batch).expect_err("Failed to detect cycle");
+    assert_eq!(parent_links(&dag, &a), vec![]);
+    assert_eq!(parent_links(&dag, &c), vec![(11, 1), (11, 2), (12, 3)]);
+    dag.rm_edge(&a, &c, 1).expect("Failed to remove edge");
+    assert_eq!(parent_links(&dag, &c), vec![(11, 2), (12, 3)]);
+    // Parents aren't kept alive by their children.
+    drop(a);
+    assert_eq!(dag.node_count(), 2);
+    assert_eq!(parent_links(&dag, &c), vec![(12, 3)]);
+}
+
+#[test]
+/// Detaching a node should remove every edge that enters it, and nothing else.
+fn test_detach_from_parents() {
+    // a -> c (twice), b -> c -> d
+    let mut dag = MyDag::new();
+    let a = dag.add_node(11);
+    let b = dag.add_node(12);
+    let c = dag.add_node(13);
+    let d = dag.add_node(14);
+    dag.add_edge(&a, &c, 1).expect("Failed to add edge");
+    dag.add_edge(&a, &c, 2).expect("Failed to add edge");
+    dag.add_edge(&b, &c, 3).expect("Failed to add edge");
+    dag.add_edge(&c, &d, 4).expect("Failed to add edge");
+    let mut removed = dag.detach_from_parents(&c).iter().map(|edge| *edge.weight()).collect::<Vec<_>>();
+    removed.sort();
+    assert_eq!(removed, vec![1, 2, 3]);
+    assert_eq!(parent_links(&dag, &c), vec![]);
+    assert_eq!((dag.children(&a).count(), dag.children(&b).count()), (0, 0));
+    assert_eq!(parent_links(&dag, &d), vec![(13, 4)]);
+    assert_eq!(dag.edge_count(), 1);
+}
+
+#[test]
+/// A batch may pass through cyclic states, so long as the end result is acyclic.
+fn test_batch_reverse() {
+    // 12 -> 1 -> 2 becomes 12 -> 2 -> 1
+    let mut dag = MyDag::new();
+    let root = dag.add_node(12);
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+    dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(n2.clone(), n1.clone(), 1002)
+        .add_edge(root.clone(), n2.clone(), 1001)
+        .rm_edge(n1.clone(), n2.clone(), 1002)
+        .rm_edge(root.clone(), n1.clone(), 1001);
+    dag.apply_batch(batch).expect("Failed to apply batch");
+    assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12, 2, 1]);
+    assert_eq!(dag.edge_count(), 2);
+}
+
+#[test]
+/// A batch that's cyclic in the end (or invalid) shouldn't modify the graph at all.
+fn test_batch_rejected() {
+    let mut dag = MyDag::new();
+    let root = dag.add_node(12);
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+    dag.add_edge(&n1, &n2, 1002).expect("Failed to add edge");
+    // Removing 12 -> 1 doesn't break the 1 -> 2 -> 1 cycle.
+    let mut batch = EdgeBatch::new();
+    batch.rm_edge(root.clone(), n1.clone(), 1001)
+        .add_edge(root.clone(), n2.clone(), 1003)
+        .add_edge(n2.clone(), n1.clone(), 1004);
+    match dag.apply_batch(batch) {
+        Err(DagError::Cycle(cycle)) => assert_eq!(cycle.len(), 2),
+        _ => panic!("Failed to detect cycle"),
+    }
+    // Removing an edge that doesn't exist fails.
+    let mut batch = EdgeBatch::new();
+    batch.add_edge(root.clone(), n2.clone(), 1003)
+        .rm_edge(n2.clone(), root.clone(), 1005);
+    assert_eq!(dag.apply_batch(batch).err(), Some(DagError::NoSuchEdge));
+    assert_eq!(dag.iter_topo(&root).map(|handle| { handle.node_data() }).collect::<Vec<u32>>(), vec![12, 1, 2]);
+    assert_eq!(dag.children(&root).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1001]);
+    assert_eq!(dag.edge_count(), 2);
+}
+
+#[test]
+/// Changing a weight should replace the edge in place, and fail if the old edge is missing.
+fn test_set_edge_weight() {
+    let mut dag = MyDag::new();
+    let root = dag.add_node(12);
+    let n1 = dag.add_node(1);
+    dag.add_edge(&root, &n1, 1001).expect("Failed to add edge");
+    dag.set_edge_weight(&root, &n1, 1001, 1002).expect("Failed to set weight");
+    assert_eq!(dag.children(&root).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1002]);
+    assert_eq!(dag.parents(&n1).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1002]);
+    assert_eq!(dag.set_edge_weight(&root, &n1, 1001, 1003), Err(DagError::NoSuchEdge));
+    assert_eq!(dag.set_edge_weight(&MyDag::new().add_node(0), &n1, 1002, 1003), Err(DagError::ForeignHandle));
+    assert_eq!(dag.children(&root).map(|edge| { *edge.weight() }).collect::<Vec<u32>>(), vec![1002]);
+    assert_eq!(dag.edge_count(), 1);
+}
+
+#[test]
+/// Restoring a snapshot should recreate everything reachable from the roots, with shared nodes
+/// appearing once.
+fn test_snapshot() {
+    //   1   5
+    //  / \ /
+    // 2   3
+    //  \ /
+    //   4
+    let mut dag = MyDag::new();
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    let n3 = dag.add_node(3);
+    let n4 = dag.add_node(4);
+    let n5 = dag.add_node(5);
+    dag.add_edge(&n1, &n2, 1001).expect("Failed to add edge");
+    dag.add_edge(&n1, &n3, 1002).expect("Failed to add edge");
+    dag.add_edge(&n2, &n4, 1003).expect("Failed to add edge");
+    dag.add_edge(&n3, &n4, 1004).expect("Failed to add edge");
+    dag.add_edge(&n5, &n3, 1005).expect("Failed to add edge");
+    let snapshot = dag.snapshot(&[n5.clone(), n1.clone()]).expect("Failed to take snapshot");
+    assert_eq!(snapshot.node_count(), 5);
+    assert_eq!(snapshot.edge_count(), 5);
+    #[cfg(feature = "serde")]
+    let snapshot = {
+        let json = serde_json::to_string(&snapshot).expect("Failed to serialize");
+        serde_json::from_str::<DagSnapshot<u32, u32>>(&json).expect("Failed to deserialize")
+    };
+
+    let mut loaded = MyDag::new();
+    let roots = snapshot.restore(&mut loaded).expect("Failed to restore snapshot");
+    assert_eq!(roots.iter().map(|root| root.node_data()).collect::<Vec<_>>(), vec![5, 1]);
+    assert_eq!(loaded.node_count(), 5);
+    assert_eq!(loaded.edge_count(), 5);
+    let order = loaded.iter_topo(&roots[1]).map(|node| node.node_data()).collect::<Vec<_>>();
+    assert_eq!(order.len(), 4);
+    assert_eq!(order[0], 1);
+    assert_eq!(order[3], 4);
+    let n3 = loaded.children(&roots[0]).next().unwrap().to().clone();
+    assert_eq!(loaded.children(&n3).map(|edge| (edge.to().node_data(), *edge.weight())).collect::<Vec<_>>(), vec![(4, 1004)]);
+    // the nodes are only kept alive by the new roots.
+    drop(roots);
+    drop(n3);
+    assert_eq!(loaded.node_count(), 0);
+}
+
+#[test]
+/// Snapshots that contain cycles or refer to missing nodes should be rejected.
+fn test_snapshot_invalid() {
+    let other = MyDag::new().add_node(1);
+    MyDag::new().snapshot(&[other]).expect_err("Failed to detect foreign handle");
+    let cyclic = DagSnapshot {
+        nodes: vec![1, 2],
+        edges: vec![(0, 1, 1001), (1, 0, 1002)],
+        roots: vec![0],
+    };
+    match cyclic.restore(&mut MyDag::new()) {
+        Err(DagError::Cycle(_)) => {},
+        _ => panic!("Failed to detect cycle"),
+    }
+    let dangling = DagSnapshot {
+        nodes: vec![1],
+        edges: vec![(0, 1, 1001)],
+        roots: vec![0],
+    };
+    match dangling.restore(&mut MyDag::new()) {
+        Err(DagError::NoSuchNode) => {},
+        _ => panic!("Failed to detect missing node"),
+    }
+}
+
+#[test]
+/// DOT output should include everything reachable from the roots (once each).
+fn test_dot() {
+    // 1 -> 2 -> 3, 1 -> 3, and an unreachable 4 -> 1
+    let mut dag = MyDag::new();
+    let n1 = dag.add_node(1);
+    let n2 = dag.add_node(2);
+    let n3 = dag.add_node(3);
+    let n4 = dag.add_node(4);
+    dag.add_edge(&n1, &n2, 1001).expect("Failed to add edge");
+    dag.add_edge(&n2, &n3, 1002).expect("Failed to add edge");
+    dag.add_edge(&n1, &n3, 1003).expect("Failed to add edge");
+    dag.add_edge(&n4, &n1, 1004).expect("Failed to add edge");
+    let mut out = vec![];
+    dag.write_dot(&mut out, &[n1], |data| data.to_string(), |edge| edge.weight().to_string(), &[])
+        .expect("Failed to write graph");
+    let out = String::from_utf8(out).unwrap();
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2 + 3 + 3);
+    assert_eq!(lines[1], "    n0 [label=\"1\"];");
+    assert!(lines.iter().all(|line| !line.contains("1004")));
+    // n2 & n3 may be discovered in either order.
+    let n2_id = if lines[2].contains("\"2\"") { "n1" } else { "n2" };
+    assert!(lines.contains(&&*format!("    n0 -> {} [label=\"1001\"];", n2_id)));
+}
+
+/// Node data that can't be cloned.
+#[derive(Debug, PartialEq)]
+struct Buffer(Vec<f32>);
+
+#[test]
+/// Node data should be accessible by reference, and modifiable, without cloning it.
+fn test_node_data_access() {
+    let mut dag = RcDag::<Buffer, u32>::new();
+    let a = dag.add_node(Buffer(vec![1.0, 2.0]));
+    let b = dag.add_node(Buffer(vec![]));
+    dag.add_edge(&a, &b, 1001).expect("Failed to add edge");
+    assert_eq!(a.node_data_ref().0.len(), 2);
+    assert_eq!(a.with_data(|buffer| buffer.0.iter().sum::<f32>()), 3.0);
+    a.with_data_mut(|buffer| buffer.0.push(3.0));
+    // Changes are visible through every handle to the node.
+    let parent = dag.parents(&b).next().unwrap();
+    assert_eq!(*parent.from().node_data_ref(), Buffer(vec![1.0, 2.0, 3.0]));
+    assert_eq!(b.replace_data(Buffer(vec![4.0])), Buffer(vec![]));
+    assert_eq!(*b.node_data_ref(), Buffer(vec![4.0]));
+    // The graph's structure is untouched.
+    assert_eq!(dag.iter_topo(&a).count(), 2);
+}
+
+#[test]
+/// Reachability should stay correct as the chains that answer it are linked, split by removing
+/// their edges & cut short by dropping nodes.
+fn test_reachability_along_chains() {
+    // 0 -> 1 -> 2 -> 3 -> 4, with a second edge 1 -> 2 & a detour 1 -> 5 -> 3.
+    let mut dag = MyDag::new();
+    let mut nodes = (0..6).map(|value| dag.add_node(value)).collect::<Vec<_>>();
+    for &(from, to) in [(0, 1), (1, 2), (2, 3), (3, 4), (1, 5), (5, 3)].iter() {
+        dag.add_edge(&nodes[from], &nodes[to], 1000 + to as u32).expect("Failed to add edge");
+    }
+    dag.add_edge(&nodes[1], &nodes[2], 2002).expect("Failed to add edge");
+    let assert_reachable = |dag: &MyDag, nodes: &[NodeHandle<u32, u32>]| {
+        for from in nodes.iter() {
+            for to in nodes.iter() {
+                // Compare against a plain traversal.
+                let expected = dag.iter_topo(from).any(|node| node == *to);
+                assert_eq!(dag.is_reachable(from, to), expected);
+                let path = dag.find_path(from, to);
+                assert_eq!(path.is_some(), expected);
+                for (edge, next) in path.iter().flat_map(|path| path.iter().zip(path.iter().skip(1))) {
+                    assert!(edge.to() == next.from());
+                }
+            }
+        }
+    };
+    assert_reachable(&dag, &nodes);
+    let path = dag.find_path(&nodes[0], &nodes[4]).expect("Failed to find path");
+    assert_eq!(path.len(), 4);
+
+    // The other edge 1 -> 2 keeps the chain together.
+    dag.rm_edge(&nodes[1], &nodes[2], 1002).expect("Failed to remove edge");
+    assert_reachable(&dag, &nodes);
+    dag.rm_edge(&nodes[1], &nodes[2], 2002).expect("Failed to remove edge");
+    assert!(!dag.is_reachable(&nodes[0], &nodes[2]) && dag.is_reachable(&nodes[0], &nodes[4]));
+    assert_reachable(&dag, &nodes);
+    // Rejoining links the chain back up.
+    dag.set_edge_weight(&nodes[2], &nodes[3], 1003, 3003).expect("Failed to set weight");
+    dag.add_edge(&nodes[1], &nodes[2], 1002).expect("Failed to add edge");
+    assert_reachable(&dag, &nodes);
+    assert!(dag.add_edge(&nodes[4], &nodes[0], 1000).is_err());
+
+    // Dropping the start of the chain leaves the rest of it intact.
+    nodes.remove(0);
+    assert_reachable(&dag, &nodes);
+    let mut batch = EdgeBatch::new();
+    batch.rm_edge(nodes[1].clone(), nodes[2].clone(), 3003).add_edge(nodes[4].clone(), nodes[1].clone(), 1001);
+    dag.apply_batch(batch).expect("Failed to apply batch");
+    assert_reachable(&dag, &nodes);
+}
+
+#[test]
+/// Dropping a long chain, of which only the root is held, shouldn't recurse down it.
+fn test_drop_chain_from_root() {
+    // 0 -> 1 -> ... -> 99999
+    let mut dag = MyDag::new();
+    let root = dag.add_node(0);
+    let mut prev = root.clone();
+    for value in 1..100000 {
+        let next = dag.add_node(value);
+        dag.add_edge(&prev, &next, 1000).expect("Failed to add edge");
+        prev = next;
+    }
+    drop(prev);
+    assert_eq!(dag.node_count(), 100000);
+    drop(root);
+    assert_eq!((dag.node_count(), dag.edge_count()), (0, 0));
 }